@@ -0,0 +1,58 @@
+use std::path::Path;
+use std::{env, fs};
+
+use chip8_rust::chip8::{assembler, octo};
+
+const USAGE: &str =
+    "Usage: chip8-asm <source_file> [--octo] [--output <rom_file>] [--source-map <file>]";
+
+fn main() -> Result<(), String> {
+    let mut source_path = None;
+    let mut output_path = None;
+    let mut source_map_path = None;
+    let mut octo_syntax = false;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--octo" => octo_syntax = true,
+            "--source-map" => {
+                source_map_path = Some(args.next().ok_or("--source-map needs a value")?);
+            }
+            "--output" | "-o" => {
+                output_path = Some(args.next().ok_or("--output needs a value")?);
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}\n{}", arg, USAGE)),
+            _ if source_path.is_none() => source_path = Some(arg),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let source_path = source_path.ok_or(USAGE)?;
+    // Default to the source name with a .ch8 extension
+    let output_path = output_path.unwrap_or_else(|| {
+        Path::new(&source_path)
+            .with_extension("ch8")
+            .to_string_lossy()
+            .into_owned()
+    });
+
+    let source = fs::read_to_string(&source_path)
+        .map_err(|e| format!("Failed to read source file {}: {}", source_path, e))?;
+    // Octo source is recognised by its usual .8o extension
+    let assembly = if octo_syntax || source_path.ends_with(".8o") {
+        octo::compile(&source)
+    } else {
+        assembler::assemble(&source)
+    }
+    .map_err(|e| format!("{}:{}", source_path, e))?;
+
+    fs::write(&output_path, &assembly.rom)
+        .map_err(|e| format!("Failed to write {}: {}", output_path, e))?;
+    println!("{}: {} bytes", output_path, assembly.rom.len());
+    if let Some(path) = source_map_path {
+        fs::write(&path, assembly.source_map.to_text())
+            .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    }
+
+    Ok(())
+}
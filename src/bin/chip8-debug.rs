@@ -0,0 +1,172 @@
+use std::io::{self, BufRead, Write};
+use std::{env, fs};
+
+use chip8_rust::chip8::assembler::SourceMap;
+use chip8_rust::chip8::debugger::{Command, Debugger, StopReason};
+use chip8_rust::chip8::octo;
+use chip8_rust::chip8::quirks::UnknownPreset;
+use chip8_rust::chip8::rom::{self, Rom};
+use chip8_rust::chip8::timing::TimingModel;
+use chip8_rust::chip8::{Chip8, KeyState, MemoryPolicy, ROM_START_ADDRESS, StackDepth};
+
+const CYCLES_PER_SECOND: u32 = 5400;
+const TARGET_FPS: u32 = 60;
+
+// `continue` gives up after this many emulated frames so a program that never hits a
+// breakpoint returns control to the prompt
+const CONTINUE_FRAME_LIMIT: u32 = 60 * TARGET_FPS;
+
+const USAGE: &str = "Usage: chip8-debug <rom_or_octo_file> [--quirks <preset>] [--load-address <addr>] \
+[--timing fixed|vip] [--memory wrap|fault|ignore] [--stack-depth <n>|unlimited] [--vip-stack] \
+[--source-map <file>]";
+
+fn main() -> Result<(), String> {
+    let mut rom_path = None;
+    let mut quirks = None;
+    let mut load_address = ROM_START_ADDRESS;
+    let mut source_map_path = None;
+    let mut timing = TimingModel::Fixed(CYCLES_PER_SECOND / TARGET_FPS);
+    let mut memory_policy = MemoryPolicy::default();
+    let mut stack_depth = None;
+    let mut vip_stack = false;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let preset = args.next().ok_or("--quirks needs a value")?;
+                quirks = Some(preset.parse().map_err(|e: UnknownPreset| e.to_string())?);
+            }
+            "--load-address" => {
+                let address = args.next().ok_or("--load-address needs a value")?;
+                load_address = rom::parse_address(&address)?;
+            }
+            "--timing" => {
+                let name = args.next().ok_or("--timing needs a value")?;
+                timing = TimingModel::from_name(&name, CYCLES_PER_SECOND / TARGET_FPS)?;
+            }
+            "--memory" => {
+                let name = args.next().ok_or("--memory needs a value")?;
+                memory_policy = MemoryPolicy::from_name(&name)?;
+            }
+            "--stack-depth" => {
+                let depth = args.next().ok_or("--stack-depth needs a value")?;
+                stack_depth = Some(StackDepth::from_name(&depth)?);
+            }
+            "--vip-stack" => vip_stack = true,
+            "--source-map" => {
+                source_map_path = Some(args.next().ok_or("--source-map needs a value")?);
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}\n{}", arg, USAGE)),
+            _ if rom_path.is_none() => rom_path = Some(arg),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let rom_path = rom_path.ok_or(USAGE)?;
+
+    // Octo source is compiled on the fly, which also provides the source map
+    let (rom, mut source_map) = if rom_path.ends_with(".8o") {
+        let source = fs::read_to_string(&rom_path)
+            .map_err(|e| format!("Failed to read source file {}: {}", rom_path, e))?;
+        let assembly = octo::compile(&source).map_err(|e| format!("{}:{}", rom_path, e))?;
+        (Rom::new(assembly.rom), Some(assembly.source_map))
+    } else {
+        let rom = Rom::read(&rom_path).map_err(|e| format!("Failed to read ROM file: {}", e))?;
+        (rom, None)
+    };
+    let rom = rom.with_load_address(load_address);
+    if let Some(path) = source_map_path {
+        let text = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read source map {}: {}", path, e))?;
+        source_map = Some(SourceMap::parse(&text)?);
+    }
+
+    let quirks = match quirks {
+        Some(quirks) => quirks,
+        None => {
+            if let Some(summary) = rom.hint_summary() {
+                println!("{}", summary);
+            }
+            rom.suggested_quirks().unwrap_or_default()
+        }
+    };
+    let mut chip8 = Chip8::new(quirks);
+    chip8
+        .load_rom(&rom)
+        .map_err(|e| format!("Failed to load {}: {}", rom_path, e))?;
+    let mut debugger = Debugger::new();
+    if let Some(source_map) = source_map {
+        debugger.set_source_map(source_map);
+    }
+    chip8.set_timing(timing);
+    chip8.set_memory_policy(memory_policy);
+    if let Some(depth) = stack_depth {
+        chip8.set_stack_depth(depth);
+    }
+    chip8.set_stack_in_memory(vip_stack);
+
+    print!("{}", debugger.location(&chip8));
+
+    let stdin = io::stdin();
+    let mut last_line = String::new();
+    loop {
+        print!("(chip8) ");
+        io::stdout().flush().map_err(|e| e.to_string())?;
+
+        let mut line = String::new();
+        if stdin
+            .lock()
+            .read_line(&mut line)
+            .map_err(|e| e.to_string())?
+            == 0
+        {
+            break;
+        }
+        // An empty line repeats the previous command, as in gdb
+        if line.trim().is_empty() {
+            line = last_line.clone();
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        last_line = line.clone();
+
+        let command: Command = match line.parse() {
+            Ok(command) => command,
+            Err(err) => {
+                println!("{}", err);
+                continue;
+            }
+        };
+
+        match command {
+            Command::Quit => break,
+            Command::Continue => {
+                let reason = continue_running(&mut debugger, &mut chip8);
+                match reason {
+                    Some(reason) => println!("{}", reason),
+                    None => println!("still running after {} frames", CONTINUE_FRAME_LIMIT),
+                }
+                print!("{}", debugger.location(&chip8));
+            }
+            command => print!("{}", debugger.execute_command(&mut chip8, &command)),
+        }
+    }
+
+    Ok(())
+}
+
+// Runs whole frames until the debugger stops. Keys are left as the press and release
+// commands set them.
+fn continue_running(debugger: &mut Debugger, chip8: &mut Chip8) -> Option<StopReason> {
+    for _ in 0..CONTINUE_FRAME_LIMIT {
+        chip8.begin_frame(&KeyState::new());
+        if let Some(reason) = debugger.run(chip8) {
+            return Some(reason);
+        }
+        for access in chip8.end_frame().ignored_accesses {
+            println!("ignored: {}", access);
+        }
+    }
+    None
+}
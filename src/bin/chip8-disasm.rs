@@ -0,0 +1,53 @@
+use std::{env, fs};
+
+use chip8_rust::chip8::ROM_START_ADDRESS;
+use chip8_rust::chip8::analyzer;
+use chip8_rust::chip8::disassembler::{self, Syntax};
+use chip8_rust::chip8::rom;
+
+const USAGE: &str = "Usage: chip8-disasm <rom_file> [--octo] [--load-address <addr>] \
+                     [--analyze] [--output <file>]";
+
+fn main() -> Result<(), String> {
+    let mut rom_path = None;
+    let mut syntax = Syntax::Cowgod;
+    let mut output_path = None;
+    let mut load_address = ROM_START_ADDRESS;
+    let mut analyze = false;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--octo" => syntax = Syntax::Octo,
+            "--cowgod" => syntax = Syntax::Cowgod,
+            "--analyze" => analyze = true,
+            "--load-address" => {
+                let address = args.next().ok_or("--load-address needs a value")?;
+                load_address = rom::parse_address(&address)?;
+            }
+            "--output" => {
+                output_path = Some(args.next().ok_or("--output needs a value")?);
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}\n{}", arg, USAGE)),
+            _ if rom_path.is_none() => rom_path = Some(arg),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let rom_path = rom_path.ok_or(USAGE)?;
+
+    let rom_data =
+        fs::read(&rom_path).map_err(|e| format!("Failed to read ROM file {}: {}", rom_path, e))?;
+    // --analyze reports the platform and quirks the code needs instead of listing it
+    let listing = if analyze {
+        analyzer::analyze(&rom_data, load_address as u16).to_string()
+    } else {
+        disassembler::disassemble(&rom_data, load_address as u16, syntax)
+    };
+
+    match output_path {
+        Some(path) => fs::write(path, listing).map_err(|e| e.to_string())?,
+        None => print!("{}", listing),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,422 @@
+use std::fmt::Write as _;
+use std::str::FromStr;
+use std::{env, fs};
+
+use sha1::{Digest, Sha1};
+
+use chip8_rust::chip8::movie::Movie;
+use chip8_rust::chip8::quirks::Quirks;
+use chip8_rust::chip8::rom::{self, Rom};
+use chip8_rust::chip8::timing::TimingModel;
+use chip8_rust::chip8::{
+    Chip8, KeyState, MemoryPolicy, ROM_START_ADDRESS, StackDepth, StepOutcome,
+};
+
+const CYCLES_PER_SECOND: u32 = 5400;
+const TARGET_FPS: u32 = 60;
+
+const USAGE: &str = "Usage: chip8-headless <rom_file> [--frames <n> | --cycles <n>] \
+[--quirks <preset>] [--load-address <addr>] [--keys <script>] [--cycles-per-frame <n>] [--timing fixed|vip] \
+[--seed <n>] [--memory wrap|fault|ignore] [--stack-depth <n>|unlimited] [--vip-stack] \
+[--replay <movie>] [--output <file>]";
+
+// A scripted key transition, applied at the start of the given frame
+struct KeyEvent {
+    frame: u64,
+    key: u8,
+    pressed: bool,
+}
+
+struct Options {
+    rom_path: String,
+    frames: Option<u64>,
+    cycles: Option<u64>,
+    // None picks the quirks the ROM suggests, or the default ones
+    quirks: Option<Quirks>,
+    load_address: Option<usize>,
+    keys_path: Option<String>,
+    cycles_per_frame: Option<u32>,
+    timing: TimingModel,
+    seed: Option<u64>,
+    memory_policy: Option<MemoryPolicy>,
+    // None leaves the quirks preset's depth
+    stack_depth: Option<StackDepth>,
+    vip_stack: bool,
+    replay_path: Option<String>,
+    output_path: Option<String>,
+}
+
+fn main() -> Result<(), String> {
+    let mut options = parse_args(env::args().skip(1).collect())?;
+
+    let rom = Rom::read(&options.rom_path)
+        .map_err(|e| format!("Failed to read ROM file {}: {}", options.rom_path, e))?
+        .with_load_address(options.load_address.unwrap_or(ROM_START_ADDRESS));
+    let key_events = match &options.keys_path {
+        Some(path) => {
+            let script = fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read key script {}: {}", path, e))?;
+            parse_key_script(&script)?
+        }
+        None => Vec::new(),
+    };
+
+    let replay = match &options.replay_path {
+        Some(path) => {
+            let data = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+            Some(Movie::parse(&data).map_err(|e| format!("{}: {}", path, e))?)
+        }
+        None => None,
+    };
+
+    // A movie brings the quirks, timing, seed, RPL flags, memory policy and stack it was
+    // recorded with
+    let mut chip8 = match &replay {
+        Some(movie) => movie
+            .start(&rom)
+            .map_err(|e| format!("Cannot replay movie: {}", e))?,
+        None => {
+            // The report goes to stdout, so notes go to stderr
+            let quirks = match options.quirks {
+                Some(quirks) => quirks,
+                None => {
+                    if let Some(summary) = rom.hint_summary() {
+                        eprintln!("{}", summary);
+                    }
+                    rom.suggested_quirks().unwrap_or_default()
+                }
+            };
+            let mut chip8 = Chip8::new(quirks);
+            chip8
+                .load_rom(&rom)
+                .map_err(|e| format!("Failed to load {}: {}", options.rom_path, e))?;
+            chip8.set_timing(options.timing);
+            if let Some(seed) = options.seed {
+                chip8.seed_random(seed);
+            }
+            chip8.set_memory_policy(options.memory_policy.unwrap_or_default());
+            if let Some(depth) = options.stack_depth {
+                chip8.set_stack_depth(depth);
+            }
+            chip8.set_stack_in_memory(options.vip_stack);
+            chip8
+        }
+    };
+    if let Some(movie) = &replay
+        && options.frames.is_none()
+        && options.cycles.is_none()
+    {
+        options.frames = Some(movie.len() as u64);
+    }
+
+    let (frames_run, cycles_run, error) = run(&mut chip8, &options, &key_events, replay.as_ref());
+
+    let report = format_report(&chip8, frames_run, cycles_run, error.as_deref());
+    match &options.output_path {
+        Some(path) => fs::write(path, report).map_err(|e| e.to_string())?,
+        None => print!("{}", report),
+    }
+
+    // The report is written either way; the exit status lets scripts tell a failed run apart
+    if error.is_some() {
+        std::process::exit(2);
+    }
+    Ok(())
+}
+
+fn parse_args(args: Vec<String>) -> Result<Options, String> {
+    let mut options = Options {
+        rom_path: String::new(),
+        frames: None,
+        cycles: None,
+        quirks: None,
+        load_address: None,
+        keys_path: None,
+        cycles_per_frame: None,
+        timing: TimingModel::Fixed(0),
+        seed: None,
+        memory_policy: None,
+        stack_depth: None,
+        vip_stack: false,
+        replay_path: None,
+        output_path: None,
+    };
+
+    let mut timing_given = false;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| args.next().ok_or_else(|| format!("{} needs a value", flag));
+        match arg.as_str() {
+            "--frames" => options.frames = Some(parse_number(&value("--frames")?)?),
+            "--cycles" => options.cycles = Some(parse_number(&value("--cycles")?)?),
+            "--quirks" => {
+                options.quirks = Some(value("--quirks")?.parse().map_err(|e| format!("{}", e))?)
+            }
+            "--load-address" => {
+                options.load_address = Some(rom::parse_address(&value("--load-address")?)?)
+            }
+            "--keys" => options.keys_path = Some(value("--keys")?),
+            "--cycles-per-frame" => {
+                options.cycles_per_frame = Some(parse_number(&value("--cycles-per-frame")?)?)
+            }
+            "--timing" => {
+                options.timing = TimingModel::from_name(&value("--timing")?, 0)?;
+                timing_given = true;
+            }
+            "--seed" => options.seed = Some(parse_number(&value("--seed")?)?),
+            "--memory" => {
+                options.memory_policy = Some(MemoryPolicy::from_name(&value("--memory")?)?)
+            }
+            "--stack-depth" => {
+                options.stack_depth = Some(StackDepth::from_name(&value("--stack-depth")?)?)
+            }
+            "--vip-stack" => options.vip_stack = true,
+            "--replay" => options.replay_path = Some(value("--replay")?),
+            "--output" => options.output_path = Some(value("--output")?),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}\n{}", arg, USAGE)),
+            _ if options.rom_path.is_empty() => options.rom_path = arg,
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    if options.rom_path.is_empty() {
+        return Err(USAGE.to_string());
+    }
+    if options.cycles_per_frame == Some(0) {
+        return Err("--cycles-per-frame must be at least 1".to_string());
+    }
+    if options.cycles_per_frame.is_some() && options.timing == TimingModel::CosmacVip {
+        return Err("--cycles-per-frame only applies to --timing fixed".to_string());
+    }
+    // The fixed model's rate may come after --timing on the command line
+    if let TimingModel::Fixed(_) = options.timing {
+        options.timing = TimingModel::Fixed(
+            options
+                .cycles_per_frame
+                .unwrap_or(CYCLES_PER_SECOND / TARGET_FPS),
+        );
+    }
+    if options.keys_path.is_some() && options.replay_path.is_some() {
+        return Err("--keys and --replay cannot be used together".to_string());
+    }
+    // The movie holds the settings it was recorded with
+    let recorded = options.quirks.is_some()
+        || options.load_address.is_some()
+        || options.cycles_per_frame.is_some()
+        || timing_given
+        || options.seed.is_some()
+        || options.memory_policy.is_some()
+        || options.stack_depth.is_some()
+        || options.vip_stack;
+    if options.replay_path.is_some() && recorded {
+        return Err(
+            "--quirks, --load-address, --cycles-per-frame, --timing, --seed, --memory, \
+--stack-depth and --vip-stack come from the movie when replaying"
+                .to_string(),
+        );
+    }
+    // A replay runs to the end of the movie unless told otherwise
+    if options.frames.is_none() && options.cycles.is_none() && options.replay_path.is_none() {
+        options.frames = Some(TARGET_FPS as u64);
+    }
+
+    Ok(options)
+}
+
+fn parse_number<T: FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Expected a number, got '{}'", value))
+}
+
+// Each non-empty line is `<frame> down|up <key>`, with the key in hex; `#` starts a comment
+fn parse_key_script(script: &str) -> Result<Vec<KeyEvent>, String> {
+    let mut events = Vec::new();
+
+    for (line_number, line) in script.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let error = || format!("Invalid key script line {}: '{}'", line_number + 1, line);
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let [frame, action, key] = parts.as_slice() else {
+            return Err(error());
+        };
+
+        let frame = frame.parse().map_err(|_| error())?;
+        let pressed = match *action {
+            "down" => true,
+            "up" => false,
+            _ => return Err(error()),
+        };
+        let key = u8::from_str_radix(key.trim_start_matches("0x"), 16)
+            .ok()
+            .filter(|key| *key < 16)
+            .ok_or_else(error)?;
+
+        events.push(KeyEvent {
+            frame,
+            key,
+            pressed,
+        });
+    }
+
+    events.sort_by_key(|event| event.frame);
+    Ok(events)
+}
+
+// Runs until the frame or cycle limit is reached, the program exits, or an error occurs.
+// Keys come from the key script, or from the movie when replaying, with every key released
+// once the movie runs out.
+// Returns the number of frames and cycles executed, plus the error if there was one.
+fn run(
+    chip8: &mut Chip8,
+    options: &Options,
+    key_events: &[KeyEvent],
+    replay: Option<&Movie>,
+) -> (u64, u64, Option<String>) {
+    let mut frames = 0;
+    let mut cycles = 0;
+    let mut pending_events = key_events.iter().peekable();
+    let mut keys = KeyState::new();
+
+    loop {
+        if options.frames.is_some_and(|limit| frames >= limit)
+            || options.cycles.is_some_and(|limit| cycles >= limit)
+        {
+            return (frames, cycles, None);
+        }
+
+        if let Some(movie) = replay {
+            keys = movie.frame(frames as usize).copied().unwrap_or_default();
+        }
+        while let Some(event) = pending_events.next_if(|event| event.frame <= frames) {
+            keys.set_key_pressed(event.key, event.pressed);
+        }
+
+        // The frame is run in parts so a cycle limit can stop it partway through
+        chip8.begin_frame(&keys);
+        while chip8.frame_has_time() {
+            if options.cycles.is_some_and(|limit| cycles >= limit) {
+                break;
+            }
+            cycles += 1;
+            match chip8.frame_cycle() {
+                Ok(StepOutcome::Exited) => return (frames + 1, cycles, None),
+                Ok(_) => {}
+                Err(err) => return (frames + 1, cycles, Some(err.to_string())),
+            }
+        }
+        for access in chip8.end_frame().ignored_accesses {
+            eprintln!("frame {}: ignored: {}", frames, access);
+        }
+        frames += 1;
+    }
+}
+
+fn format_report(chip8: &Chip8, frames: u64, cycles: u64, error: Option<&str>) -> String {
+    let mut report = String::new();
+
+    writeln!(report, "frames: {}", frames).unwrap();
+    writeln!(report, "cycles: {}", cycles).unwrap();
+    match error {
+        Some(error) => writeln!(report, "status: error: {}", error).unwrap(),
+        None if chip8.has_exited() => writeln!(report, "status: exited").unwrap(),
+        None => writeln!(report, "status: ok").unwrap(),
+    }
+
+    writeln!(report, "pc: {:#06X}", chip8.program_counter()).unwrap();
+    writeln!(report, "i: {:#06X}", chip8.index_register()).unwrap();
+    let registers: Vec<String> = chip8
+        .registers()
+        .values()
+        .iter()
+        .enumerate()
+        .map(|(i, value)| format!("V{:X}={:02X}", i, value))
+        .collect();
+    writeln!(report, "registers: {}", registers.join(" ")).unwrap();
+    let stack: Vec<String> = chip8
+        .stack()
+        .iter()
+        .map(|addr| format!("{:#06X}", addr))
+        .collect();
+    writeln!(report, "stack: [{}]", stack.join(", ")).unwrap();
+    writeln!(report, "delay_timer: {}", chip8.delay_timer()).unwrap();
+    writeln!(report, "sound_timer: {}", chip8.sound_timer()).unwrap();
+    writeln!(
+        report,
+        "memory_sha1: {}",
+        hex(&Sha1::digest(chip8.memory()))
+    )
+    .unwrap();
+
+    writeln!(
+        report,
+        "display: {}x{}",
+        chip8.display.width(),
+        chip8.display.height()
+    )
+    .unwrap();
+    for y in 0..chip8.display.height() {
+        let row: String = (0..chip8.display.width())
+            .map(|x| match chip8.display.pixel_color(x, y) {
+                0 => '.',
+                1 => '#',
+                2 => '+',
+                _ => '@',
+            })
+            .collect();
+        writeln!(report, "{}", row).unwrap();
+    }
+
+    report
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options, String> {
+        parse_args(args.split_whitespace().map(String::from).collect())
+    }
+
+    #[test]
+    fn runs_last_a_second_unless_limited() {
+        assert_eq!(parse("rom.ch8").unwrap().frames, Some(60));
+        let options = parse("rom.ch8 --cycles 100").unwrap();
+        assert_eq!((options.frames, options.cycles), (None, Some(100)));
+        assert!(parse("").is_err());
+        assert!(parse("rom.ch8 other.ch8").is_err());
+        assert!(parse("rom.ch8 --bogus").is_err());
+        assert!(parse("rom.ch8 --frames").is_err());
+    }
+
+    #[test]
+    fn key_scripts_are_sorted_by_frame() {
+        let events = parse_key_script("# taps A\n10 up 0xA\n\n2 down a  # press\n").unwrap();
+        let events: Vec<_> = events
+            .iter()
+            .map(|event| (event.frame, event.key, event.pressed))
+            .collect();
+        assert_eq!(events, [(2, 0xA, true), (10, 0xA, false)]);
+
+        assert!(parse_key_script("1 down 10").is_err());
+        assert!(parse_key_script("1 hold 5").is_err());
+        assert!(parse_key_script("down 5").is_err());
+    }
+
+    #[test]
+    fn cycles_per_frame_sets_the_fixed_rate() {
+        let options = parse("rom.ch8 --timing fixed --cycles-per-frame 12").unwrap();
+        assert_eq!(options.timing, TimingModel::Fixed(12));
+        assert!(parse("rom.ch8 --cycles-per-frame 0").is_err());
+        assert!(parse("rom.ch8 --cycles-per-frame 4294967296").is_err());
+        assert!(parse("rom.ch8 --cycles-per-frame 12 --timing vip").is_err());
+    }
+}
@@ -0,0 +1,522 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+use std::{env, fs};
+
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::{cursor, execute, queue, terminal};
+
+use chip8_rust::chip8::debugger::format_registers;
+use chip8_rust::chip8::instruction::Instruction;
+use chip8_rust::chip8::quirks::{Quirks, UnknownPreset};
+use chip8_rust::chip8::rom::{self, Rom};
+use chip8_rust::chip8::timing::TimingModel;
+use chip8_rust::chip8::{Chip8, KeyState, MemoryPolicy, ROM_START_ADDRESS, StackDepth};
+use chip8_rust::keymap::{self, Keymap};
+
+const CYCLES_PER_SECOND: u32 = 5400;
+const TARGET_FPS: u32 = 60;
+const FRAME_DURATION: Duration = Duration::from_micros(1_000_000 / TARGET_FPS as u64);
+
+// Terminals without key release events only report presses and auto-repeats, so a key
+// counts as held until this many frames pass without hearing from it. It has to outlast
+// the delay before auto-repeat starts.
+const HOLD_FRAMES: u32 = 30;
+
+// Instructions listed from the PC in the side pane
+const PANE_INSTRUCTIONS: usize = 12;
+
+// Background followed by the colours for plane 1, plane 2 and both planes lit
+const PALETTE: [Color; 4] = [
+    Color::Rgb { r: 0, g: 0, b: 0 },
+    Color::Rgb { r: 0, g: 255, b: 0 },
+    Color::Rgb {
+        r: 0,
+        g: 120,
+        b: 255,
+    },
+    Color::Rgb {
+        r: 255,
+        g: 255,
+        b: 255,
+    },
+];
+// Border colour normally and while the sound timer runs, which stands in for the buzzer
+const BORDER: Color = Color::DarkGrey;
+const BELL: Color = Color::Yellow;
+
+const USAGE: &str = "Usage: chip8-tui <rom_file> [--quirks <preset>] [--load-address <addr>] \
+[--timing fixed|vip] [--seed <n>] [--memory wrap|fault|ignore] [--stack-depth <n>|unlimited] [--vip-stack] \
+[--keymap <file>|qwerty|azerty|dvorak] [--braille] [--pane]";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Glyphs {
+    // Two vertically stacked pixels per cell, in colour
+    HalfBlocks,
+    // A 2x4 block of pixels per cell, lit if any plane is
+    Braille,
+}
+
+struct Options {
+    rom_path: String,
+    // None picks the quirks the ROM suggests, or the default ones
+    quirks: Option<Quirks>,
+    load_address: usize,
+    timing: TimingModel,
+    seed: Option<u64>,
+    memory_policy: MemoryPolicy,
+    // None leaves the quirks preset's depth
+    stack_depth: Option<StackDepth>,
+    vip_stack: bool,
+    keymap_path: Option<String>,
+    glyphs: Glyphs,
+    pane: bool,
+}
+
+// Puts the terminal into raw mode on an alternate screen and restores it when dropped,
+// including when the emulator exits with an error
+struct TerminalGuard {
+    enhanced_keys: bool,
+}
+
+impl TerminalGuard {
+    fn new() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        // Key release events, where the terminal supports them, give exact key holds
+        let enhanced_keys = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        let mut stdout = io::stdout();
+        execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide)?;
+        if enhanced_keys {
+            execute!(
+                stdout,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+        Ok(Self { enhanced_keys })
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        if self.enhanced_keys {
+            let _ = execute!(stdout, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(
+            stdout,
+            ResetColor,
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        );
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+// Tracks which CHIP-8 keys are held from terminal key events
+struct KeyTracker {
+    enhanced: bool,
+    // Frames left before each key counts as released, for terminals without release events
+    hold: [u32; 16],
+    keys: KeyState,
+}
+
+impl KeyTracker {
+    fn new(enhanced: bool) -> Self {
+        Self {
+            enhanced,
+            hold: [0; 16],
+            keys: KeyState::new(),
+        }
+    }
+
+    fn handle(&mut self, key: u8, kind: KeyEventKind) {
+        let pressed = kind != KeyEventKind::Release;
+        self.keys.set_key_pressed(key, pressed);
+        self.hold[key as usize] = if pressed { HOLD_FRAMES } else { 0 };
+    }
+
+    // The keys to run the next frame with
+    fn next_frame(&mut self) -> KeyState {
+        let keys = self.keys;
+        if !self.enhanced {
+            for (key, hold) in self.hold.iter_mut().enumerate() {
+                *hold = hold.saturating_sub(1);
+                if *hold == 0 {
+                    self.keys.set_key_pressed(key as u8, false);
+                }
+            }
+        }
+        keys
+    }
+}
+
+fn main() -> Result<(), String> {
+    let options = parse_args(env::args().skip(1).collect())?;
+    let rom = Rom::read(&options.rom_path)
+        .map_err(|e| format!("Failed to read ROM file {}: {}", options.rom_path, e))?
+        .with_load_address(options.load_address);
+
+    let quirks = options
+        .quirks
+        .unwrap_or_else(|| rom.suggested_quirks().unwrap_or_default());
+    let mut chip8 = Chip8::new(quirks);
+    chip8
+        .load_rom(&rom)
+        .map_err(|e| format!("Failed to load {}: {}", options.rom_path, e))?;
+    chip8.set_timing(options.timing);
+    chip8.set_memory_policy(options.memory_policy);
+    if let Some(depth) = options.stack_depth {
+        chip8.set_stack_depth(depth);
+    }
+    chip8.set_stack_in_memory(options.vip_stack);
+    if let Some(seed) = options.seed {
+        chip8.seed_random(seed);
+    }
+
+    let keymap = match &options.keymap_path {
+        Some(path) => {
+            // A bundled layout's name, else a file
+            let text = match keymap::bundled(path) {
+                Some(text) => text.to_string(),
+                None => fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read {}: {}", path, e))?,
+            };
+            Keymap::parse(&text, chip8.rom_hash()).map_err(|e| format!("{}: {}", path, e))?
+        }
+        None => Keymap::default(),
+    };
+
+    let guard = TerminalGuard::new().map_err(|e| e.to_string())?;
+    let mut ignored_accesses = 0;
+    let result = run(
+        &mut chip8,
+        &options,
+        &keymap,
+        guard.enhanced_keys,
+        &mut ignored_accesses,
+    )
+    .map_err(|e| e.to_string());
+    drop(guard);
+
+    // Printing these while running would scribble over the screen
+    if options.quirks.is_none()
+        && let Some(summary) = rom.hint_summary()
+    {
+        eprintln!("{}", summary);
+    }
+    if ignored_accesses > 0 {
+        eprintln!("Ignored {} out-of-bounds memory accesses", ignored_accesses);
+    }
+
+    match result {
+        Ok(Some(error)) => Err(format!("Emulation halted: {}", error)),
+        Ok(None) => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+fn parse_args(args: Vec<String>) -> Result<Options, String> {
+    let mut options = Options {
+        rom_path: String::new(),
+        quirks: None,
+        load_address: ROM_START_ADDRESS,
+        timing: TimingModel::Fixed(CYCLES_PER_SECOND / TARGET_FPS),
+        seed: None,
+        memory_policy: MemoryPolicy::default(),
+        stack_depth: None,
+        vip_stack: false,
+        keymap_path: None,
+        glyphs: Glyphs::HalfBlocks,
+        pane: false,
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| args.next().ok_or_else(|| format!("{} needs a value", flag));
+        match arg.as_str() {
+            "--quirks" => {
+                options.quirks = Some(
+                    value("--quirks")?
+                        .parse()
+                        .map_err(|e: UnknownPreset| e.to_string())?,
+                )
+            }
+            "--load-address" => {
+                options.load_address = rom::parse_address(&value("--load-address")?)?
+            }
+            "--timing" => {
+                options.timing =
+                    TimingModel::from_name(&value("--timing")?, CYCLES_PER_SECOND / TARGET_FPS)?
+            }
+            "--seed" => {
+                let seed = value("--seed")?;
+                options.seed = Some(
+                    seed.parse()
+                        .map_err(|_| format!("Invalid seed '{}'", seed))?,
+                );
+            }
+            "--memory" => options.memory_policy = MemoryPolicy::from_name(&value("--memory")?)?,
+            "--stack-depth" => {
+                options.stack_depth = Some(StackDepth::from_name(&value("--stack-depth")?)?)
+            }
+            "--vip-stack" => options.vip_stack = true,
+            "--keymap" => options.keymap_path = Some(value("--keymap")?),
+            "--braille" => options.glyphs = Glyphs::Braille,
+            "--pane" => options.pane = true,
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}\n{}", arg, USAGE)),
+            _ if options.rom_path.is_empty() => options.rom_path = arg,
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    if options.rom_path.is_empty() {
+        return Err(USAGE.to_string());
+    }
+    Ok(options)
+}
+
+// Runs frames at 60 Hz until Esc or Ctrl-C is pressed or the program exits. Returns the
+// error that stopped emulation, if any, and counts accesses skipped by the memory policy.
+fn run(
+    chip8: &mut Chip8,
+    options: &Options,
+    keymap: &Keymap,
+    enhanced_keys: bool,
+    ignored_accesses: &mut usize,
+) -> io::Result<Option<String>> {
+    let mut stdout = io::stdout();
+    let mut keys = KeyTracker::new(enhanced_keys);
+    let mut next_frame = Instant::now();
+    // Cleared to force a full redraw; also changes when the program switches resolution
+    let mut screen_size = None;
+
+    loop {
+        // Handle input until it is time for the next frame
+        while event::poll(next_frame.saturating_duration_since(Instant::now()))? {
+            match event::read()? {
+                Event::Key(KeyEvent {
+                    code: KeyCode::Esc, ..
+                }) => return Ok(None),
+                Event::Key(KeyEvent {
+                    code: KeyCode::Char('c'),
+                    modifiers,
+                    ..
+                }) if modifiers.contains(KeyModifiers::CONTROL) => return Ok(None),
+                Event::Key(KeyEvent { code, kind, .. }) => {
+                    if let Some(key) = key_name(code).and_then(|name| keymap.keyboard_key(&name)) {
+                        keys.handle(key, kind);
+                    }
+                }
+                Event::Resize(..) => screen_size = None,
+                _ => {}
+            }
+        }
+        next_frame += FRAME_DURATION;
+        // Don't try to catch up after falling far behind, e.g. when the process was stopped
+        if next_frame < Instant::now() {
+            next_frame = Instant::now() + FRAME_DURATION;
+        }
+
+        let output = chip8.run_frame(&keys.next_frame());
+        *ignored_accesses += output.ignored_accesses.len();
+        let size = screen_cells(chip8, options.glyphs);
+        let redraw = screen_size != Some(size);
+        if redraw {
+            queue!(stdout, terminal::Clear(terminal::ClearType::All))?;
+            screen_size = Some(size);
+        }
+        if output.display_changed || redraw {
+            draw_screen(&mut stdout, chip8, options.glyphs)?;
+        }
+        draw_border(&mut stdout, chip8, options.glyphs, output.sound_on)?;
+        if options.pane {
+            draw_pane(&mut stdout, chip8, options.glyphs)?;
+        }
+        stdout.flush()?;
+
+        if let Some(error) = output.error {
+            return Ok(Some(error.to_string()));
+        }
+        if chip8.has_exited() {
+            return Ok(None);
+        }
+    }
+}
+
+// Size of the screen in terminal cells
+fn screen_cells(chip8: &Chip8, glyphs: Glyphs) -> (u16, u16) {
+    let (width, height) = (chip8.display.width(), chip8.display.height());
+    match glyphs {
+        Glyphs::HalfBlocks => (width as u16, height.div_ceil(2) as u16),
+        Glyphs::Braille => (width.div_ceil(2) as u16, height.div_ceil(4) as u16),
+    }
+}
+
+// Draws the screen inside a one-cell border at the top left of the terminal
+fn draw_screen(out: &mut impl Write, chip8: &Chip8, glyphs: Glyphs) -> io::Result<()> {
+    let (columns, rows) = screen_cells(chip8, glyphs);
+    let display = &chip8.display;
+    let pixel = |x: usize, y: usize| {
+        if x < display.width() && y < display.height() {
+            display.pixel_color(x, y)
+        } else {
+            0
+        }
+    };
+
+    for row in 0..rows {
+        queue!(out, cursor::MoveTo(1, row + 1))?;
+        match glyphs {
+            Glyphs::HalfBlocks => {
+                // Only change colours when they differ from the previous cell's
+                let mut current = None;
+                for column in 0..columns as usize {
+                    let top = pixel(column, row as usize * 2);
+                    let bottom = pixel(column, row as usize * 2 + 1);
+                    if current != Some((top, bottom)) {
+                        queue!(
+                            out,
+                            SetForegroundColor(PALETTE[top as usize]),
+                            SetBackgroundColor(PALETTE[bottom as usize])
+                        )?;
+                        current = Some((top, bottom));
+                    }
+                    queue!(out, Print('▀'))?;
+                }
+            }
+            Glyphs::Braille => {
+                queue!(
+                    out,
+                    SetForegroundColor(PALETTE[1]),
+                    SetBackgroundColor(PALETTE[0])
+                )?;
+                let line: String = (0..columns as usize)
+                    .map(|column| braille_cell(&pixel, column * 2, row as usize * 4))
+                    .collect();
+                queue!(out, Print(line))?;
+            }
+        }
+    }
+    queue!(out, ResetColor)
+}
+
+// The braille character for the 2x4 pixels whose top left corner is (x, y)
+fn braille_cell(pixel: &impl Fn(usize, usize) -> u8, x: usize, y: usize) -> char {
+    // Unicode braille numbers its dots down the left column, then the right, then the
+    // bottom row
+    const DOTS: [(usize, usize, u32); 8] = [
+        (0, 0, 0x01),
+        (0, 1, 0x02),
+        (0, 2, 0x04),
+        (1, 0, 0x08),
+        (1, 1, 0x10),
+        (1, 2, 0x20),
+        (0, 3, 0x40),
+        (1, 3, 0x80),
+    ];
+    let bits = DOTS
+        .iter()
+        .filter(|(dx, dy, _)| pixel(x + dx, y + dy) != 0)
+        .fold(0, |bits, (_, _, bit)| bits | bit);
+    char::from_u32(0x2800 + bits).unwrap()
+}
+
+// The border doubles as a visual bell, lighting up while the sound timer runs
+fn draw_border(out: &mut impl Write, chip8: &Chip8, glyphs: Glyphs, bell: bool) -> io::Result<()> {
+    let (columns, rows) = screen_cells(chip8, glyphs);
+    let horizontal = "─".repeat(columns as usize);
+
+    queue!(out, SetForegroundColor(if bell { BELL } else { BORDER }))?;
+    queue!(
+        out,
+        cursor::MoveTo(0, 0),
+        Print(format!("┌{}┐", horizontal))
+    )?;
+    for row in 1..=rows {
+        queue!(
+            out,
+            cursor::MoveTo(0, row),
+            Print('│'),
+            cursor::MoveTo(columns + 1, row),
+            Print('│')
+        )?;
+    }
+    queue!(
+        out,
+        cursor::MoveTo(0, rows + 1),
+        Print(format!("└{}┘", horizontal)),
+        cursor::MoveTo(0, rows + 2),
+        Print(if bell { "♪ beep " } else { "       " }),
+        ResetColor
+    )
+}
+
+// Registers and the instructions from the PC on, to the right of the screen
+fn draw_pane(out: &mut impl Write, chip8: &Chip8, glyphs: Glyphs) -> io::Result<()> {
+    let (columns, _) = screen_cells(chip8, glyphs);
+    let left = columns + 4;
+
+    let mut lines: Vec<String> = format_registers(chip8)
+        .lines()
+        .map(str::to_string)
+        .collect();
+    lines.push(String::new());
+
+    let memory = chip8.memory();
+    let mut address = chip8.program_counter() as usize;
+    for _ in 0..PANE_INSTRUCTIONS {
+        if address + 1 >= memory.len() {
+            break;
+        }
+        let opcode = u16::from_be_bytes([memory[address], memory[address + 1]]);
+        let marker = if address == chip8.program_counter() as usize {
+            '>'
+        } else {
+            ' '
+        };
+        let text = match Instruction::try_from(opcode) {
+            Ok(instruction) => instruction.to_string(),
+            Err(_) => format!("DW {:#06X}", opcode),
+        };
+        lines.push(format!("{} {:04X}: {}", marker, address, text));
+        address += match Instruction::try_from(opcode) {
+            Ok(Instruction::LoadIndexLong) => 4,
+            _ => 2,
+        };
+    }
+
+    // Pad every line so text left by a longer previous line is overwritten
+    let width = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0)
+        + 8;
+    for (row, line) in lines.iter().enumerate() {
+        queue!(
+            out,
+            cursor::MoveTo(left, row as u16),
+            Print(format!("{:width$}", line, width = width))
+        )?;
+    }
+    Ok(())
+}
+
+// The SDL name of a terminal key, which is what keymaps bind
+fn key_name(code: KeyCode) -> Option<String> {
+    match code {
+        KeyCode::Char(' ') => Some("Space".to_string()),
+        KeyCode::Char(c) => Some(c.to_string()),
+        KeyCode::Up => Some("Up".to_string()),
+        KeyCode::Down => Some("Down".to_string()),
+        KeyCode::Left => Some("Left".to_string()),
+        KeyCode::Right => Some("Right".to_string()),
+        KeyCode::Enter => Some("Return".to_string()),
+        KeyCode::Tab => Some("Tab".to_string()),
+        KeyCode::Backspace => Some("Backspace".to_string()),
+        _ => None,
+    }
+}
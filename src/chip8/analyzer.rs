@@ -0,0 +1,339 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use super::disassembler::{self, decode_at, instruction_size};
+use super::instruction::Instruction;
+use super::quirks::Quirks;
+use super::rom::PlatformHint;
+
+// How far past Fx55/Fx65 to look for an instruction that uses the I it left behind
+const INDEX_LOOKAHEAD: usize = 8;
+
+// The newest instruction set a program's reachable code uses. Each is a superset of the
+// one before, so a program is placed by the newest instruction it contains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DetectedPlatform {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl DetectedPlatform {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DetectedPlatform::Chip8 => "CHIP-8",
+            DetectedPlatform::SuperChip => "SUPER-CHIP",
+            DetectedPlatform::XoChip => "XO-CHIP",
+        }
+    }
+
+    // The quirks preset of the platform
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            DetectedPlatform::Chip8 => Quirks::COSMAC_VIP,
+            DetectedPlatform::SuperChip => Quirks::SUPER_CHIP,
+            DetectedPlatform::XoChip => Quirks::XO_CHIP,
+        }
+    }
+
+    fn of(instruction: &Instruction) -> Self {
+        if instruction.requires_xo_chip() {
+            return DetectedPlatform::XoChip;
+        }
+        match instruction {
+            Instruction::ScrollDown(_)
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::Exit
+            | Instruction::LowResolution
+            | Instruction::HighResolution
+            | Instruction::LoadBigFontCharacter(_)
+            | Instruction::StoreFlags(_)
+            | Instruction::LoadFlags(_) => DetectedPlatform::SuperChip,
+            // 16x16 sprites; the VIP draws nothing
+            Instruction::Display(_, _, n) if n.value() == 0 => DetectedPlatform::SuperChip,
+            _ => DetectedPlatform::Chip8,
+        }
+    }
+}
+
+// A quirk whose setting changes what a program does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SensitiveQuirk {
+    // 8xy6/8xyE with different registers shift Vy on the VIP and Vx on later interpreters
+    Shift,
+    // An instruction after Fx55/Fx65 uses I, which only the VIP leaves past the registers
+    LoadStore,
+    // Bxnn with x above 0 adds V0 on the VIP and Vx on the CHIP-48 and SUPER-CHIP
+    Jump,
+}
+
+impl SensitiveQuirk {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            SensitiveQuirk::Shift => "shifts a register other than its source (shift quirk)",
+            SensitiveQuirk::LoadStore => {
+                "relies on where a register store or load leaves I (load/store quirk)"
+            }
+            SensitiveQuirk::Jump => "jumps with an offset other than V0 (jump quirk)",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Finding {
+    pub address: u16,
+    pub instruction: Instruction,
+    pub quirk: SensitiveQuirk,
+}
+
+// What reading a ROM's code, without running it, says about the machine it needs
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomReport {
+    pub platform: DetectedPlatform,
+    // The first reachable instruction of the platform's own instruction set, if any
+    pub evidence: Option<(u16, Instruction)>,
+    // Reachable instructions that behave differently under different quirks
+    pub findings: Vec<Finding>,
+    // Number of reachable instructions the report is based on
+    pub instructions: usize,
+}
+
+impl RomReport {
+    pub fn suggested_quirks(&self) -> Quirks {
+        self.platform.quirks()
+    }
+
+    // A hint for `Rom`. Plain CHIP-8 code gives none, since later platforms run it too.
+    pub fn hint(&self) -> Option<PlatformHint> {
+        let (address, instruction) = self.evidence?;
+        Some(PlatformHint {
+            quirks: self.suggested_quirks(),
+            reason: format!(
+                "{} instruction {} at {:#05X}",
+                self.platform.name(),
+                instruction,
+                address
+            ),
+        })
+    }
+}
+
+impl fmt::Display for RomReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Reachable instructions: {}", self.instructions)?;
+        match self.evidence {
+            Some((address, instruction)) => writeln!(
+                f,
+                "Platform: {} ({} at {:#05X})",
+                self.platform.name(),
+                instruction,
+                address
+            )?,
+            None => writeln!(
+                f,
+                "Platform: {} (no later instructions used)",
+                self.platform.name()
+            )?,
+        }
+        let quirks = self.suggested_quirks();
+        writeln!(
+            f,
+            "Suggested quirks: {}",
+            quirks.preset_name().unwrap_or("custom")
+        )?;
+        if self.findings.is_empty() {
+            writeln!(f, "No quirk-sensitive code found")?;
+        } else {
+            writeln!(f, "Quirk-sensitive code:")?;
+            for finding in &self.findings {
+                writeln!(
+                    f,
+                    "    {:#05X}  {:<16} {}",
+                    finding.address,
+                    finding.instruction.to_string(),
+                    finding.quirk.describe()
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Looks through the code reachable from `origin` for the instructions a ROM needs and for
+// patterns whose behaviour depends on quirks. Data is skipped, so sprite bytes that happen
+// to look like SUPER-CHIP opcodes do not count.
+pub fn analyze(image: &[u8], origin: u16) -> RomReport {
+    let analysis = disassembler::analyze(image, origin);
+    let mut report = RomReport {
+        platform: DetectedPlatform::Chip8,
+        evidence: None,
+        findings: Vec::new(),
+        instructions: analysis.code.len(),
+    };
+
+    for &address in &analysis.code {
+        let Some((instruction, _)) = decode_at(image, origin, address) else {
+            continue;
+        };
+
+        let platform = DetectedPlatform::of(&instruction);
+        if platform > report.platform {
+            report.platform = platform;
+            report.evidence = Some((address, instruction));
+        }
+
+        let quirk = match instruction {
+            Instruction::LoadShiftRight(x, y) | Instruction::LoadShiftLeft(x, y)
+                if x.value() != y.value() =>
+            {
+                Some(SensitiveQuirk::Shift)
+            }
+            Instruction::JumpWithOffset(nnn) if nnn >> 8 != 0 => Some(SensitiveQuirk::Jump),
+            Instruction::StoreRegisters(_) | Instruction::LoadRegisters(_)
+                if uses_index_next(image, origin, &analysis.code, address, &instruction) =>
+            {
+                Some(SensitiveQuirk::LoadStore)
+            }
+            _ => None,
+        };
+        if let Some(quirk) = quirk {
+            report.findings.push(Finding {
+                address,
+                instruction,
+                quirk,
+            });
+        }
+    }
+
+    report
+}
+
+// Follows the straight-line code after the instruction at `address` and reports whether
+// something reads I before it is set again or control moves elsewhere
+fn uses_index_next(
+    image: &[u8],
+    origin: u16,
+    code: &BTreeSet<u16>,
+    address: u16,
+    instruction: &Instruction,
+) -> bool {
+    let mut next = address.wrapping_add(instruction_size(instruction));
+    for _ in 0..INDEX_LOOKAHEAD {
+        if !code.contains(&next) {
+            return false;
+        }
+        let Some((instruction, _)) = decode_at(image, origin, next) else {
+            return false;
+        };
+        match instruction {
+            Instruction::StoreRegisters(_)
+            | Instruction::LoadRegisters(_)
+            | Instruction::StoreRegisterRange(..)
+            | Instruction::LoadRegisterRange(..)
+            | Instruction::Display(..)
+            | Instruction::LoadBinaryCodedDecimal(_)
+            | Instruction::AddIndexRegister(_)
+            | Instruction::LoadAudioPattern => return true,
+            Instruction::LoadIndexRegister(_)
+            | Instruction::LoadIndexLong
+            | Instruction::LoadFontCharacter(_)
+            | Instruction::LoadBigFontCharacter(_)
+            | Instruction::Jump(_)
+            | Instruction::JumpWithOffset(_)
+            | Instruction::Call(_)
+            | Instruction::Return
+            | Instruction::Exit => return false,
+            _ => {}
+        }
+        next = next.wrapping_add(instruction_size(&instruction));
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyze_words(words: &[u16]) -> RomReport {
+        let image: Vec<u8> = words.iter().flat_map(|word| word.to_be_bytes()).collect();
+        analyze(&image, 0x200)
+    }
+
+    fn quirks_found(report: &RomReport) -> Vec<(u16, SensitiveQuirk)> {
+        let findings = report.findings.iter();
+        findings
+            .map(|finding| (finding.address, finding.quirk))
+            .collect()
+    }
+
+    #[test]
+    fn plain_chip8_gives_no_hint() {
+        let report = analyze_words(&[0x6001, 0xA20A, 0xD015, 0x1206]);
+        assert_eq!(report.platform, DetectedPlatform::Chip8);
+        assert_eq!(report.evidence, None);
+        assert_eq!(report.hint(), None);
+        assert_eq!(report.instructions, 4);
+        assert!(quirks_found(&report).is_empty());
+    }
+
+    #[test]
+    fn the_newest_instruction_places_the_program() {
+        let report = analyze_words(&[0x00FF, 0x1202]);
+        assert_eq!(report.platform, DetectedPlatform::SuperChip);
+        assert_eq!(report.evidence, Some((0x200, Instruction::HighResolution)));
+        let hint = report.hint().unwrap();
+        assert_eq!(hint.quirks, Quirks::SUPER_CHIP);
+        assert_eq!(hint.reason, "SUPER-CHIP instruction HIGH at 0x200");
+
+        let sprite = analyze_words(&[0x6001, 0xD110, 0x1202]);
+        assert_eq!(sprite.platform, DetectedPlatform::SuperChip);
+        assert_eq!(sprite.evidence.map(|(address, _)| address), Some(0x202));
+
+        // XO-CHIP wins over the SUPER-CHIP scroll before it
+        let report = analyze_words(&[0x00FB, 0xF000, 0x0300, 0x1206]);
+        assert_eq!(report.platform, DetectedPlatform::XoChip);
+        assert_eq!(report.evidence, Some((0x202, Instruction::LoadIndexLong)));
+        assert_eq!(report.suggested_quirks(), Quirks::XO_CHIP);
+    }
+
+    #[test]
+    fn unreachable_bytes_do_not_count() {
+        // 00FF and 5122 sit behind the jump, where only data can be
+        let report = analyze_words(&[0x1206, 0x00FF, 0x5122, 0x1206]);
+        assert_eq!(report.platform, DetectedPlatform::Chip8);
+        assert_eq!(report.instructions, 2);
+    }
+
+    #[test]
+    fn quirk_sensitive_code_is_reported() {
+        let shifts = analyze_words(&[0x8006, 0x800E, 0x8126, 0x1206]);
+        assert_eq!(quirks_found(&shifts), [(0x204, SensitiveQuirk::Shift)]);
+
+        // B0nn reads V0 under either setting
+        assert!(quirks_found(&analyze_words(&[0xB0FF])).is_empty());
+        let jump = analyze_words(&[0x6000, 0xB1FF]);
+        assert_eq!(quirks_found(&jump), [(0x202, SensitiveQuirk::Jump)]);
+
+        // The first store is followed by a load that uses the I it left; the second has
+        // I set again before anything reads it
+        let stores = analyze_words(&[
+            0xA300, 0xF155, 0x6000, 0xF165, 0xA300, 0xF155, 0xA300, 0xD015,
+        ]);
+        let stores = quirks_found(&stores);
+        assert_eq!(stores, [(0x202, SensitiveQuirk::LoadStore)]);
+    }
+
+    #[test]
+    fn reports_list_their_findings() {
+        let text = analyze_words(&[0x00FF, 0x8126, 0x1204]).to_string();
+        assert!(text.contains("Platform: SUPER-CHIP (HIGH at 0x200)\n"));
+        assert!(text.contains("Suggested quirks: schip\n"));
+        assert!(text.contains("0x202  SHR V1, V2"));
+        assert!(text.contains("(shift quirk)"));
+
+        let text = analyze_words(&[0x1200]).to_string();
+        assert!(text.contains("Platform: CHIP-8 (no later instructions used)\n"));
+        assert!(text.contains("No quirk-sensitive code found\n"));
+    }
+}
@@ -0,0 +1,784 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use super::ROM_START_ADDRESS;
+use super::instruction::Instruction;
+use super::register::RegisterIndex;
+use super::types::Nibble;
+
+// Assembles the mnemonics documented on `Instruction` (Cowgod's syntax), which is also
+// what the disassembler prints. Besides instructions a line can hold:
+//   label:                 a label, optionally followed by a statement
+//   define NAME value      a constant
+//   DB 1, 0x02, #03, %100  data bytes (DW for big-endian words)
+//   SPRITE ..####.. .#....#.   sprite rows, 8 or 16 pixels wide
+//   ORG 0x300              continue at a later address, padding with zeros
+// Values are decimal, hex (0x, # or $), binary (0b or %), names, or sums of them.
+// `;` starts a comment.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl AssembleError {
+    pub(crate) fn new(line: usize, column: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            column,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+pub struct Assembly {
+    pub rom: Vec<u8>,
+    // Label addresses by name
+    pub labels: BTreeMap<String, u16>,
+    pub source_map: SourceMap,
+}
+
+// Source line of each emitted instruction or data statement, by address
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    lines: BTreeMap<u16, usize>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, address: u16, line: usize) {
+        self.lines.insert(address, line);
+    }
+
+    pub fn line_for(&self, address: u16) -> Option<usize> {
+        self.lines.get(&address).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u16, usize)> + '_ {
+        self.lines.iter().map(|(&address, &line)| (address, line))
+    }
+
+    // One `<hex address> <line>` pair per line
+    pub fn to_text(&self) -> String {
+        self.iter()
+            .map(|(address, line)| format!("{:04X} {}\n", address, line))
+            .collect()
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut map = Self::new();
+        for (index, entry) in text.lines().enumerate() {
+            if entry.trim().is_empty() {
+                continue;
+            }
+            let error = || format!("invalid source map line {}: '{}'", index + 1, entry);
+            let (address, line) = entry.trim().split_once(' ').ok_or_else(error)?;
+            let address = u16::from_str_radix(address, 16).map_err(|_| error())?;
+            let line = line.trim().parse().map_err(|_| error())?;
+            map.insert(address, line);
+        }
+        Ok(map)
+    }
+}
+
+// A piece of a line, remembering where in the line it started (1-based)
+#[derive(Clone, Copy)]
+struct Span<'a> {
+    text: &'a str,
+    column: usize,
+}
+
+impl<'a> Span<'a> {
+    fn slice(&self, start: usize, end: usize) -> Span<'a> {
+        Span {
+            text: &self.text[start..end],
+            column: self.column + start,
+        }
+    }
+
+    fn trim(&self) -> Span<'a> {
+        let start = self.text.len() - self.text.trim_start().len();
+        let end = self.text.trim_end().len().max(start);
+        self.slice(start, end)
+    }
+
+    // Splits off the first whitespace-delimited word
+    fn split_word(&self) -> (Span<'a>, Span<'a>) {
+        let trimmed = self.trim();
+        let end = trimmed
+            .text
+            .find(char::is_whitespace)
+            .unwrap_or(trimmed.text.len());
+        (
+            trimmed.slice(0, end),
+            trimmed.slice(end, trimmed.text.len()).trim(),
+        )
+    }
+
+    fn split(&self, separator: impl Fn(char) -> bool) -> Vec<Span<'a>> {
+        let mut parts = Vec::new();
+        let mut start = 0;
+        for (offset, c) in self.text.char_indices() {
+            if separator(c) {
+                parts.push(self.slice(start, offset).trim());
+                start = offset + c.len_utf8();
+            }
+        }
+        parts.push(self.slice(start, self.text.len()).trim());
+        parts
+    }
+}
+
+enum Term {
+    Number(i64),
+    Symbol(String),
+}
+
+struct Expr {
+    // Signed terms that are summed
+    terms: Vec<(i64, Term, usize)>,
+    column: usize,
+}
+
+enum Operand {
+    Register(RegisterIndex),
+    // Vx-Vy
+    Range(RegisterIndex, RegisterIndex),
+    // I
+    Index,
+    // [I]
+    Indirect,
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    BigFont,
+    Bcd,
+    Flags,
+    // long addr, only valid as `LD I, long addr`
+    Long(Expr),
+    Value(Expr),
+}
+
+enum Statement {
+    Instruction {
+        mnemonic: String,
+        operands: Vec<Operand>,
+    },
+    Bytes(Vec<Expr>),
+    Words(Vec<Expr>),
+    Raw(Vec<u8>),
+    // Zeros skipped over by ORG
+    Padding(usize),
+}
+
+struct Line {
+    number: usize,
+    column: usize,
+    statement: Statement,
+}
+
+const MNEMONICS: &[&str] = &[
+    "CLS", "RET", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "JP", "CALL", "SE", "SNE",
+    "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL", "RND", "DRW", "SKP", "SKNP",
+    "PLANE", "AUDIO", "PITCH",
+];
+
+// Assembles a program loaded at 0x200
+pub fn assemble(source: &str) -> Result<Assembly, AssembleError> {
+    let mut assembler = Assembler {
+        symbols: HashMap::new(),
+        labels: BTreeMap::new(),
+        lines: Vec::new(),
+        address: ROM_START_ADDRESS as u16,
+    };
+
+    for (index, text) in source.lines().enumerate() {
+        assembler.parse_line(index + 1, text)?;
+    }
+    assembler.emit()
+}
+
+struct Assembler {
+    // Constants and labels share one namespace
+    symbols: HashMap<String, i64>,
+    labels: BTreeMap<String, u16>,
+    lines: Vec<Line>,
+    address: u16,
+}
+
+impl Assembler {
+    fn parse_line(&mut self, number: usize, text: &str) -> Result<(), AssembleError> {
+        let text = text.split(';').next().unwrap_or("");
+        let mut rest = Span { text, column: 1 }.trim();
+
+        // A label is a name directly followed by a colon
+        let (word, _) = rest.split_word();
+        if let Some(colon) = word.text.find(':') {
+            let name = word.slice(0, colon);
+            if !is_identifier(name.text) {
+                return Err(AssembleError::new(
+                    number,
+                    name.column,
+                    format!("invalid label name '{}'", name.text),
+                ));
+            }
+            self.define(number, name, self.address as i64)?;
+            self.labels.insert(name.text.to_string(), self.address);
+            rest = rest.slice(colon + 1, rest.text.len()).trim();
+        }
+        if rest.text.is_empty() {
+            return Ok(());
+        }
+
+        let (keyword, operands) = rest.split_word();
+        let statement = match keyword.text.to_ascii_uppercase().as_str() {
+            "DEFINE" => {
+                let (name, value) = operands.split_word();
+                if !is_identifier(name.text) {
+                    return Err(AssembleError::new(
+                        number,
+                        name.column,
+                        "expected a constant name after define",
+                    ));
+                }
+                let value = self.evaluate_now(number, value)?;
+                return self.define(number, name, value);
+            }
+            "ORG" => {
+                let target = self.evaluate_now(number, operands)?;
+                if target < self.address as i64 {
+                    return Err(AssembleError::new(
+                        number,
+                        operands.column,
+                        format!("ORG {:#X} is behind the current address", target),
+                    ));
+                }
+                if target > 0xFFFF {
+                    return Err(AssembleError::new(
+                        number,
+                        operands.column,
+                        format!("ORG {:#X} is outside memory", target),
+                    ));
+                }
+                Statement::Padding((target - self.address as i64) as usize)
+            }
+            "DB" => Statement::Bytes(self.parse_list(number, operands)?),
+            "DW" => Statement::Words(self.parse_list(number, operands)?),
+            "SPRITE" => Statement::Raw(parse_sprite(number, operands)?),
+            mnemonic if MNEMONICS.contains(&mnemonic) => {
+                let operands = if operands.text.is_empty() {
+                    Vec::new()
+                } else {
+                    operands
+                        .split(|c| c == ',')
+                        .into_iter()
+                        .map(|operand| parse_operand(number, operand))
+                        .collect::<Result<_, _>>()?
+                };
+                Statement::Instruction {
+                    mnemonic: mnemonic.to_string(),
+                    operands,
+                }
+            }
+            _ => {
+                return Err(AssembleError::new(
+                    number,
+                    keyword.column,
+                    format!("unknown instruction '{}'", keyword.text),
+                ));
+            }
+        };
+
+        let size = statement_size(&statement);
+        if self.address as usize + size > 0x10000 {
+            return Err(AssembleError::new(
+                number,
+                keyword.column,
+                "program does not fit in memory",
+            ));
+        }
+        self.lines.push(Line {
+            number,
+            column: keyword.column,
+            statement,
+        });
+        self.address = self.address.wrapping_add(size as u16);
+        Ok(())
+    }
+
+    fn define(&mut self, number: usize, name: Span, value: i64) -> Result<(), AssembleError> {
+        if self.symbols.insert(name.text.to_string(), value).is_some() {
+            return Err(AssembleError::new(
+                number,
+                name.column,
+                format!("'{}' is already defined", name.text),
+            ));
+        }
+        Ok(())
+    }
+
+    fn parse_list(&self, number: usize, operands: Span) -> Result<Vec<Expr>, AssembleError> {
+        operands
+            .split(|c| c == ',')
+            .into_iter()
+            .map(|value| parse_expr(number, value))
+            .collect()
+    }
+
+    // Directives that change layout must be resolvable when they are read, so they may
+    // only refer to names defined above them
+    fn evaluate_now(&self, number: usize, span: Span) -> Result<i64, AssembleError> {
+        let expr = parse_expr(number, span)?;
+        self.evaluate(number, &expr)
+    }
+
+    fn evaluate(&self, number: usize, expr: &Expr) -> Result<i64, AssembleError> {
+        let mut value = 0;
+        for (sign, term, column) in &expr.terms {
+            let term = match term {
+                Term::Number(n) => *n,
+                Term::Symbol(name) => *self.symbols.get(name).ok_or_else(|| {
+                    AssembleError::new(number, *column, format!("undefined name '{}'", name))
+                })?,
+            };
+            value += sign * term;
+        }
+        Ok(value)
+    }
+
+    fn ranged(
+        &self,
+        number: usize,
+        expr: &Expr,
+        what: &str,
+        range: std::ops::RangeInclusive<i64>,
+    ) -> Result<i64, AssembleError> {
+        let value = self.evaluate(number, expr)?;
+        if !range.contains(&value) {
+            return Err(AssembleError::new(
+                number,
+                expr.column,
+                format!("{} {} is out of range", what, value),
+            ));
+        }
+        Ok(value)
+    }
+
+    fn address(&self, number: usize, expr: &Expr) -> Result<u16, AssembleError> {
+        Ok(self.ranged(number, expr, "address", 0..=0xFFF)? as u16)
+    }
+
+    // Bytes may be written signed, so `ADD V0, -1` means 0xFF
+    fn byte(&self, number: usize, expr: &Expr) -> Result<u8, AssembleError> {
+        Ok(self.ranged(number, expr, "byte", -0x80..=0xFF)? as u8)
+    }
+
+    fn nibble(&self, number: usize, expr: &Expr) -> Result<Nibble, AssembleError> {
+        Ok(Nibble::try_from(self.ranged(number, expr, "nibble", 0..=0xF)? as u8).unwrap())
+    }
+
+    fn emit(self) -> Result<Assembly, AssembleError> {
+        let mut rom = Vec::new();
+        let mut source_map = SourceMap::new();
+
+        for line in &self.lines {
+            if !matches!(line.statement, Statement::Padding(_)) {
+                source_map.insert((ROM_START_ADDRESS + rom.len()) as u16, line.number);
+            }
+            match &line.statement {
+                Statement::Instruction { mnemonic, operands } => {
+                    let (instruction, long) = self.encode(line, mnemonic, operands)?;
+                    rom.extend_from_slice(&u16::from(instruction).to_be_bytes());
+                    if let Some(address) = long {
+                        rom.extend_from_slice(&address.to_be_bytes());
+                    }
+                }
+                Statement::Bytes(values) => {
+                    for value in values {
+                        rom.push(self.byte(line.number, value)?);
+                    }
+                }
+                Statement::Words(values) => {
+                    for value in values {
+                        let word = self.ranged(line.number, value, "word", -0x8000..=0xFFFF)?;
+                        rom.extend_from_slice(&(word as u16).to_be_bytes());
+                    }
+                }
+                Statement::Raw(bytes) => rom.extend_from_slice(bytes),
+                Statement::Padding(len) => rom.resize(rom.len() + len, 0),
+            }
+        }
+
+        Ok(Assembly {
+            rom,
+            labels: self.labels,
+            source_map,
+        })
+    }
+
+    fn encode(
+        &self,
+        line: &Line,
+        mnemonic: &str,
+        operands: &[Operand],
+    ) -> Result<(Instruction, Option<u16>), AssembleError> {
+        use Operand::*;
+        let n = line.number;
+
+        let instruction = match (mnemonic, operands) {
+            ("CLS", []) => Instruction::Clear,
+            ("RET", []) => Instruction::Return,
+            ("SCD", [Value(e)]) => Instruction::ScrollDown(self.nibble(n, e)?),
+            ("SCU", [Value(e)]) => Instruction::ScrollUp(self.nibble(n, e)?),
+            ("SCR", []) => Instruction::ScrollRight,
+            ("SCL", []) => Instruction::ScrollLeft,
+            ("EXIT", []) => Instruction::Exit,
+            ("LOW", []) => Instruction::LowResolution,
+            ("HIGH", []) => Instruction::HighResolution,
+            ("JP", [Value(e)]) => Instruction::Jump(self.address(n, e)?),
+            ("JP", [Register(v0), Value(e)]) if v0.value() == 0 => {
+                Instruction::JumpWithOffset(self.address(n, e)?)
+            }
+            ("CALL", [Value(e)]) => Instruction::Call(self.address(n, e)?),
+            ("SE", [Register(x), Value(e)]) => Instruction::SkipEqual(*x, self.byte(n, e)?),
+            ("SE", [Register(x), Register(y)]) => Instruction::SkipEqualRegister(*x, *y),
+            ("SNE", [Register(x), Value(e)]) => Instruction::SkipNotEqual(*x, self.byte(n, e)?),
+            ("SNE", [Register(x), Register(y)]) => Instruction::SkipNotEqualRegister(*x, *y),
+            ("LD", [Indirect, Range(x, y)]) => Instruction::StoreRegisterRange(*x, *y),
+            ("LD", [Range(x, y), Indirect]) => Instruction::LoadRegisterRange(*x, *y),
+            ("LD", [Register(x), Value(e)]) => Instruction::Load(*x, self.byte(n, e)?),
+            ("ADD", [Register(x), Value(e)]) => Instruction::Add(*x, self.byte(n, e)?),
+            ("LD", [Register(x), Register(y)]) => Instruction::LoadRegister(*x, *y),
+            ("OR", [Register(x), Register(y)]) => Instruction::LoadOr(*x, *y),
+            ("AND", [Register(x), Register(y)]) => Instruction::LoadAnd(*x, *y),
+            ("XOR", [Register(x), Register(y)]) => Instruction::LoadXor(*x, *y),
+            ("ADD", [Register(x), Register(y)]) => Instruction::LoadAdd(*x, *y),
+            ("SUB", [Register(x), Register(y)]) => Instruction::LoadSub(*x, *y),
+            ("SHR", [Register(x)]) => Instruction::LoadShiftRight(*x, *x),
+            ("SHR", [Register(x), Register(y)]) => Instruction::LoadShiftRight(*x, *y),
+            ("SUBN", [Register(x), Register(y)]) => Instruction::LoadSubNegative(*x, *y),
+            ("SHL", [Register(x)]) => Instruction::LoadShiftLeft(*x, *x),
+            ("SHL", [Register(x), Register(y)]) => Instruction::LoadShiftLeft(*x, *y),
+            ("LD", [Index, Value(e)]) => Instruction::LoadIndexRegister(self.address(n, e)?),
+            ("LD", [Index, Long(e)]) => {
+                let address = self.ranged(n, e, "address", 0..=0xFFFF)? as u16;
+                return Ok((Instruction::LoadIndexLong, Some(address)));
+            }
+            ("RND", [Register(x), Value(e)]) => Instruction::Random(*x, self.byte(n, e)?),
+            ("DRW", [Register(x), Register(y), Value(e)]) => {
+                Instruction::Display(*x, *y, self.nibble(n, e)?)
+            }
+            ("SKP", [Register(x)]) => Instruction::SkipKeyPress(*x),
+            ("SKNP", [Register(x)]) => Instruction::SkipKeyNotPress(*x),
+            ("PLANE", [Value(e)]) => Instruction::SelectPlanes(self.nibble(n, e)?),
+            ("AUDIO", []) => Instruction::LoadAudioPattern,
+            ("LD", [Register(x), DelayTimer]) => Instruction::LoadDelayTimer(*x),
+            ("LD", [Register(x), Key]) => Instruction::LoadKeyPress(*x),
+            ("LD", [DelayTimer, Register(x)]) => Instruction::StoreDelayTimer(*x),
+            ("LD", [SoundTimer, Register(x)]) => Instruction::StoreSoundTimer(*x),
+            ("ADD", [Index, Register(x)]) => Instruction::AddIndexRegister(*x),
+            ("LD", [Font, Register(x)]) => Instruction::LoadFontCharacter(*x),
+            ("LD", [BigFont, Register(x)]) => Instruction::LoadBigFontCharacter(*x),
+            ("LD", [Bcd, Register(x)]) => Instruction::LoadBinaryCodedDecimal(*x),
+            ("PITCH", [Register(x)]) => Instruction::LoadPitch(*x),
+            ("LD", [Indirect, Register(x)]) => Instruction::StoreRegisters(*x),
+            ("LD", [Register(x), Indirect]) => Instruction::LoadRegisters(*x),
+            ("LD", [Flags, Register(x)]) => Instruction::StoreFlags(*x),
+            ("LD", [Register(x), Flags]) => Instruction::LoadFlags(*x),
+            _ => {
+                return Err(AssembleError::new(
+                    n,
+                    line.column,
+                    format!("invalid operands for {}", mnemonic),
+                ));
+            }
+        };
+
+        Ok((instruction, None))
+    }
+}
+
+fn statement_size(statement: &Statement) -> usize {
+    match statement {
+        Statement::Instruction { operands, .. } => match operands.as_slice() {
+            [Operand::Index, Operand::Long(_)] => 4,
+            _ => 2,
+        },
+        Statement::Bytes(values) => values.len(),
+        Statement::Words(values) => values.len() * 2,
+        Statement::Raw(bytes) => bytes.len(),
+        Statement::Padding(len) => *len,
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let lower = text.to_ascii_lowercase();
+    let (digits, radix) = if let Some(hex) = lower
+        .strip_prefix("0x")
+        .or_else(|| lower.strip_prefix('#'))
+        .or_else(|| lower.strip_prefix('$'))
+    {
+        (hex, 16)
+    } else if let Some(binary) = lower.strip_prefix("0b").or_else(|| lower.strip_prefix('%')) {
+        (binary, 2)
+    } else {
+        (lower.as_str(), 10)
+    };
+    if digits.is_empty() {
+        return None;
+    }
+    i64::from_str_radix(digits, radix).ok()
+}
+
+fn parse_register(text: &str) -> Option<RegisterIndex> {
+    let digit = text.strip_prefix(['V', 'v'])?;
+    if digit.len() != 1 {
+        return None;
+    }
+    RegisterIndex::try_from(u8::from_str_radix(digit, 16).ok()?).ok()
+}
+
+fn parse_operand(number: usize, span: Span) -> Result<Operand, AssembleError> {
+    if span.text.is_empty() {
+        return Err(AssembleError::new(number, span.column, "missing operand"));
+    }
+    if let Some(register) = parse_register(span.text) {
+        return Ok(Operand::Register(register));
+    }
+    if let [x, y] = span.split(|c| c == '-').as_slice()
+        && let (Some(x), Some(y)) = (parse_register(x.text), parse_register(y.text))
+    {
+        return Ok(Operand::Range(x, y));
+    }
+
+    let operand = match span.text.to_ascii_uppercase().as_str() {
+        "I" => Operand::Index,
+        "[I]" => Operand::Indirect,
+        "DT" => Operand::DelayTimer,
+        "ST" => Operand::SoundTimer,
+        "K" => Operand::Key,
+        "F" => Operand::Font,
+        "HF" => Operand::BigFont,
+        "B" => Operand::Bcd,
+        "R" => Operand::Flags,
+        _ => {
+            let (word, rest) = span.split_word();
+            if word.text.eq_ignore_ascii_case("long") {
+                Operand::Long(parse_expr(number, rest)?)
+            } else {
+                Operand::Value(parse_expr(number, span)?)
+            }
+        }
+    };
+    Ok(operand)
+}
+
+fn parse_expr(number: usize, span: Span) -> Result<Expr, AssembleError> {
+    let mut terms = Vec::new();
+    let mut sign = 1;
+    let mut expect_term = true;
+    let bytes = span.text.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let c = bytes[i] as char;
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c == '+' || c == '-' {
+            if !expect_term {
+                expect_term = true;
+                sign = 1;
+            }
+            if c == '-' {
+                sign = -sign;
+            }
+            i += 1;
+            continue;
+        }
+        if !expect_term {
+            return Err(AssembleError::new(
+                number,
+                span.column + i,
+                "expected + or - between values",
+            ));
+        }
+
+        let start = i;
+        while i < bytes.len() && !matches!(bytes[i], b'+' | b'-') && !bytes[i].is_ascii_whitespace()
+        {
+            i += 1;
+        }
+        let text = &span.text[start..i];
+        let term = if let Some(value) = parse_number(text) {
+            Term::Number(value)
+        } else if is_identifier(text) {
+            Term::Symbol(text.to_string())
+        } else {
+            return Err(AssembleError::new(
+                number,
+                span.column + start,
+                format!("invalid value '{}'", text),
+            ));
+        };
+        terms.push((sign, term, span.column + start));
+        sign = 1;
+        expect_term = false;
+    }
+
+    if expect_term {
+        return Err(AssembleError::new(
+            number,
+            span.column + span.text.len(),
+            "expected a value",
+        ));
+    }
+    Ok(Expr {
+        terms,
+        column: span.column,
+    })
+}
+
+// Each row is a run of `.` (off) and `#` (on), 8 pixels for a byte or 16 for a SUPER-CHIP
+// big sprite row of two bytes
+fn parse_sprite(number: usize, span: Span) -> Result<Vec<u8>, AssembleError> {
+    let mut bytes = Vec::new();
+    for row in span.split(|c| c == ',' || c.is_whitespace()) {
+        if row.text.is_empty() {
+            continue;
+        }
+        if !matches!(row.text.len(), 8 | 16) || !row.text.chars().all(|c| c == '.' || c == '#') {
+            return Err(AssembleError::new(
+                number,
+                row.column,
+                "sprite rows are 8 or 16 characters of '.' and '#'",
+            ));
+        }
+        let bits = row
+            .text
+            .chars()
+            .fold(0u16, |bits, c| (bits << 1) | (c == '#') as u16);
+        if row.text.len() == 16 {
+            bytes.extend_from_slice(&bits.to_be_bytes());
+        } else {
+            bytes.push(bits as u8);
+        }
+    }
+    if bytes.is_empty() {
+        return Err(AssembleError::new(
+            number,
+            span.column,
+            "expected sprite rows",
+        ));
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(source: &str) -> Vec<u8> {
+        assemble(source).unwrap().rom
+    }
+
+    fn error(source: &str) -> AssembleError {
+        assemble(source).err().expect("source should not assemble")
+    }
+
+    #[test]
+    fn every_instruction_reassembles_from_its_listing() {
+        for raw in 0..=u16::MAX {
+            let Ok(instruction) = Instruction::try_from(raw) else {
+                continue;
+            };
+            // F000 is followed by its address word, which the listing shows as an operand
+            let (source, expected) = match instruction {
+                Instruction::LoadIndexLong => (
+                    "LD I, long 0x1234".to_string(),
+                    vec![0xF0, 0x00, 0x12, 0x34],
+                ),
+                _ => (instruction.to_string(), raw.to_be_bytes().to_vec()),
+            };
+            assert_eq!(bytes(&source), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn labels_resolve_forwards_and_backwards() {
+        let assembly = assemble("start: CALL sub\n  JP start\nsub:\n  RET").unwrap();
+        assert_eq!(assembly.rom, [0x22, 0x04, 0x12, 0x00, 0x00, 0xEE]);
+        assert_eq!(assembly.labels["start"], 0x200);
+        assert_eq!(assembly.labels["sub"], 0x204);
+    }
+
+    #[test]
+    fn constants_and_sums_are_evaluated() {
+        let source = "define SPEED 2\ndefine KEY 0xA\nLD V1, SPEED + 1\nSKP V1\nLD V2, KEY";
+        assert_eq!(bytes(source), [0x61, 0x03, 0xE1, 0x9E, 0x62, 0x0A]);
+    }
+
+    #[test]
+    fn data_directives_emit_their_bytes() {
+        assert_eq!(
+            bytes("DB 1, 0x02, #03, $04, %101, 0b11"),
+            [1, 2, 3, 4, 5, 3]
+        );
+        assert_eq!(bytes("DW 0x1234, 5"), [0x12, 0x34, 0x00, 0x05]);
+        assert_eq!(bytes("SPRITE ..####.. #......#"), [0x3C, 0x81]);
+        assert_eq!(
+            bytes("SPRITE ########........ ...............#"),
+            [0xFF, 0x00, 0x00, 0x01]
+        );
+        assert_eq!(
+            bytes("CLS\nORG 0x206\nRET"),
+            [0x00, 0xE0, 0, 0, 0, 0, 0x00, 0xEE]
+        );
+    }
+
+    #[test]
+    fn source_map_records_the_line_of_each_statement() {
+        let assembly = assemble("; header\nCLS\n\nloop: JP loop\nDB 1").unwrap();
+        assert_eq!(assembly.source_map.line_for(0x200), Some(2));
+        assert_eq!(assembly.source_map.line_for(0x202), Some(4));
+        assert_eq!(assembly.source_map.line_for(0x204), Some(5));
+    }
+
+    #[test]
+    fn errors_point_at_the_line_and_column() {
+        let err = error("CLS\n  FOO V1");
+        assert_eq!((err.line, err.column), (2, 3));
+        assert!(err.message.contains("FOO"), "{}", err.message);
+
+        let err = error("LD V1, 2\n  JP nowhere");
+        assert_eq!(err.line, 2);
+        assert!(err.message.contains("nowhere"), "{}", err.message);
+
+        let err = error("x: CLS\nx: RET");
+        assert_eq!((err.line, err.column), (2, 1));
+        assert_eq!(err.to_string(), "2:1: 'x' is already defined");
+
+        assert_eq!(error("CLS\nORG 0x100").line, 2);
+    }
+}
@@ -0,0 +1,948 @@
+use std::collections::BTreeSet;
+use std::fmt::{self, Write as _};
+use std::str::FromStr;
+
+use super::assembler::SourceMap;
+use super::error::Chip8Error;
+use super::instruction::Instruction;
+use super::register::{NUM_REGISTERS, RegisterIndex};
+use super::{AUDIO_PATTERN_SIZE, Chip8, StepOutcome};
+
+// Upper bound on instructions run by a single step-over or step-out, so a subroutine
+// that never returns cannot hang the frontend
+pub const STEP_LIMIT: u32 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(&self, access: Access) -> bool {
+        matches!(
+            (self, access),
+            (WatchKind::ReadWrite, _)
+                | (WatchKind::Read, Access::Read)
+                | (WatchKind::Write, Access::Write)
+        )
+    }
+}
+
+// Stops execution when an instruction touches any address from `start` to `end` inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn holds(&self, lhs: u8, rhs: u8) -> bool {
+        match self {
+            Comparison::Equal => lhs == rhs,
+            Comparison::NotEqual => lhs != rhs,
+            Comparison::Less => lhs < rhs,
+            Comparison::LessOrEqual => lhs <= rhs,
+            Comparison::Greater => lhs > rhs,
+            Comparison::GreaterOrEqual => lhs >= rhs,
+        }
+    }
+
+    fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+        }
+    }
+}
+
+// Breaks when `V[register] <comparison> value` becomes true
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RegisterCondition {
+    pub register: RegisterIndex,
+    pub comparison: Comparison,
+    pub value: u8,
+}
+
+impl RegisterCondition {
+    fn holds(&self, chip8: &Chip8) -> bool {
+        self.comparison
+            .holds(chip8.registers().get(self.register), self.value)
+    }
+}
+
+impl fmt::Display for RegisterCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "V{:X} {} {:#04X}",
+            self.register.value(),
+            self.comparison.symbol(),
+            self.value
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopReason {
+    // The requested step, step-over or step-out finished
+    Step,
+    Breakpoint(u16),
+    Watchpoint {
+        pc: u16,
+        address: u16,
+        access: Access,
+    },
+    Condition {
+        pc: u16,
+        condition: RegisterCondition,
+    },
+    WaitingForKey,
+    Exited,
+    // A step-over or step-out ran for `STEP_LIMIT` instructions without finishing
+    StepLimit,
+    Error(Chip8Error),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Step => write!(f, "step complete"),
+            StopReason::Breakpoint(pc) => write!(f, "breakpoint at {:#05X}", pc),
+            StopReason::Watchpoint {
+                pc,
+                address,
+                access,
+            } => write!(
+                f,
+                "watchpoint: {} of {:#05X} by instruction at {:#05X}",
+                match access {
+                    Access::Read => "read",
+                    Access::Write => "write",
+                },
+                address,
+                pc
+            ),
+            StopReason::Condition { pc, condition } => {
+                write!(f, "condition {} met after {:#05X}", condition, pc)
+            }
+            StopReason::WaitingForKey => write!(f, "waiting for a key press"),
+            StopReason::Exited => write!(f, "program exited"),
+            StopReason::StepLimit => {
+                write!(f, "gave up after {} instructions", STEP_LIMIT)
+            }
+            StopReason::Error(err) => write!(f, "error: {}", err),
+        }
+    }
+}
+
+// One active subroutine call, innermost last
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackFrame {
+    // Address of the 2nnn instruction that made the call
+    pub call_site: u16,
+    // Subroutine entry point, if the call site still decodes as a call
+    pub target: Option<u16>,
+    pub return_address: u16,
+}
+
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    // Each condition with whether it held after the last instruction, so that it only
+    // breaks when it becomes true rather than on every instruction while it stays true
+    conditions: Vec<(RegisterCondition, bool)>,
+    // Set after stopping at a breakpoint so that resuming executes that instruction
+    // instead of stopping on it again
+    resume_from: Option<u16>,
+    // Maps addresses back to the assembly or Octo source the ROM was built from
+    source_map: Option<SourceMap>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            conditions: Vec::new(),
+            resume_from: None,
+            source_map: None,
+        }
+    }
+
+    pub fn set_source_map(&mut self, source_map: SourceMap) {
+        self.source_map = Some(source_map);
+    }
+
+    // `format_location`, followed by the source line of the PC when a source map is loaded
+    pub fn location(&self, chip8: &Chip8) -> String {
+        let location = format_location(chip8);
+        let line = self
+            .source_map
+            .as_ref()
+            .and_then(|map| map.line_for(chip8.program_counter()));
+        match line {
+            Some(line) => format!("{}  (line {})\n", location.trim_end(), line),
+            None => location,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    // Removes every watchpoint covering `address`
+    pub fn remove_watchpoint(&mut self, address: u16) -> bool {
+        let before = self.watchpoints.len();
+        self.watchpoints
+            .retain(|watch| !(watch.start..=watch.end).contains(&address));
+        self.watchpoints.len() != before
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn add_condition(&mut self, chip8: &Chip8, condition: RegisterCondition) {
+        self.conditions.push((condition, condition.holds(chip8)));
+    }
+
+    pub fn remove_condition(&mut self, index: usize) -> Option<RegisterCondition> {
+        (index < self.conditions.len()).then(|| self.conditions.remove(index).0)
+    }
+
+    pub fn conditions(&self) -> impl Iterator<Item = &RegisterCondition> + '_ {
+        self.conditions.iter().map(|(condition, _)| condition)
+    }
+
+    // Executes a single instruction. Stepping is not tied to frames, so a sprite draw
+    // waiting for the display is let through at once.
+    pub fn step(&mut self, chip8: &mut Chip8) -> StopReason {
+        self.resume_from = None;
+        chip8.vblank();
+        self.execute(chip8).unwrap_or(StopReason::Step)
+    }
+
+    // Executes one instruction, running a whole subroutine if that instruction is a call
+    pub fn step_over(&mut self, chip8: &mut Chip8) -> StopReason {
+        let depth = chip8.stack().len();
+        if !matches!(chip8.peek_instruction(), Ok(Instruction::Call(_))) {
+            return self.step(chip8);
+        }
+        self.run_while(chip8, |chip8| chip8.stack().len() > depth)
+    }
+
+    // Runs until the current subroutine returns to its caller
+    pub fn step_out(&mut self, chip8: &mut Chip8) -> StopReason {
+        let depth = chip8.stack().len();
+        if depth == 0 {
+            return self.step(chip8);
+        }
+        self.run_while(chip8, |chip8| chip8.stack().len() >= depth)
+    }
+
+    // Runs what is left of the frame started by `Chip8::begin_frame`, stopping early at
+    // breakpoints, watchpoints and conditions. Returns None if the frame ran to its end.
+    pub fn run(&mut self, chip8: &mut Chip8) -> Option<StopReason> {
+        while chip8.frame_has_time() {
+            if let Some(reason) = self.check_breakpoint(chip8) {
+                return Some(reason);
+            }
+            if let Some(reason) = chip8.charged(|chip8| self.execute(chip8)) {
+                return Some(reason);
+            }
+        }
+        None
+    }
+
+    // The active subroutine calls, outermost first
+    pub fn call_stack(&self, chip8: &Chip8) -> Vec<StackFrame> {
+        chip8
+            .stack()
+            .iter()
+            .map(|&return_address| {
+                let call_site = return_address.wrapping_sub(2);
+                let target = match instruction_at(chip8, call_site) {
+                    Some(Instruction::Call(target)) => Some(target),
+                    _ => None,
+                };
+                StackFrame {
+                    call_site,
+                    target,
+                    return_address,
+                }
+            })
+            .collect()
+    }
+
+    // Steps until `keep_going` returns false, the step limit is hit or something stops execution
+    fn run_while(&mut self, chip8: &mut Chip8, keep_going: impl Fn(&Chip8) -> bool) -> StopReason {
+        self.resume_from = None;
+        chip8.vblank();
+        if let Some(reason) = self.execute(chip8) {
+            return reason;
+        }
+        for _ in 0..STEP_LIMIT {
+            if !keep_going(chip8) {
+                return StopReason::Step;
+            }
+            if let Some(reason) = self.check_breakpoint(chip8) {
+                return reason;
+            }
+            chip8.vblank();
+            if let Some(reason) = self.execute(chip8) {
+                return reason;
+            }
+        }
+        StopReason::StepLimit
+    }
+
+    fn check_breakpoint(&mut self, chip8: &Chip8) -> Option<StopReason> {
+        let pc = chip8.program_counter();
+        if self.resume_from.take() != Some(pc) && self.breakpoints.contains(&pc) {
+            self.resume_from = Some(pc);
+            return Some(StopReason::Breakpoint(pc));
+        }
+        None
+    }
+
+    // Executes one instruction and reports anything that should stop execution
+    fn execute(&mut self, chip8: &mut Chip8) -> Option<StopReason> {
+        let pc = chip8.program_counter();
+        let accesses = match chip8.peek_instruction() {
+            Ok(instruction) => memory_accesses(chip8, &instruction),
+            Err(_) => Vec::new(),
+        };
+
+        match chip8.emulate_cycle() {
+            Ok(StepOutcome::WaitingForKey) => return Some(StopReason::WaitingForKey),
+            Ok(StepOutcome::Exited) => return Some(StopReason::Exited),
+            // The draw has not happened yet, so neither have its reads
+            Ok(StepOutcome::WaitingForDisplay) => return None,
+            Ok(StepOutcome::Executed) => {}
+            Err(err) => return Some(StopReason::Error(err)),
+        }
+
+        for (address, access) in accesses {
+            let hit = self.watchpoints.iter().any(|watch| {
+                (watch.start..=watch.end).contains(&address) && watch.kind.matches(access)
+            });
+            if hit {
+                return Some(StopReason::Watchpoint {
+                    pc,
+                    address,
+                    access,
+                });
+            }
+        }
+
+        let mut triggered = None;
+        for (condition, held) in self.conditions.iter_mut() {
+            let holds = condition.holds(chip8);
+            if holds && !*held && triggered.is_none() {
+                triggered = Some(StopReason::Condition {
+                    pc,
+                    condition: *condition,
+                });
+            }
+            *held = holds;
+        }
+        triggered
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn instruction_at(chip8: &Chip8, address: u16) -> Option<Instruction> {
+    let memory = chip8.memory();
+    let high = *memory.get(address as usize)? as u16;
+    let low = *memory.get(address as usize + 1)? as u16;
+    Instruction::try_from((high << 8) | low).ok()
+}
+
+// Data memory the instruction will touch when executed in the current machine state.
+// Instruction fetches are not included; use breakpoints for those.
+fn memory_accesses(chip8: &Chip8, instruction: &Instruction) -> Vec<(u16, Access)> {
+    let i = chip8.index_register();
+    let range = |len: u16, access: Access| {
+        (0..len)
+            .map(|offset| (i.wrapping_add(offset), access))
+            .collect()
+    };
+
+    match *instruction {
+        Instruction::Display(_, _, n) => {
+            let bytes_per_plane = match n.value() {
+                0 => 32,
+                n => n as u16,
+            };
+            let planes = chip8.display.selected().count() as u16;
+            range(bytes_per_plane * planes, Access::Read)
+        }
+        Instruction::LoadBinaryCodedDecimal(_) => range(3, Access::Write),
+        Instruction::StoreRegisters(x) => range(x.value() as u16 + 1, Access::Write),
+        Instruction::LoadRegisters(x) => range(x.value() as u16 + 1, Access::Read),
+        Instruction::StoreRegisterRange(x, y) => {
+            range(x.value().abs_diff(y.value()) as u16 + 1, Access::Write)
+        }
+        Instruction::LoadRegisterRange(x, y) => {
+            range(x.value().abs_diff(y.value()) as u16 + 1, Access::Read)
+        }
+        Instruction::LoadAudioPattern => range(AUDIO_PATTERN_SIZE as u16, Access::Read),
+        _ => Vec::new(),
+    }
+}
+
+// A debugger command as typed at the REPL. Addresses and values are hexadecimal.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Step(u32),
+    Next,
+    Finish,
+    Continue,
+    Break(u16),
+    Delete(u16),
+    Watch(Watchpoint),
+    Unwatch(u16),
+    Condition(RegisterCondition),
+    Uncondition(usize),
+    List,
+    Registers,
+    Backtrace,
+    Memory(u16, u16),
+    Screen,
+    Press(u8),
+    Release(u8),
+    Help,
+    Quit,
+}
+
+pub const HELP: &str = "\
+step [n]              execute n instructions (default 1)      (s)
+next                  step over subroutine calls              (n)
+finish                run until the current subroutine returns
+continue              resume execution                        (c)
+break <addr>          set a breakpoint                        (b)
+delete <addr>         remove a breakpoint                     (d)
+watch <addr> [len] [r|w|rw]
+                      stop when memory is read or written     (w)
+unwatch <addr>        remove watchpoints covering addr
+cond V<x> <op> <val>  break when a register comparison becomes true
+uncond <n>            remove condition n
+list                  show breakpoints, watchpoints and conditions
+regs                  show registers                          (r)
+bt                    show the call stack
+mem <addr> [len]      dump memory                             (x)
+screen                show the display as text
+press <key>           hold down a keypad key
+release <key>         let go of a keypad key
+help                  show this text
+quit                  exit                                    (q)";
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&name, args)) = words.split_first() else {
+            return Err("empty command".to_string());
+        };
+
+        let command = match (name, args) {
+            ("s" | "step", []) => Command::Step(1),
+            ("s" | "step", [count]) => Command::Step(
+                count
+                    .parse()
+                    .map_err(|_| format!("invalid step count '{}'", count))?,
+            ),
+            ("n" | "next", []) => Command::Next,
+            ("finish", []) => Command::Finish,
+            ("c" | "continue", []) => Command::Continue,
+            ("b" | "break", [address]) => Command::Break(parse_hex(address)?),
+            ("d" | "delete", [address]) => Command::Delete(parse_hex(address)?),
+            ("w" | "watch", [address, rest @ ..]) if rest.len() <= 2 => {
+                let start = parse_hex(address)?;
+                let mut len = 1;
+                let mut kind = WatchKind::Write;
+                for arg in rest {
+                    match *arg {
+                        "r" => kind = WatchKind::Read,
+                        "w" => kind = WatchKind::Write,
+                        "rw" => kind = WatchKind::ReadWrite,
+                        _ => len = parse_hex(arg)?.max(1),
+                    }
+                }
+                Command::Watch(Watchpoint {
+                    start,
+                    end: start.saturating_add(len - 1),
+                    kind,
+                })
+            }
+            ("unwatch", [address]) => Command::Unwatch(parse_hex(address)?),
+            ("cond", [register, op, value]) => Command::Condition(RegisterCondition {
+                register: parse_register(register)?,
+                comparison: match *op {
+                    "==" => Comparison::Equal,
+                    "!=" => Comparison::NotEqual,
+                    "<" => Comparison::Less,
+                    "<=" => Comparison::LessOrEqual,
+                    ">" => Comparison::Greater,
+                    ">=" => Comparison::GreaterOrEqual,
+                    _ => return Err(format!("unknown comparison '{}'", op)),
+                },
+                value: u8::try_from(parse_hex(value)?)
+                    .map_err(|_| format!("value '{}' does not fit in a byte", value))?,
+            }),
+            ("uncond", [index]) => Command::Uncondition(
+                index
+                    .parse()
+                    .map_err(|_| format!("invalid condition number '{}'", index))?,
+            ),
+            ("list", []) => Command::List,
+            ("r" | "regs", []) => Command::Registers,
+            ("bt" | "backtrace", []) => Command::Backtrace,
+            ("x" | "mem", [address]) => Command::Memory(parse_hex(address)?, 0x10),
+            ("x" | "mem", [address, len]) => Command::Memory(parse_hex(address)?, parse_hex(len)?),
+            ("screen", []) => Command::Screen,
+            ("press", [key]) => Command::Press(parse_key(key)?),
+            ("release", [key]) => Command::Release(parse_key(key)?),
+            ("h" | "help" | "?", []) => Command::Help,
+            ("q" | "quit", []) => Command::Quit,
+            _ => {
+                return Err(format!(
+                    "unrecognised command '{}' (try 'help')",
+                    line.trim()
+                ));
+            }
+        };
+
+        Ok(command)
+    }
+}
+
+fn parse_hex(text: &str) -> Result<u16, String> {
+    let digits = text.trim_start_matches("0x").trim_start_matches('#');
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid hex number '{}'", text))
+}
+
+fn parse_key(text: &str) -> Result<u8, String> {
+    u8::from_str_radix(text, 16)
+        .ok()
+        .filter(|key| *key < 16)
+        .ok_or_else(|| format!("invalid key '{}'", text))
+}
+
+fn parse_register(text: &str) -> Result<RegisterIndex, String> {
+    let index = text
+        .strip_prefix(['V', 'v'])
+        .and_then(|digit| u8::from_str_radix(digit, 16).ok())
+        .ok_or_else(|| format!("invalid register '{}'", text))?;
+    RegisterIndex::try_from(index).map_err(|_| format!("invalid register '{}'", text))
+}
+
+impl Debugger {
+    // Runs a command and returns the text to show. `Continue` and `Quit` change how the
+    // frontend drives the machine, so they are left to the caller and produce no output.
+    pub fn execute_command(&mut self, chip8: &mut Chip8, command: &Command) -> String {
+        let mut out = String::new();
+
+        match command {
+            Command::Step(count) => {
+                let mut reason = StopReason::Step;
+                for _ in 0..*count {
+                    reason = self.step(chip8);
+                    if reason != StopReason::Step {
+                        break;
+                    }
+                }
+                writeln!(out, "{}", reason).unwrap();
+                out.push_str(&self.location(chip8));
+            }
+            Command::Next => {
+                writeln!(out, "{}", self.step_over(chip8)).unwrap();
+                out.push_str(&self.location(chip8));
+            }
+            Command::Finish => {
+                writeln!(out, "{}", self.step_out(chip8)).unwrap();
+                out.push_str(&self.location(chip8));
+            }
+            Command::Break(address) => {
+                self.add_breakpoint(*address);
+                writeln!(out, "breakpoint at {:#05X}", address).unwrap();
+            }
+            Command::Delete(address) => {
+                if self.remove_breakpoint(*address) {
+                    writeln!(out, "removed breakpoint at {:#05X}", address).unwrap();
+                } else {
+                    writeln!(out, "no breakpoint at {:#05X}", address).unwrap();
+                }
+            }
+            Command::Watch(watch) => {
+                self.add_watchpoint(*watch);
+                writeln!(out, "watching {}", format_watchpoint(watch)).unwrap();
+            }
+            Command::Unwatch(address) => {
+                if self.remove_watchpoint(*address) {
+                    writeln!(out, "removed watchpoints covering {:#05X}", address).unwrap();
+                } else {
+                    writeln!(out, "no watchpoint covers {:#05X}", address).unwrap();
+                }
+            }
+            Command::Condition(condition) => {
+                self.add_condition(chip8, *condition);
+                writeln!(out, "break when {}", condition).unwrap();
+            }
+            Command::Uncondition(index) => match self.remove_condition(*index) {
+                Some(condition) => writeln!(out, "removed condition {}", condition).unwrap(),
+                None => writeln!(out, "no condition {}", index).unwrap(),
+            },
+            Command::List => {
+                for address in self.breakpoints() {
+                    writeln!(out, "break {:#05X}", address).unwrap();
+                }
+                for watch in &self.watchpoints {
+                    writeln!(out, "watch {}", format_watchpoint(watch)).unwrap();
+                }
+                for (index, condition) in self.conditions().enumerate() {
+                    writeln!(out, "cond {}: {}", index, condition).unwrap();
+                }
+            }
+            Command::Registers => out.push_str(&format_registers(chip8)),
+            Command::Backtrace => {
+                let frames = self.call_stack(chip8);
+                if frames.is_empty() {
+                    writeln!(out, "(top level)").unwrap();
+                }
+                for (depth, frame) in frames.iter().enumerate().rev() {
+                    let target = frame
+                        .target
+                        .map_or("?".to_string(), |target| format!("{:#05X}", target));
+                    writeln!(
+                        out,
+                        "#{} {} called from {:#05X}, returns to {:#05X}",
+                        depth, target, frame.call_site, frame.return_address
+                    )
+                    .unwrap();
+                }
+            }
+            Command::Memory(address, len) => {
+                let memory = chip8.memory();
+                let start = *address as usize;
+                let end = (start + *len as usize).min(memory.len());
+                for (row, chunk) in memory[start.min(end)..end].chunks(16).enumerate() {
+                    let bytes: Vec<String> =
+                        chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+                    writeln!(out, "{:04X}: {}", start + row * 16, bytes.join(" ")).unwrap();
+                }
+            }
+            Command::Screen => {
+                for y in 0..chip8.display.height() {
+                    let row: String = (0..chip8.display.width())
+                        .map(|x| match chip8.display.pixel_color(x, y) {
+                            0 => '.',
+                            1 => '#',
+                            2 => '+',
+                            _ => '@',
+                        })
+                        .collect();
+                    writeln!(out, "{}", row).unwrap();
+                }
+            }
+            Command::Press(key) => {
+                if chip8.is_waiting_for_key() {
+                    chip8.resolve_key_wait(*key);
+                } else {
+                    chip8.keypad.set_key_pressed(*key, true);
+                }
+                writeln!(out, "key {:X} down", key).unwrap();
+            }
+            Command::Release(key) => {
+                if !chip8.is_waiting_for_key() && chip8.is_waiting_for_release() {
+                    chip8.resolve_key_release();
+                }
+                chip8.keypad.set_key_pressed(*key, false);
+                writeln!(out, "key {:X} up", key).unwrap();
+            }
+            Command::Help => writeln!(out, "{}", HELP).unwrap(),
+            Command::Continue | Command::Quit => {}
+        }
+
+        out
+    }
+}
+
+pub fn format_registers(chip8: &Chip8) -> String {
+    let mut out = String::new();
+    for (row, values) in chip8
+        .registers()
+        .values()
+        .chunks(NUM_REGISTERS / 2)
+        .enumerate()
+    {
+        let registers: Vec<String> = values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| format!("V{:X}={:02X}", row * NUM_REGISTERS / 2 + i, value))
+            .collect();
+        writeln!(out, "{}", registers.join(" ")).unwrap();
+    }
+    writeln!(
+        out,
+        "PC={:04X} I={:04X} SP={:X} DT={:02X} ST={:02X}",
+        chip8.program_counter(),
+        chip8.index_register(),
+        chip8.stack().len(),
+        chip8.delay_timer(),
+        chip8.sound_timer()
+    )
+    .unwrap();
+    out
+}
+
+// The PC and the instruction about to execute
+pub fn format_location(chip8: &Chip8) -> String {
+    match chip8.peek_instruction() {
+        Ok(instruction) => format!("{:04X}: {}\n", chip8.program_counter(), instruction),
+        Err(err) => format!("{:04X}: {}\n", chip8.program_counter(), err),
+    }
+}
+
+fn format_watchpoint(watch: &Watchpoint) -> String {
+    let kind = match watch.kind {
+        WatchKind::Read => "reads",
+        WatchKind::Write => "writes",
+        WatchKind::ReadWrite => "accesses",
+    };
+    if watch.start == watch.end {
+        format!("{} of {:#05X}", kind, watch.start)
+    } else {
+        format!("{} of {:#05X}-{:#05X}", kind, watch.start, watch.end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::KeyState;
+    use crate::chip8::quirks::Quirks;
+    use crate::chip8::rom::Rom;
+
+    fn chip8_with(program: &[u16]) -> Chip8 {
+        let data = program.iter().flat_map(|word| word.to_be_bytes()).collect();
+        let mut chip8 = Chip8::default();
+        chip8.load_rom(&Rom::new(data)).unwrap();
+        chip8.begin_frame(&KeyState::new());
+        chip8
+    }
+
+    fn register(chip8: &Chip8, n: u8) -> u8 {
+        chip8.registers().get(RegisterIndex::try_from(n).unwrap())
+    }
+
+    #[test]
+    fn breakpoints_stop_before_the_instruction_and_resume_past_it() {
+        let mut chip8 = chip8_with(&[0x6000, 0x7001, 0x7001, 0x1202]);
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x204);
+
+        assert_eq!(
+            debugger.run(&mut chip8),
+            Some(StopReason::Breakpoint(0x204))
+        );
+        assert_eq!(register(&chip8, 0), 1);
+        assert_eq!(
+            debugger.run(&mut chip8),
+            Some(StopReason::Breakpoint(0x204))
+        );
+        assert_eq!(register(&chip8, 0), 3);
+
+        assert!(debugger.remove_breakpoint(0x204));
+        assert_eq!(debugger.run(&mut chip8), None);
+    }
+
+    #[test]
+    fn watchpoints_tell_reads_from_writes() {
+        // I = 0x300, store the BCD of V0 there, then load it back
+        let program = [0xA300, 0x607B, 0xF033, 0xF265, 0x1208];
+        let watch = |start, end, kind| Watchpoint { start, end, kind };
+
+        let mut chip8 = chip8_with(&program);
+        let mut debugger = Debugger::new();
+        debugger.add_watchpoint(watch(0x301, 0x301, WatchKind::Write));
+        assert_eq!(
+            debugger.run(&mut chip8),
+            Some(StopReason::Watchpoint {
+                pc: 0x204,
+                address: 0x301,
+                access: Access::Write,
+            })
+        );
+        assert_eq!(chip8.memory()[0x300..0x303], [1, 2, 3]);
+
+        let mut chip8 = chip8_with(&program);
+        let mut debugger = Debugger::new();
+        debugger.add_watchpoint(watch(0x302, 0x310, WatchKind::Read));
+        assert_eq!(
+            debugger.run(&mut chip8),
+            Some(StopReason::Watchpoint {
+                pc: 0x206,
+                address: 0x302,
+                access: Access::Read,
+            })
+        );
+
+        // Only the jump is left, which touches no data
+        assert!(debugger.remove_watchpoint(0x305));
+        debugger.add_watchpoint(watch(0x300, 0x302, WatchKind::ReadWrite));
+        assert_eq!(debugger.run(&mut chip8), None);
+    }
+
+    #[test]
+    fn conditions_break_when_they_become_true() {
+        let mut chip8 = chip8_with(&[0x7001, 0x1200]);
+        let mut debugger = Debugger::new();
+        let condition = |comparison, value| RegisterCondition {
+            register: RegisterIndex::try_from(0).unwrap(),
+            comparison,
+            value,
+        };
+        // Already true, so it never breaks
+        debugger.add_condition(&chip8, condition(Comparison::Less, 0x80));
+        debugger.add_condition(&chip8, condition(Comparison::Greater, 2));
+
+        assert_eq!(
+            debugger.run(&mut chip8),
+            Some(StopReason::Condition {
+                pc: 0x200,
+                condition: condition(Comparison::Greater, 2),
+            })
+        );
+        assert_eq!(register(&chip8, 0), 3);
+        // Still true, but it has not become true again
+        assert_eq!(debugger.run(&mut chip8), None);
+    }
+
+    #[test]
+    fn stepping_over_and_out_of_calls() {
+        // 0x200 calls the subroutine at 0x206, which sets V2 and returns
+        let program = [0x2206, 0x6101, 0x1204, 0x6205, 0x6306, 0x00EE];
+
+        let mut chip8 = chip8_with(&program);
+        let mut debugger = Debugger::new();
+        assert_eq!(debugger.step_over(&mut chip8), StopReason::Step);
+        assert_eq!(chip8.program_counter(), 0x202);
+        assert_eq!((register(&chip8, 2), register(&chip8, 3)), (5, 6));
+        assert_eq!(debugger.step_over(&mut chip8), StopReason::Step);
+        assert_eq!(chip8.program_counter(), 0x204);
+
+        let mut chip8 = chip8_with(&program);
+        assert_eq!(debugger.step(&mut chip8), StopReason::Step);
+        assert_eq!(
+            debugger.call_stack(&chip8),
+            [StackFrame {
+                call_site: 0x200,
+                target: Some(0x206),
+                return_address: 0x202,
+            }]
+        );
+        assert_eq!(debugger.step(&mut chip8), StopReason::Step);
+        assert_eq!(debugger.step_out(&mut chip8), StopReason::Step);
+        assert_eq!(chip8.program_counter(), 0x202);
+        assert!(debugger.call_stack(&chip8).is_empty());
+
+        // Breakpoints inside the subroutine still stop a step over
+        let mut chip8 = chip8_with(&program);
+        debugger.add_breakpoint(0x208);
+        assert_eq!(
+            debugger.step_over(&mut chip8),
+            StopReason::Breakpoint(0x208)
+        );
+    }
+
+    #[test]
+    fn step_over_gives_up_on_calls_that_never_return() {
+        let mut chip8 = chip8_with(&[0x2204, 0x0000, 0x1204]);
+        let mut debugger = Debugger::new();
+        assert_eq!(debugger.step_over(&mut chip8), StopReason::StepLimit);
+        assert_eq!(chip8.program_counter(), 0x204);
+    }
+
+    #[test]
+    fn stepping_does_not_run_past_a_key_wait() {
+        let mut chip8 = Chip8::default();
+        chip8
+            .load_rom(&Rom::new(vec![0xF0, 0x0A, 0x61, 0x01, 0x12, 0x04]))
+            .unwrap();
+        let mut debugger = Debugger::new();
+        assert_eq!(debugger.step(&mut chip8), StopReason::WaitingForKey);
+        assert_eq!(debugger.step(&mut chip8), StopReason::WaitingForKey);
+        assert_eq!(debugger.step_over(&mut chip8), StopReason::WaitingForKey);
+        assert_eq!(chip8.program_counter(), 0x202);
+        assert!(chip8.is_waiting_for_key());
+    }
+
+    #[test]
+    fn draws_waiting_for_the_display_do_not_trigger_watchpoints() {
+        let mut chip8 = Chip8::new(Quirks::COSMAC_VIP);
+        chip8
+            .load_rom(&Rom::new(vec![0xD0, 0x01, 0x12, 0x02]))
+            .unwrap();
+        let mut debugger = Debugger::new();
+        debugger.add_watchpoint(Watchpoint {
+            start: 0x000,
+            end: 0x000,
+            kind: WatchKind::Read,
+        });
+
+        chip8.begin_frame(&KeyState::new());
+        assert_eq!(debugger.run(&mut chip8), None);
+        chip8.end_frame();
+        chip8.begin_frame(&KeyState::new());
+        assert_eq!(
+            debugger.run(&mut chip8),
+            Some(StopReason::Watchpoint {
+                pc: 0x200,
+                address: 0x000,
+                access: Access::Read,
+            })
+        );
+    }
+}
@@ -0,0 +1,526 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+
+use super::instruction::Instruction;
+use super::register::RegisterIndex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    // The mnemonics from Cowgod's Chip-8 Technical Reference (`LD Vx, byte`)
+    Cowgod,
+    // Octo's high-level assembly (`vx := byte`)
+    Octo,
+}
+
+// Why an address got a label; the strongest reason wins when several apply
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LabelKind {
+    // Loaded into I, so most likely sprite or other data
+    Data,
+    Jump,
+    Subroutine,
+    Entry,
+}
+
+impl LabelKind {
+    fn prefix(&self) -> &'static str {
+        match self {
+            LabelKind::Data => "data",
+            LabelKind::Jump => "label",
+            LabelKind::Subroutine => "sub",
+            LabelKind::Entry => "main",
+        }
+    }
+}
+
+// Result of following control flow through a ROM image
+pub struct Analysis {
+    pub origin: u16,
+    // Addresses at which a reachable instruction starts
+    pub code: BTreeSet<u16>,
+    pub labels: BTreeMap<u16, LabelKind>,
+}
+
+impl Analysis {
+    pub fn label_name(&self, address: u16) -> Option<String> {
+        self.labels.get(&address).map(|kind| match kind {
+            LabelKind::Entry => kind.prefix().to_string(),
+            _ => format!("{}_{:03x}", kind.prefix(), address),
+        })
+    }
+
+    fn is_code(&self, address: u16) -> bool {
+        self.code.contains(&address)
+    }
+}
+
+// Decodes the instruction at `address` in an image loaded at `origin`, along with the
+// operand word of a four-byte F000 nnnn
+pub(super) fn decode_at(
+    image: &[u8],
+    origin: u16,
+    address: u16,
+) -> Option<(Instruction, Option<u16>)> {
+    let word = |address: u16| -> Option<u16> {
+        let offset = address.checked_sub(origin)? as usize;
+        let bytes = image.get(offset..offset + 2)?;
+        Some(((bytes[0] as u16) << 8) | bytes[1] as u16)
+    };
+
+    let instruction = Instruction::try_from(word(address)?).ok()?;
+    match instruction {
+        Instruction::LoadIndexLong => Some((instruction, Some(word(address.wrapping_add(2))?))),
+        _ => Some((instruction, None)),
+    }
+}
+
+pub(super) fn instruction_size(instruction: &Instruction) -> u16 {
+    match instruction {
+        Instruction::LoadIndexLong => 4,
+        _ => 2,
+    }
+}
+
+// Follows every path from the entry point: jumps, calls, both outcomes of each skip and
+// the first entry of Bnnn jump tables. Anything never reached is treated as data.
+pub fn analyze(image: &[u8], origin: u16) -> Analysis {
+    let mut analysis = Analysis {
+        origin,
+        code: BTreeSet::new(),
+        labels: BTreeMap::new(),
+    };
+    let add_label = |labels: &mut BTreeMap<u16, LabelKind>, address: u16, kind: LabelKind| {
+        let entry = labels.entry(address).or_insert(kind);
+        *entry = (*entry).max(kind);
+    };
+
+    add_label(&mut analysis.labels, origin, LabelKind::Entry);
+    let mut pending = vec![origin];
+
+    while let Some(address) = pending.pop() {
+        if analysis.code.contains(&address) {
+            continue;
+        }
+        let Some((instruction, long_operand)) = decode_at(image, origin, address) else {
+            continue;
+        };
+        analysis.code.insert(address);
+
+        let next = address.wrapping_add(instruction_size(&instruction));
+        match instruction {
+            Instruction::Jump(target) => {
+                add_label(&mut analysis.labels, target, LabelKind::Jump);
+                pending.push(target);
+            }
+            Instruction::JumpWithOffset(target) => {
+                add_label(&mut analysis.labels, target, LabelKind::Jump);
+                pending.push(target);
+            }
+            Instruction::Call(target) => {
+                add_label(&mut analysis.labels, target, LabelKind::Subroutine);
+                pending.push(target);
+                pending.push(next);
+            }
+            Instruction::Return | Instruction::Exit => {}
+            Instruction::SkipEqual(..)
+            | Instruction::SkipNotEqual(..)
+            | Instruction::SkipEqualRegister(..)
+            | Instruction::SkipNotEqualRegister(..)
+            | Instruction::SkipKeyPress(_)
+            | Instruction::SkipKeyNotPress(_) => {
+                pending.push(next);
+                let skipped = decode_at(image, origin, next)
+                    .map_or(2, |(instruction, _)| instruction_size(&instruction));
+                pending.push(next.wrapping_add(skipped));
+            }
+            Instruction::LoadIndexRegister(target) => {
+                add_label(&mut analysis.labels, target, LabelKind::Data);
+                pending.push(next);
+            }
+            Instruction::LoadIndexLong => {
+                if let Some(target) = long_operand {
+                    add_label(&mut analysis.labels, target, LabelKind::Data);
+                }
+                pending.push(next);
+            }
+            _ => pending.push(next),
+        }
+    }
+
+    // Only label addresses inside the image; references to the font or other RAM stay numeric
+    let end = origin as usize + image.len();
+    analysis
+        .labels
+        .retain(|&address, _| (origin as usize..end).contains(&(address as usize)));
+
+    analysis
+}
+
+// Formats one instruction. `address` renders addresses, which lets callers substitute
+// labels; `long_operand` is the second word of F000 nnnn.
+pub fn format_instruction(
+    instruction: &Instruction,
+    syntax: Syntax,
+    long_operand: Option<u16>,
+    address: &dyn Fn(u16) -> String,
+) -> String {
+    match syntax {
+        Syntax::Cowgod => format_cowgod(instruction, long_operand, address),
+        Syntax::Octo => format_octo(instruction, long_operand, address),
+    }
+}
+
+pub fn hex_address(address: u16) -> String {
+    format!("{:#05X}", address)
+}
+
+fn format_cowgod(
+    instruction: &Instruction,
+    long_operand: Option<u16>,
+    address: &dyn Fn(u16) -> String,
+) -> String {
+    let v = |x: &RegisterIndex| format!("V{:X}", x.value());
+    let byte = |kk: &u8| format!("{:#04X}", kk);
+
+    match instruction {
+        Instruction::ScrollDown(n) => format!("SCD {}", n.value()),
+        Instruction::ScrollUp(n) => format!("SCU {}", n.value()),
+        Instruction::Clear => "CLS".to_string(),
+        Instruction::Return => "RET".to_string(),
+        Instruction::ScrollRight => "SCR".to_string(),
+        Instruction::ScrollLeft => "SCL".to_string(),
+        Instruction::Exit => "EXIT".to_string(),
+        Instruction::LowResolution => "LOW".to_string(),
+        Instruction::HighResolution => "HIGH".to_string(),
+        Instruction::Jump(nnn) => format!("JP {}", address(*nnn)),
+        Instruction::Call(nnn) => format!("CALL {}", address(*nnn)),
+        Instruction::SkipEqual(x, kk) => format!("SE {}, {}", v(x), byte(kk)),
+        Instruction::SkipNotEqual(x, kk) => format!("SNE {}, {}", v(x), byte(kk)),
+        Instruction::SkipEqualRegister(x, y) => format!("SE {}, {}", v(x), v(y)),
+        Instruction::StoreRegisterRange(x, y) => format!("LD [I], {}-{}", v(x), v(y)),
+        Instruction::LoadRegisterRange(x, y) => format!("LD {}-{}, [I]", v(x), v(y)),
+        Instruction::Load(x, kk) => format!("LD {}, {}", v(x), byte(kk)),
+        Instruction::Add(x, kk) => format!("ADD {}, {}", v(x), byte(kk)),
+        Instruction::LoadRegister(x, y) => format!("LD {}, {}", v(x), v(y)),
+        Instruction::LoadOr(x, y) => format!("OR {}, {}", v(x), v(y)),
+        Instruction::LoadAnd(x, y) => format!("AND {}, {}", v(x), v(y)),
+        Instruction::LoadXor(x, y) => format!("XOR {}, {}", v(x), v(y)),
+        Instruction::LoadAdd(x, y) => format!("ADD {}, {}", v(x), v(y)),
+        Instruction::LoadSub(x, y) => format!("SUB {}, {}", v(x), v(y)),
+        Instruction::LoadShiftRight(x, y) => format!("SHR {}, {}", v(x), v(y)),
+        Instruction::LoadSubNegative(x, y) => format!("SUBN {}, {}", v(x), v(y)),
+        Instruction::LoadShiftLeft(x, y) => format!("SHL {}, {}", v(x), v(y)),
+        Instruction::SkipNotEqualRegister(x, y) => format!("SNE {}, {}", v(x), v(y)),
+        Instruction::LoadIndexRegister(nnn) => format!("LD I, {}", address(*nnn)),
+        Instruction::JumpWithOffset(nnn) => format!("JP V0, {}", address(*nnn)),
+        Instruction::Random(x, kk) => format!("RND {}, {}", v(x), byte(kk)),
+        Instruction::Display(x, y, n) => format!("DRW {}, {}, {}", v(x), v(y), n.value()),
+        Instruction::SkipKeyPress(x) => format!("SKP {}", v(x)),
+        Instruction::SkipKeyNotPress(x) => format!("SKNP {}", v(x)),
+        Instruction::LoadIndexLong => match long_operand {
+            Some(nnnn) => format!("LD I, long {}", address(nnnn)),
+            None => "LD I, long".to_string(),
+        },
+        Instruction::SelectPlanes(n) => format!("PLANE {}", n.value()),
+        Instruction::LoadAudioPattern => "AUDIO".to_string(),
+        Instruction::LoadDelayTimer(x) => format!("LD {}, DT", v(x)),
+        Instruction::LoadKeyPress(x) => format!("LD {}, K", v(x)),
+        Instruction::StoreDelayTimer(x) => format!("LD DT, {}", v(x)),
+        Instruction::StoreSoundTimer(x) => format!("LD ST, {}", v(x)),
+        Instruction::AddIndexRegister(x) => format!("ADD I, {}", v(x)),
+        Instruction::LoadFontCharacter(x) => format!("LD F, {}", v(x)),
+        Instruction::LoadBigFontCharacter(x) => format!("LD HF, {}", v(x)),
+        Instruction::LoadBinaryCodedDecimal(x) => format!("LD B, {}", v(x)),
+        Instruction::LoadPitch(x) => format!("PITCH {}", v(x)),
+        Instruction::StoreRegisters(x) => format!("LD [I], {}", v(x)),
+        Instruction::LoadRegisters(x) => format!("LD {}, [I]", v(x)),
+        Instruction::StoreFlags(x) => format!("LD R, {}", v(x)),
+        Instruction::LoadFlags(x) => format!("LD {}, R", v(x)),
+    }
+}
+
+fn format_octo(
+    instruction: &Instruction,
+    long_operand: Option<u16>,
+    address: &dyn Fn(u16) -> String,
+) -> String {
+    let v = |x: &RegisterIndex| format!("v{:x}", x.value());
+    let byte = |kk: &u8| format!("{:#04x}", kk);
+
+    // Octo's `if ... then` runs the next instruction when the condition holds, which is
+    // the opposite of the skip condition
+    match instruction {
+        Instruction::ScrollDown(n) => format!("scroll-down {}", n.value()),
+        Instruction::ScrollUp(n) => format!("scroll-up {}", n.value()),
+        Instruction::Clear => "clear".to_string(),
+        Instruction::Return => "return".to_string(),
+        Instruction::ScrollRight => "scroll-right".to_string(),
+        Instruction::ScrollLeft => "scroll-left".to_string(),
+        Instruction::Exit => "exit".to_string(),
+        Instruction::LowResolution => "lores".to_string(),
+        Instruction::HighResolution => "hires".to_string(),
+        Instruction::Jump(nnn) => format!("jump {}", address(*nnn)),
+        // A call is written as the bare label; numeric targets need the :call directive
+        Instruction::Call(nnn) => {
+            let target = address(*nnn);
+            if target.starts_with("0x") {
+                format!(":call {}", target)
+            } else {
+                target
+            }
+        }
+        Instruction::SkipEqual(x, kk) => format!("if {} != {} then", v(x), byte(kk)),
+        Instruction::SkipNotEqual(x, kk) => format!("if {} == {} then", v(x), byte(kk)),
+        Instruction::SkipEqualRegister(x, y) => format!("if {} != {} then", v(x), v(y)),
+        Instruction::StoreRegisterRange(x, y) => format!("save {} - {}", v(x), v(y)),
+        Instruction::LoadRegisterRange(x, y) => format!("load {} - {}", v(x), v(y)),
+        Instruction::Load(x, kk) => format!("{} := {}", v(x), byte(kk)),
+        Instruction::Add(x, kk) => format!("{} += {}", v(x), byte(kk)),
+        Instruction::LoadRegister(x, y) => format!("{} := {}", v(x), v(y)),
+        Instruction::LoadOr(x, y) => format!("{} |= {}", v(x), v(y)),
+        Instruction::LoadAnd(x, y) => format!("{} &= {}", v(x), v(y)),
+        Instruction::LoadXor(x, y) => format!("{} ^= {}", v(x), v(y)),
+        Instruction::LoadAdd(x, y) => format!("{} += {}", v(x), v(y)),
+        Instruction::LoadSub(x, y) => format!("{} -= {}", v(x), v(y)),
+        Instruction::LoadShiftRight(x, y) => format!("{} >>= {}", v(x), v(y)),
+        Instruction::LoadSubNegative(x, y) => format!("{} =- {}", v(x), v(y)),
+        Instruction::LoadShiftLeft(x, y) => format!("{} <<= {}", v(x), v(y)),
+        Instruction::SkipNotEqualRegister(x, y) => format!("if {} == {} then", v(x), v(y)),
+        Instruction::LoadIndexRegister(nnn) => format!("i := {}", address(*nnn)),
+        Instruction::JumpWithOffset(nnn) => format!("jump0 {}", address(*nnn)),
+        Instruction::Random(x, kk) => format!("{} := random {}", v(x), byte(kk)),
+        Instruction::Display(x, y, n) => format!("sprite {} {} {}", v(x), v(y), n.value()),
+        Instruction::SkipKeyPress(x) => format!("if {} -key then", v(x)),
+        Instruction::SkipKeyNotPress(x) => format!("if {} key then", v(x)),
+        Instruction::LoadIndexLong => match long_operand {
+            Some(nnnn) => format!("i := long {}", address(nnnn)),
+            None => "i := long".to_string(),
+        },
+        Instruction::SelectPlanes(n) => format!("plane {}", n.value()),
+        Instruction::LoadAudioPattern => "audio".to_string(),
+        Instruction::LoadDelayTimer(x) => format!("{} := delay", v(x)),
+        Instruction::LoadKeyPress(x) => format!("{} := key", v(x)),
+        Instruction::StoreDelayTimer(x) => format!("delay := {}", v(x)),
+        Instruction::StoreSoundTimer(x) => format!("buzzer := {}", v(x)),
+        Instruction::AddIndexRegister(x) => format!("i += {}", v(x)),
+        Instruction::LoadFontCharacter(x) => format!("i := hex {}", v(x)),
+        Instruction::LoadBigFontCharacter(x) => format!("i := bighex {}", v(x)),
+        Instruction::LoadBinaryCodedDecimal(x) => format!("bcd {}", v(x)),
+        Instruction::LoadPitch(x) => format!("pitch := {}", v(x)),
+        Instruction::StoreRegisters(x) => format!("save {}", v(x)),
+        Instruction::LoadRegisters(x) => format!("load {}", v(x)),
+        Instruction::StoreFlags(x) => format!("saveflags {}", v(x)),
+        Instruction::LoadFlags(x) => format!("loadflags {}", v(x)),
+    }
+}
+
+// Produces a full listing of a ROM image loaded at `origin`
+pub fn disassemble(image: &[u8], origin: u16, syntax: Syntax) -> String {
+    let analysis = analyze(image, origin);
+    let address_name = |address: u16| {
+        analysis
+            .label_name(address)
+            .unwrap_or_else(|| hex_address(address))
+    };
+    let comment = match syntax {
+        Syntax::Cowgod => ";",
+        Syntax::Octo => "#",
+    };
+
+    let mut out = String::new();
+    let end = origin as usize + image.len();
+    let mut address = origin as usize;
+
+    while address < end {
+        let current = address as u16;
+        if let Some(name) = analysis.label_name(current) {
+            match syntax {
+                Syntax::Cowgod => writeln!(out, "{}:", name).unwrap(),
+                Syntax::Octo => writeln!(out, ": {}", name).unwrap(),
+            }
+        }
+
+        if analysis.is_code(current) {
+            let (instruction, long_operand) = decode_at(image, origin, current).unwrap();
+            let size = instruction_size(&instruction) as usize;
+            let raw: String = image[address - origin as usize..address - origin as usize + size]
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect();
+            let text = format_instruction(&instruction, syntax, long_operand, &address_name);
+            writeln!(out, "    {:<24} {} {:04X}: {}", text, comment, address, raw).unwrap();
+            address += size;
+            continue;
+        }
+
+        // Data runs until the next code or label. Data loaded into I is usually a sprite,
+        // so it is printed a byte per line with its pixels drawn in the comment.
+        let run_end = (address + 1..end)
+            .find(|&next| {
+                analysis.is_code(next as u16) || analysis.labels.contains_key(&(next as u16))
+            })
+            .unwrap_or(end);
+        let bytes = &image[address - origin as usize..run_end - origin as usize];
+        let is_sprite = analysis.labels.get(&current) == Some(&LabelKind::Data);
+        let per_line = if is_sprite { 1 } else { 8 };
+
+        for (row, chunk) in bytes.chunks(per_line).enumerate() {
+            let values: Vec<String> = chunk
+                .iter()
+                .map(|byte| match syntax {
+                    Syntax::Cowgod => format!("{:#04X}", byte),
+                    Syntax::Octo => format!("{:#04x}", byte),
+                })
+                .collect();
+            let text = match syntax {
+                Syntax::Cowgod => format!("DB {}", values.join(", ")),
+                Syntax::Octo => values.join(" "),
+            };
+            let row_address = address + row * per_line;
+            if is_sprite {
+                let pixels: String = (0..8)
+                    .map(|bit| {
+                        if chunk[0] & (0x80 >> bit) != 0 {
+                            '#'
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect();
+                writeln!(
+                    out,
+                    "    {:<24} {} {:04X}: {}",
+                    text, comment, row_address, pixels
+                )
+                .unwrap();
+            } else {
+                writeln!(out, "    {:<24} {} {:04X}", text, comment, row_address).unwrap();
+            }
+        }
+        address = run_end;
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(words: &[u16]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_be_bytes()).collect()
+    }
+
+    // A call, a skip with both outcomes, a sprite and two bytes nothing reaches
+    fn program() -> Vec<u8> {
+        let mut data = image(&[
+            0x220A, 0xA212, 0x3000, 0x120E, 0x1208, 0x6001, 0x00EE, 0x120E, 0x1234,
+        ]);
+        data.extend([0xF0, 0x90]);
+        data
+    }
+
+    #[test]
+    fn control_flow_decides_what_is_code() {
+        let analysis = analyze(&program(), 0x200);
+        let code: Vec<u16> = analysis.code.iter().copied().collect();
+        assert_eq!(
+            code,
+            [0x200, 0x202, 0x204, 0x206, 0x208, 0x20A, 0x20C, 0x20E]
+        );
+        assert!(!analysis.is_code(0x210));
+        assert!(!analysis.is_code(0x212));
+    }
+
+    #[test]
+    fn labels_are_named_after_their_use() {
+        let analysis = analyze(&program(), 0x200);
+        let name = |address| analysis.label_name(address);
+        assert_eq!(name(0x200).as_deref(), Some("main"));
+        assert_eq!(name(0x208).as_deref(), Some("label_208"));
+        assert_eq!(name(0x20A).as_deref(), Some("sub_20a"));
+        assert_eq!(name(0x20E).as_deref(), Some("label_20e"));
+        assert_eq!(name(0x212).as_deref(), Some("data_212"));
+        assert_eq!(name(0x210), None);
+
+        // The font lies outside the image, so it keeps its address
+        let analysis = analyze(&image(&[0xA050, 0x1202]), 0x200);
+        assert_eq!(analysis.label_name(0x050), None);
+    }
+
+    #[test]
+    fn jumps_into_data_make_it_code() {
+        // The target is loaded into I and then jumped to, so it is both
+        let analysis = analyze(&image(&[0xA204, 0x1204, 0x1204]), 0x200);
+        assert_eq!(analysis.labels.get(&0x204), Some(&LabelKind::Jump));
+        assert!(analysis.is_code(0x204));
+    }
+
+    #[test]
+    fn jump_tables_and_long_loads() {
+        // The skip steps over all four bytes of F000 nnnn, whose operand is not code
+        let mut data = image(&[0x3000, 0xF000, 0x020A, 0xB20C, 0x0000]);
+        data.extend([0x3C, 0x3C]);
+        data.extend(image(&[0x120C]));
+        let analysis = analyze(&data, 0x200);
+
+        let code: Vec<u16> = analysis.code.iter().copied().collect();
+        assert_eq!(code, [0x200, 0x202, 0x206, 0x20C]);
+        assert_eq!(analysis.labels.get(&0x20A), Some(&LabelKind::Data));
+        assert_eq!(analysis.labels.get(&0x20C), Some(&LabelKind::Jump));
+
+        let listing = disassemble(&data, 0x200, Syntax::Octo);
+        assert!(listing.contains("i := long data_20a       # 0202: F000020A\n"));
+        assert!(listing.contains("jump0 label_20c          # 0206: B20C\n"));
+    }
+
+    #[test]
+    fn cowgod_listing() {
+        let expected = "\
+main:
+    CALL sub_20a             ; 0200: 220A
+    LD I, data_212           ; 0202: A212
+    SE V0, 0x00              ; 0204: 3000
+    JP label_20e             ; 0206: 120E
+label_208:
+    JP label_208             ; 0208: 1208
+sub_20a:
+    LD V0, 0x01              ; 020A: 6001
+    RET                      ; 020C: 00EE
+label_20e:
+    JP label_20e             ; 020E: 120E
+    DB 0x12, 0x34            ; 0210
+data_212:
+    DB 0xF0                  ; 0212: ####....
+    DB 0x90                  ; 0213: #..#....
+";
+        assert_eq!(disassemble(&program(), 0x200, Syntax::Cowgod), expected);
+    }
+
+    #[test]
+    fn octo_listing() {
+        let expected = "\
+: main
+    sub_20a                  # 0200: 220A
+    i := data_212            # 0202: A212
+    if v0 != 0x00 then       # 0204: 3000
+    jump label_20e           # 0206: 120E
+: label_208
+    jump label_208           # 0208: 1208
+: sub_20a
+    v0 := 0x01               # 020A: 6001
+    return                   # 020C: 00EE
+: label_20e
+    jump label_20e           # 020E: 120E
+    0x12 0x34                # 0210
+: data_212
+    0xf0                     # 0212: ####....
+    0x90                     # 0213: #..#....
+";
+        assert_eq!(disassemble(&program(), 0x200, Syntax::Octo), expected);
+
+        // Calls to addresses without a label need the directive
+        let listing = disassemble(&image(&[0x2300, 0x1202]), 0x200, Syntax::Octo);
+        assert!(listing.contains(":call 0x300"));
+    }
+}
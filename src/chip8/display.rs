@@ -1,30 +1,243 @@
+use super::savestate::{SaveStateError, StateReader, StateWriter};
+
 pub const DISPLAY_WIDTH: usize = 64;
 pub const DISPLAY_HEIGHT: usize = 32;
+pub const HIRES_DISPLAY_WIDTH: usize = 128;
+pub const HIRES_DISPLAY_HEIGHT: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    // 64x32, the original CHIP-8 screen
+    Low,
+    // 128x64, the SUPER-CHIP extended screen
+    High,
+}
+
+impl Resolution {
+    pub fn width(&self) -> usize {
+        match self {
+            Resolution::Low => DISPLAY_WIDTH,
+            Resolution::High => HIRES_DISPLAY_WIDTH,
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        match self {
+            Resolution::Low => DISPLAY_HEIGHT,
+            Resolution::High => HIRES_DISPLAY_HEIGHT,
+        }
+    }
+}
+
+// XO-CHIP draws on two independent bit-planes; plain CHIP-8 only ever uses the first
+pub const NUM_PLANES: usize = 2;
+
+pub type PlaneBuffer = [[bool; HIRES_DISPLAY_WIDTH]; HIRES_DISPLAY_HEIGHT];
+
+const BLANK_PLANE: PlaneBuffer = [[false; HIRES_DISPLAY_WIDTH]; HIRES_DISPLAY_HEIGHT];
 
 pub struct Display {
-    buffer: [[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+    // Sized for the largest resolution; only the top-left width x height area is in use
+    planes: [PlaneBuffer; NUM_PLANES],
+    resolution: Resolution,
+    // Bitmask of the planes affected by clear, scroll and draw (bit 0 = first plane)
+    selected_planes: u8,
 }
 
 impl Display {
     pub fn new() -> Self {
         Self {
-            buffer: [[false; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+            planes: [BLANK_PLANE; NUM_PLANES],
+            resolution: Resolution::Low,
+            selected_planes: 0b01,
         }
     }
 
+    // Clears the selected planes only, matching XO-CHIP's 00E0
     pub fn clear(&mut self) {
-        self.buffer = [[false; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+        for plane in self.selected() {
+            self.planes[plane] = BLANK_PLANE;
+        }
+    }
+
+    // The first plane, which holds the whole picture for monochrome programs
+    pub fn get_buffer(&self) -> &PlaneBuffer {
+        &self.planes[0]
+    }
+
+    pub fn get_plane(&self, plane: usize) -> &PlaneBuffer {
+        &self.planes[plane]
+    }
+
+    // Palette index of a pixel: bit 0 from the first plane, bit 1 from the second
+    pub fn pixel_color(&self, x: usize, y: usize) -> u8 {
+        (0..NUM_PLANES).fold(0, |color, plane| {
+            color | ((self.planes[plane][y][x] as u8) << plane)
+        })
+    }
+
+    pub fn selected_planes(&self) -> u8 {
+        self.selected_planes
     }
 
-    pub fn get_buffer(&self) -> &[[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT] {
-        &self.buffer
+    pub fn select_planes(&mut self, mask: u8) {
+        self.selected_planes = mask & 0b11;
     }
 
-    pub fn toggle_pixel(&mut self, x: usize, y: usize) -> bool {
-        let pixel_on = self.buffer[y][x];
+    // Indices of the currently selected planes, in drawing order
+    pub fn selected(&self) -> impl Iterator<Item = usize> + use<> {
+        let mask = self.selected_planes;
+        (0..NUM_PLANES).filter(move |plane| mask & (1 << plane) != 0)
+    }
 
-        self.buffer[y][x] ^= true;
+    pub fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+
+    pub fn width(&self) -> usize {
+        self.resolution.width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.resolution.height()
+    }
+
+    // Switching resolution clears the screen, as Octo and most SUPER-CHIP emulators do
+    pub fn set_resolution(&mut self, resolution: Resolution) {
+        self.resolution = resolution;
+        self.planes = [BLANK_PLANE; NUM_PLANES];
+    }
+
+    pub fn toggle_pixel(&mut self, plane: usize, x: usize, y: usize) -> bool {
+        let pixel_on = self.planes[plane][y][x];
+
+        self.planes[plane][y][x] ^= true;
 
         pixel_on
     }
+
+    // XORs a sprite onto the screen and returns whether any lit pixel was turned off.
+    // Each row is `width` bits wide (8 or 16), most significant bit leftmost.
+    pub fn draw_sprite(
+        &mut self,
+        plane: usize,
+        x: usize,
+        y: usize,
+        rows: &[u16],
+        width: usize,
+        wrap: bool,
+    ) -> bool {
+        let (screen_width, screen_height) = (self.width(), self.height());
+        let x = x % screen_width;
+        let y = y % screen_height;
+        let mut collision = false;
+
+        for (row, &bits) in rows.iter().enumerate() {
+            for col in 0..width {
+                if bits & (1 << (width - 1 - col)) == 0 {
+                    continue;
+                }
+
+                let mut pixel_x = x + col;
+                let mut pixel_y = y + row;
+                if wrap {
+                    pixel_x %= screen_width;
+                    pixel_y %= screen_height;
+                }
+                if pixel_x < screen_width
+                    && pixel_y < screen_height
+                    && self.toggle_pixel(plane, pixel_x, pixel_y)
+                {
+                    collision = true;
+                }
+            }
+        }
+
+        collision
+    }
+
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        writer.u8(match self.resolution {
+            Resolution::Low => 0,
+            Resolution::High => 1,
+        });
+        writer.u8(self.selected_planes);
+        for plane in &self.planes {
+            writer.bits(plane.iter().flatten().copied());
+        }
+    }
+
+    pub(crate) fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.resolution = match reader.u8()? {
+            0 => Resolution::Low,
+            1 => Resolution::High,
+            _ => return Err(SaveStateError::Invalid("resolution")),
+        };
+        self.select_planes(reader.u8()?);
+        for plane in self.planes.iter_mut() {
+            let bits = reader.bits(HIRES_DISPLAY_WIDTH * HIRES_DISPLAY_HEIGHT)?;
+            for (row, row_bits) in plane.iter_mut().zip(bits.chunks(HIRES_DISPLAY_WIDTH)) {
+                row.copy_from_slice(row_bits);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn scroll_down(&mut self, rows: usize) {
+        let height = self.height();
+        for plane in self.selected() {
+            let buffer = &mut self.planes[plane];
+            for y in (0..height).rev() {
+                buffer[y] = if y >= rows {
+                    buffer[y - rows]
+                } else {
+                    [false; HIRES_DISPLAY_WIDTH]
+                };
+            }
+        }
+    }
+
+    pub fn scroll_up(&mut self, rows: usize) {
+        let height = self.height();
+        for plane in self.selected() {
+            let buffer = &mut self.planes[plane];
+            for y in 0..height {
+                buffer[y] = if y + rows < height {
+                    buffer[y + rows]
+                } else {
+                    [false; HIRES_DISPLAY_WIDTH]
+                };
+            }
+        }
+    }
+
+    pub fn scroll_left(&mut self, cols: usize) {
+        let (width, height) = (self.width(), self.height());
+        for plane in self.selected() {
+            let buffer = &mut self.planes[plane];
+            for row in buffer.iter_mut().take(height) {
+                for x in 0..width {
+                    row[x] = x + cols < width && row[x + cols];
+                }
+            }
+        }
+    }
+
+    pub fn scroll_right(&mut self, cols: usize) {
+        let (width, height) = (self.width(), self.height());
+        for plane in self.selected() {
+            let buffer = &mut self.planes[plane];
+            for row in buffer.iter_mut().take(height) {
+                for x in (0..width).rev() {
+                    row[x] = x >= cols && row[x - cols];
+                }
+            }
+        }
+    }
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
 }
@@ -0,0 +1,86 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    pub opcode: u16,
+    pub pc: Option<u16>,
+}
+
+impl DecodeError {
+    pub fn new(opcode: u16) -> Self {
+        Self { opcode, pc: None }
+    }
+
+    // Attaches the address the opcode was fetched from
+    pub fn at(self, pc: u16) -> Self {
+        Self {
+            pc: Some(pc),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown instruction {:#06X}", self.opcode)?;
+        if let Some(pc) = self.pc {
+            write!(f, " at {:#05X}", pc)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chip8Error {
+    Decode(DecodeError),
+    // A valid opcode that the configured platform does not provide
+    UnsupportedInstruction { opcode: u16, pc: u16 },
+    // The instruction at `pc` accessed `address`, which is past the end of memory
+    MemoryOutOfBounds { address: usize, pc: u16 },
+    // 2nnn at `pc` was called with the stack already at its depth limit
+    StackOverflow { pc: u16 },
+    // 00EE at `pc` was executed with nothing on the stack
+    StackUnderflow { pc: u16 },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::Decode(err) => write!(f, "decode error: {}", err),
+            Chip8Error::UnsupportedInstruction { opcode, pc } => write!(
+                f,
+                "instruction {:#06X} at {:#05X} requires XO-CHIP mode",
+                opcode, pc
+            ),
+            Chip8Error::MemoryOutOfBounds { address, pc } => write!(
+                f,
+                "instruction at {:#05X} accessed {:#06X}, outside memory",
+                pc, address
+            ),
+            Chip8Error::StackOverflow { pc } => write!(f, "stack overflow at {:#05X}", pc),
+            Chip8Error::StackUnderflow { pc } => {
+                write!(f, "return with an empty stack at {:#05X}", pc)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Chip8Error::Decode(err) => Some(err),
+            Chip8Error::UnsupportedInstruction { .. }
+            | Chip8Error::MemoryOutOfBounds { .. }
+            | Chip8Error::StackOverflow { .. }
+            | Chip8Error::StackUnderflow { .. } => None,
+        }
+    }
+}
+
+impl From<DecodeError> for Chip8Error {
+    fn from(err: DecodeError) -> Self {
+        Chip8Error::Decode(err)
+    }
+}
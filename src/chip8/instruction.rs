@@ -1,12 +1,30 @@
+use std::fmt;
+
+use super::disassembler::{self, Syntax};
+use super::error::DecodeError;
 use super::register::RegisterIndex;
 use super::types::Nibble;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Instruction {
+    // 00Cn - SCD nibble
+    ScrollDown(Nibble),
+    // 00Dn - SCU nibble
+    ScrollUp(Nibble),
     // 00E0 - CLS
     Clear,
     // 00EE - RET
     Return,
+    // 00FB - SCR
+    ScrollRight,
+    // 00FC - SCL
+    ScrollLeft,
+    // 00FD - EXIT
+    Exit,
+    // 00FE - LOW
+    LowResolution,
+    // 00FF - HIGH
+    HighResolution,
     // 1nnn - JP addr
     Jump(u16),
     // 2nnn - CALL addr
@@ -17,6 +35,10 @@ pub enum Instruction {
     SkipNotEqual(RegisterIndex, u8),
     // 5xy0 - SE Vx, Vy
     SkipEqualRegister(RegisterIndex, RegisterIndex),
+    // 5xy2 - LD [I], Vx-Vy
+    StoreRegisterRange(RegisterIndex, RegisterIndex),
+    // 5xy3 - LD Vx-Vy, [I]
+    LoadRegisterRange(RegisterIndex, RegisterIndex),
     // 6xkk - LD Vx, byte
     Load(RegisterIndex, u8),
     // 7xkk - ADD Vx, byte
@@ -53,6 +75,12 @@ pub enum Instruction {
     SkipKeyPress(RegisterIndex),
     // ExA1 - SKNP Vx
     SkipKeyNotPress(RegisterIndex),
+    // F000 nnnn - LD I, long addr (the address is the following word)
+    LoadIndexLong,
+    // Fn01 - PLANE n
+    SelectPlanes(Nibble),
+    // F002 - AUDIO
+    LoadAudioPattern,
     // Fx07 - LD Vx, DT
     LoadDelayTimer(RegisterIndex),
     // Fx0A - LD Vx, K
@@ -65,26 +93,62 @@ pub enum Instruction {
     AddIndexRegister(RegisterIndex),
     // Fx29 - LD F, Vx
     LoadFontCharacter(RegisterIndex),
+    // Fx30 - LD HF, Vx
+    LoadBigFontCharacter(RegisterIndex),
     // Fx33 - LD B, Vx
     LoadBinaryCodedDecimal(RegisterIndex),
+    // Fx3A - PITCH Vx
+    LoadPitch(RegisterIndex),
     // Fx55 - LD [I], Vx
     StoreRegisters(RegisterIndex),
     // Fx65 - LD Vx, [I]
     LoadRegisters(RegisterIndex),
+    // Fx75 - LD R, Vx
+    StoreFlags(RegisterIndex),
+    // Fx85 - LD Vx, R
+    LoadFlags(RegisterIndex),
 }
 
-impl From<u16> for Instruction {
-    fn from(raw_instruction: u16) -> Self {
+impl Instruction {
+    pub fn requires_xo_chip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::ScrollUp(_)
+                | Instruction::StoreRegisterRange(_, _)
+                | Instruction::LoadRegisterRange(_, _)
+                | Instruction::LoadIndexLong
+                | Instruction::SelectPlanes(_)
+                | Instruction::LoadAudioPattern
+                | Instruction::LoadPitch(_)
+        )
+    }
+}
+
+impl TryFrom<u16> for Instruction {
+    type Error = DecodeError;
+
+    fn try_from(raw_instruction: u16) -> Result<Self, Self::Error> {
         let opcode = (raw_instruction & 0xF000) >> 12;
 
-        match opcode {
-            0x0 => {
-                if raw_instruction == 0x00E0 {
-                    Instruction::Clear
-                } else {
-                    Instruction::Return
+        let instruction = match opcode {
+            0x0 => match raw_instruction {
+                0x00C0..=0x00CF => {
+                    let n = (raw_instruction & 0x000F) as u8;
+                    Instruction::ScrollDown(Nibble::try_from(n).unwrap())
                 }
-            }
+                0x00D0..=0x00DF => {
+                    let n = (raw_instruction & 0x000F) as u8;
+                    Instruction::ScrollUp(Nibble::try_from(n).unwrap())
+                }
+                0x00E0 => Instruction::Clear,
+                0x00EE => Instruction::Return,
+                0x00FB => Instruction::ScrollRight,
+                0x00FC => Instruction::ScrollLeft,
+                0x00FD => Instruction::Exit,
+                0x00FE => Instruction::LowResolution,
+                0x00FF => Instruction::HighResolution,
+                _ => return Err(DecodeError::new(raw_instruction)),
+            },
             0x1 => {
                 let nnn = raw_instruction & 0x0FFF;
                 Instruction::Jump(nnn)
@@ -106,10 +170,22 @@ impl From<u16> for Instruction {
             0x5 => {
                 let x = ((raw_instruction & 0x0F00) >> 8) as u8;
                 let y = ((raw_instruction & 0x00F0) >> 4) as u8;
-                Instruction::SkipEqualRegister(
-                    RegisterIndex::try_from(x).unwrap(),
-                    RegisterIndex::try_from(y).unwrap(),
-                )
+                let subcode = raw_instruction & 0x000F;
+                match subcode {
+                    0x0 => Instruction::SkipEqualRegister(
+                        RegisterIndex::try_from(x).unwrap(),
+                        RegisterIndex::try_from(y).unwrap(),
+                    ),
+                    0x2 => Instruction::StoreRegisterRange(
+                        RegisterIndex::try_from(x).unwrap(),
+                        RegisterIndex::try_from(y).unwrap(),
+                    ),
+                    0x3 => Instruction::LoadRegisterRange(
+                        RegisterIndex::try_from(x).unwrap(),
+                        RegisterIndex::try_from(y).unwrap(),
+                    ),
+                    _ => return Err(DecodeError::new(raw_instruction)),
+                }
             }
             0x6 => {
                 let x = ((raw_instruction & 0x0F00) >> 8) as u8;
@@ -162,12 +238,15 @@ impl From<u16> for Instruction {
                         RegisterIndex::try_from(x).unwrap(),
                         RegisterIndex::try_from(y).unwrap(),
                     ),
-                    _ => panic!("Unknown instruction: {:#X}", raw_instruction),
+                    _ => return Err(DecodeError::new(raw_instruction)),
                 }
             }
             0x9 => {
                 let x = ((raw_instruction & 0x0F00) >> 8) as u8;
                 let y = ((raw_instruction & 0x00F0) >> 4) as u8;
+                if raw_instruction & 0x000F != 0 {
+                    return Err(DecodeError::new(raw_instruction));
+                }
                 Instruction::SkipNotEqualRegister(
                     RegisterIndex::try_from(x).unwrap(),
                     RegisterIndex::try_from(y).unwrap(),
@@ -202,28 +281,148 @@ impl From<u16> for Instruction {
                 match subcode {
                     0x9E => Instruction::SkipKeyPress(RegisterIndex::try_from(x).unwrap()),
                     0xA1 => Instruction::SkipKeyNotPress(RegisterIndex::try_from(x).unwrap()),
-                    _ => panic!("Unknown instruction: {:#X}", raw_instruction),
+                    _ => return Err(DecodeError::new(raw_instruction)),
                 }
             }
             0xF => {
                 let x = ((raw_instruction & 0x0F00) >> 8) as u8;
                 let subcode = raw_instruction & 0x00FF;
                 match subcode {
-                    0x7 => Instruction::LoadDelayTimer(RegisterIndex::try_from(x).unwrap()),
+                    0x00 if x == 0 => Instruction::LoadIndexLong,
+                    0x01 => Instruction::SelectPlanes(Nibble::try_from(x).unwrap()),
+                    0x02 if x == 0 => Instruction::LoadAudioPattern,
+                    0x07 => Instruction::LoadDelayTimer(RegisterIndex::try_from(x).unwrap()),
                     0x0A => Instruction::LoadKeyPress(RegisterIndex::try_from(x).unwrap()),
                     0x15 => Instruction::StoreDelayTimer(RegisterIndex::try_from(x).unwrap()),
                     0x18 => Instruction::StoreSoundTimer(RegisterIndex::try_from(x).unwrap()),
                     0x1E => Instruction::AddIndexRegister(RegisterIndex::try_from(x).unwrap()),
                     0x29 => Instruction::LoadFontCharacter(RegisterIndex::try_from(x).unwrap()),
+                    0x30 => Instruction::LoadBigFontCharacter(RegisterIndex::try_from(x).unwrap()),
                     0x33 => {
                         Instruction::LoadBinaryCodedDecimal(RegisterIndex::try_from(x).unwrap())
                     }
+                    0x3A => Instruction::LoadPitch(RegisterIndex::try_from(x).unwrap()),
                     0x55 => Instruction::StoreRegisters(RegisterIndex::try_from(x).unwrap()),
                     0x65 => Instruction::LoadRegisters(RegisterIndex::try_from(x).unwrap()),
-                    _ => panic!("Unknown instruction: {:#X}", raw_instruction),
+                    0x75 => Instruction::StoreFlags(RegisterIndex::try_from(x).unwrap()),
+                    0x85 => Instruction::LoadFlags(RegisterIndex::try_from(x).unwrap()),
+                    _ => return Err(DecodeError::new(raw_instruction)),
                 }
             }
-            _ => panic!("Unknown instruction: {:#X}", raw_instruction),
+            _ => return Err(DecodeError::new(raw_instruction)),
+        };
+
+        Ok(instruction)
+    }
+}
+
+// Encodes back to the opcode `TryFrom<u16>` decodes. F000 encodes as its first word only;
+// the address that follows it is written separately.
+impl From<Instruction> for u16 {
+    fn from(instruction: Instruction) -> Self {
+        let xkk =
+            |opcode: u16, x: RegisterIndex, kk: u8| opcode | ((x.value() as u16) << 8) | kk as u16;
+        let xyn = |opcode: u16, x: RegisterIndex, y: RegisterIndex, n: u16| {
+            opcode | ((x.value() as u16) << 8) | ((y.value() as u16) << 4) | n
+        };
+        let x = |opcode: u16, x: RegisterIndex| opcode | ((x.value() as u16) << 8);
+
+        match instruction {
+            Instruction::ScrollDown(n) => 0x00C0 | n.value() as u16,
+            Instruction::ScrollUp(n) => 0x00D0 | n.value() as u16,
+            Instruction::Clear => 0x00E0,
+            Instruction::Return => 0x00EE,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::LowResolution => 0x00FE,
+            Instruction::HighResolution => 0x00FF,
+            Instruction::Jump(nnn) => 0x1000 | (nnn & 0x0FFF),
+            Instruction::Call(nnn) => 0x2000 | (nnn & 0x0FFF),
+            Instruction::SkipEqual(vx, kk) => xkk(0x3000, vx, kk),
+            Instruction::SkipNotEqual(vx, kk) => xkk(0x4000, vx, kk),
+            Instruction::SkipEqualRegister(vx, vy) => xyn(0x5000, vx, vy, 0x0),
+            Instruction::StoreRegisterRange(vx, vy) => xyn(0x5000, vx, vy, 0x2),
+            Instruction::LoadRegisterRange(vx, vy) => xyn(0x5000, vx, vy, 0x3),
+            Instruction::Load(vx, kk) => xkk(0x6000, vx, kk),
+            Instruction::Add(vx, kk) => xkk(0x7000, vx, kk),
+            Instruction::LoadRegister(vx, vy) => xyn(0x8000, vx, vy, 0x0),
+            Instruction::LoadOr(vx, vy) => xyn(0x8000, vx, vy, 0x1),
+            Instruction::LoadAnd(vx, vy) => xyn(0x8000, vx, vy, 0x2),
+            Instruction::LoadXor(vx, vy) => xyn(0x8000, vx, vy, 0x3),
+            Instruction::LoadAdd(vx, vy) => xyn(0x8000, vx, vy, 0x4),
+            Instruction::LoadSub(vx, vy) => xyn(0x8000, vx, vy, 0x5),
+            Instruction::LoadShiftRight(vx, vy) => xyn(0x8000, vx, vy, 0x6),
+            Instruction::LoadSubNegative(vx, vy) => xyn(0x8000, vx, vy, 0x7),
+            Instruction::LoadShiftLeft(vx, vy) => xyn(0x8000, vx, vy, 0xE),
+            Instruction::SkipNotEqualRegister(vx, vy) => xyn(0x9000, vx, vy, 0x0),
+            Instruction::LoadIndexRegister(nnn) => 0xA000 | (nnn & 0x0FFF),
+            Instruction::JumpWithOffset(nnn) => 0xB000 | (nnn & 0x0FFF),
+            Instruction::Random(vx, kk) => xkk(0xC000, vx, kk),
+            Instruction::Display(vx, vy, n) => xyn(0xD000, vx, vy, n.value() as u16),
+            Instruction::SkipKeyPress(vx) => x(0xE09E, vx),
+            Instruction::SkipKeyNotPress(vx) => x(0xE0A1, vx),
+            Instruction::LoadIndexLong => 0xF000,
+            Instruction::SelectPlanes(n) => 0xF001 | ((n.value() as u16) << 8),
+            Instruction::LoadAudioPattern => 0xF002,
+            Instruction::LoadDelayTimer(vx) => x(0xF007, vx),
+            Instruction::LoadKeyPress(vx) => x(0xF00A, vx),
+            Instruction::StoreDelayTimer(vx) => x(0xF015, vx),
+            Instruction::StoreSoundTimer(vx) => x(0xF018, vx),
+            Instruction::AddIndexRegister(vx) => x(0xF01E, vx),
+            Instruction::LoadFontCharacter(vx) => x(0xF029, vx),
+            Instruction::LoadBigFontCharacter(vx) => x(0xF030, vx),
+            Instruction::LoadBinaryCodedDecimal(vx) => x(0xF033, vx),
+            Instruction::LoadPitch(vx) => x(0xF03A, vx),
+            Instruction::StoreRegisters(vx) => x(0xF055, vx),
+            Instruction::LoadRegisters(vx) => x(0xF065, vx),
+            Instruction::StoreFlags(vx) => x(0xF075, vx),
+            Instruction::LoadFlags(vx) => x(0xF085, vx),
+        }
+    }
+}
+
+// Cowgod-style mnemonic with numeric addresses, e.g. `LD V0, 0x05`
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = disassembler::format_instruction(
+            self,
+            Syntax::Cowgod,
+            None,
+            &disassembler::hex_address,
+        );
+        f.write_str(&text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_decodable_opcode_encodes_back_to_itself() {
+        let mut decoded = 0;
+        for raw in 0..=u16::MAX {
+            if let Ok(instruction) = Instruction::try_from(raw) {
+                assert_eq!(
+                    u16::from(instruction),
+                    raw,
+                    "{:#06X} ({})",
+                    raw,
+                    instruction
+                );
+                decoded += 1;
+            }
+        }
+        assert_eq!(decoded, 44_585);
+    }
+
+    #[test]
+    fn undefined_opcodes_report_themselves() {
+        for raw in [
+            0x0000, 0x00E1, 0x5001, 0x8008, 0x9001, 0xE000, 0xF100, 0xF0FF,
+        ] {
+            assert_eq!(Instruction::try_from(raw), Err(DecodeError::new(raw)));
         }
     }
 }
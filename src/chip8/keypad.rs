@@ -34,3 +34,40 @@ impl Keypad {
         None
     }
 }
+
+// The host's view of which keys are held, handed to `Chip8::run_frame` each frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyState {
+    keys: [bool; NUM_KEYS],
+}
+
+impl KeyState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_key_pressed(&mut self, key_index: u8, is_pressed: bool) {
+        if (key_index as usize) < NUM_KEYS {
+            self.keys[key_index as usize] = is_pressed;
+        }
+    }
+
+    pub fn is_key_pressed(&self, key_index: u8) -> bool {
+        (key_index as usize) < NUM_KEYS && self.keys[key_index as usize]
+    }
+
+    // Bit n is set while key n is held
+    pub fn to_bits(&self) -> u16 {
+        (0..NUM_KEYS)
+            .filter(|&key| self.keys[key])
+            .fold(0, |bits, key| bits | 1 << key)
+    }
+
+    pub fn from_bits(bits: u16) -> Self {
+        let mut keys = [false; NUM_KEYS];
+        for (key, pressed) in keys.iter_mut().enumerate() {
+            *pressed = bits & (1 << key) != 0;
+        }
+        Self { keys }
+    }
+}
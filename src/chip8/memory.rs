@@ -1,6 +1,12 @@
-const MEMORY_SIZE: usize = 4096;
-const ROM_START_ADDRESS: usize = 0x200;
+use super::rom::RomError;
+use super::savestate::{SaveStateError, StateReader, StateWriter};
+
+pub const MEMORY_SIZE: usize = 4096;
+// XO-CHIP extends the address space to the full 16 bits
+pub const XO_MEMORY_SIZE: usize = 0x10000;
+pub const ROM_START_ADDRESS: usize = 0x200;
 pub const FONTSET_START_ADDRESS: usize = 0x50;
+pub const BIG_FONTSET_START_ADDRESS: usize = 0xA0;
 
 const FONT_SET: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -21,43 +27,129 @@ const FONT_SET: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+// SUPER-CHIP 8x10 hex digits, used by Fx30
+const BIG_FONT_SET: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+// What happens when an instruction reads or writes past the end of memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MemoryPolicy {
+    // The address wraps around, as the 12-bit (or 16-bit for XO-CHIP) address bus does
+    #[default]
+    Wrap,
+    // The access stops emulation with `Chip8Error::MemoryOutOfBounds`
+    Fault,
+    // Reads give 0 and writes are dropped, and the access is reported in `FrameOutput`
+    Ignore,
+}
+
+impl MemoryPolicy {
+    // Parses a frontend's `--memory` value
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "wrap" => Ok(MemoryPolicy::Wrap),
+            "fault" => Ok(MemoryPolicy::Fault),
+            "ignore" => Ok(MemoryPolicy::Ignore),
+            _ => Err(format!(
+                "unknown memory policy '{}' (expected wrap, fault or ignore)",
+                name
+            )),
+        }
+    }
+}
+
 pub struct Memory {
-    ram: [u8; MEMORY_SIZE],
+    ram: Vec<u8>,
 }
 
 impl Memory {
     pub fn new() -> Self {
-        let mut ram = [0; MEMORY_SIZE];
+        Self::with_size(MEMORY_SIZE)
+    }
+
+    pub fn with_size(size: usize) -> Self {
+        let mut ram = vec![0; size];
 
         let font_end = FONTSET_START_ADDRESS + FONT_SET.len();
         ram[FONTSET_START_ADDRESS..font_end].copy_from_slice(&FONT_SET);
 
+        let big_font_end = BIG_FONTSET_START_ADDRESS + BIG_FONT_SET.len();
+        ram[BIG_FONTSET_START_ADDRESS..big_font_end].copy_from_slice(&BIG_FONT_SET);
+
         Self { ram }
     }
 
-    pub fn load_rom(&mut self, rom_data: &[u8]) {
-        let rom_start = ROM_START_ADDRESS;
-        let rom_end = rom_start + rom_data.len();
+    pub fn load_rom(&mut self, rom_data: &[u8], address: usize) -> Result<(), RomError> {
+        if rom_data.is_empty() {
+            return Err(RomError::Empty);
+        }
+        if !(ROM_START_ADDRESS..self.ram.len()).contains(&address) {
+            return Err(RomError::InvalidLoadAddress(address));
+        }
+        let available = self.ram.len() - address;
+        if rom_data.len() > available {
+            return Err(RomError::TooLarge {
+                size: rom_data.len(),
+                available,
+            });
+        }
+
+        self.ram[address..address + rom_data.len()].copy_from_slice(rom_data);
+        Ok(())
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.u32(self.ram.len() as u32);
+        writer.bytes(&self.ram);
+    }
 
-        if rom_end > MEMORY_SIZE {
-            panic!("ROM size exceeds available memory");
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        let size = reader.u32()? as usize;
+        if size != self.ram.len() {
+            return Err(SaveStateError::Invalid("memory size"));
         }
+        self.ram.copy_from_slice(reader.bytes(size)?);
+        Ok(())
+    }
 
-        self.ram[rom_start..rom_end].copy_from_slice(rom_data);
+    pub fn as_slice(&self) -> &[u8] {
+        &self.ram
     }
 
-    pub fn read_byte(&self, address: u16) -> u8 {
-        self.ram[address as usize]
+    pub fn size(&self) -> usize {
+        self.ram.len()
     }
 
-    pub fn read_instruction(&self, address: u16) -> u16 {
-        let addr = address as usize;
-        let high_byte = self.ram[addr] as u16;
-        let low_byte = self.ram[addr + 1] as u16;
-        (high_byte << 8) | low_byte
+    // `address` must be inside memory; `Chip8` applies its memory policy first
+    pub fn read_byte(&self, address: usize) -> u8 {
+        self.ram[address]
     }
 
-    pub fn write_byte(&mut self, address: u16, value: u8) {
-        self.ram[address as usize] = value;
+    pub fn write_byte(&mut self, address: usize, value: u8) {
+        self.ram[address] = value;
+    }
+
+    // Reads the two bytes at `address`, wrapping around the end of memory. For looking at
+    // code without running it, where the memory policy does not apply.
+    pub fn read_instruction(&self, address: usize) -> u16 {
+        let high_byte = self.ram[address % self.ram.len()] as u16;
+        let low_byte = self.ram[(address + 1) % self.ram.len()] as u16;
+        (high_byte << 8) | low_byte
     }
 }
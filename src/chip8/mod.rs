@@ -1,51 +1,200 @@
+pub mod analyzer;
+pub mod assembler;
+pub mod debugger;
+pub mod disassembler;
 pub mod display;
-mod instruction;
+pub mod error;
+pub mod instruction;
 mod keypad;
 mod memory;
+pub mod movie;
+pub mod octo;
+pub mod quirks;
+pub mod random;
 pub mod register;
-mod types;
+pub mod rewind;
+pub mod rom;
+pub mod savestate;
+mod stack;
+pub mod timing;
+pub mod types;
 
 use display::Display;
+use error::{Chip8Error, DecodeError};
 use instruction::Instruction;
+pub use keypad::KeyState;
 use keypad::Keypad;
-use memory::Memory;
+use memory::{Memory, XO_MEMORY_SIZE};
+pub use memory::{MemoryPolicy, ROM_START_ADDRESS};
+use quirks::Quirks;
+use random::{RandomSource, SeededRandom};
 use register::{RegisterFile, RegisterIndex};
+use rom::{Rom, RomError};
+use savestate::ROM_HASH_SIZE;
+use stack::Stack;
+pub use stack::{DEFAULT_STACK_DEPTH, StackDepth, VIP_STACK_ADDRESS, VIP_STACK_DEPTH};
+use timing::{FrameClock, TimingModel};
 
-use crate::chip8::memory::FONTSET_START_ADDRESS;
+use crate::chip8::display::Resolution;
+use crate::chip8::memory::{BIG_FONTSET_START_ADDRESS, FONTSET_START_ADDRESS};
+
+// Number of RPL user flags saved by Fx75 and restored by Fx85
+pub const NUM_RPL_FLAGS: usize = 16;
+// Size of the XO-CHIP audio pattern buffer loaded by F002
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+// Pitch register value at which the audio pattern plays back at 4000 bits per second
+pub const DEFAULT_PITCH: u8 = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    // The instruction was executed normally
+    Executed,
+    // Fx0A was executed and the core is now waiting for a key press
+    WaitingForKey,
+    // A draw is waiting for the next vblank under the display-wait quirk; the PC was not
+    // advanced and nothing more runs until `vblank` is called
+    WaitingForDisplay,
+    // 00FD was executed; the program has ended and no further cycles will run
+    Exited,
+}
+
+// What one frame of emulation produced, for the frontend to present
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameOutput {
+    // The screen was drawn to, cleared, scrolled or resized during the frame
+    pub display_changed: bool,
+    // The sound timer was running, so the buzzer should sound for this frame
+    pub sound_on: bool,
+    // The error that stopped the frame early; the machine cannot run any further
+    pub error: Option<Chip8Error>,
+    // Out-of-bounds accesses skipped under `MemoryPolicy::Ignore`, for the frontend to log
+    pub ignored_accesses: Vec<Chip8Error>,
+}
 
 pub struct Chip8 {
     memory: Memory,
     registers: RegisterFile,
     index_register: u16,
     program_counter: u16,
-    stack: [u16; 16],
-    stack_pointer: u8,
-    pub delay_timer: u8,
-    pub sound_timer: u8,
+    stack: Stack,
+    delay_timer: u8,
+    sound_timer: u8,
     pub display: Display,
     pub keypad: Keypad,
     waiting_for_key: Option<RegisterIndex>,
     waiting_for_release: bool,
+    rpl_flags: [u8; NUM_RPL_FLAGS],
+    audio_pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+    pitch: u8,
+    exited: bool,
+    // Set by `vblank` and cleared by the next instruction, so only a draw that runs first
+    // after a vblank goes ahead under the display-wait quirk
+    vblank_occurred: bool,
+    waiting_for_vblank: bool,
+    display_changed: bool,
+    // Host state that is not part of the emulated machine, so save states leave it alone:
+    // the keys held at the last frame, to find presses and releases, and the frame budget
+    input: KeyState,
+    clock: FrameClock,
+    memory_policy: MemoryPolicy,
+    ignored_accesses: Vec<Chip8Error>,
+    random: Box<dyn RandomSource>,
+    quirks: Quirks,
+    rom_hash: [u8; ROM_HASH_SIZE],
+    load_address: usize,
 }
 
 impl Chip8 {
-    pub fn new() -> Self {
+    pub fn new(quirks: Quirks) -> Self {
         Chip8 {
-            memory: Memory::new(),
+            memory: if quirks.xo_chip {
+                Memory::with_size(XO_MEMORY_SIZE)
+            } else {
+                Memory::new()
+            },
             registers: RegisterFile::new(),
             index_register: 0,
-            program_counter: 0x200, // Programs start at memory location 0x200
-            stack: [0; 16],
-            stack_pointer: 0,
+            program_counter: ROM_START_ADDRESS as u16,
+            stack: Stack::new(StackDepth::Limited(if quirks.vip_stack_depth {
+                VIP_STACK_DEPTH
+            } else {
+                DEFAULT_STACK_DEPTH
+            })),
             delay_timer: 0,
             sound_timer: 0,
             display: Display::new(),
             keypad: Keypad::new(),
             waiting_for_key: None,
             waiting_for_release: false,
+            rpl_flags: [0; NUM_RPL_FLAGS],
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            exited: false,
+            vblank_occurred: false,
+            waiting_for_vblank: false,
+            display_changed: false,
+            input: KeyState::new(),
+            clock: FrameClock::new(TimingModel::default()),
+            memory_policy: MemoryPolicy::default(),
+            ignored_accesses: Vec::new(),
+            random: random::default_source(),
+            quirks,
+            rom_hash: [0; ROM_HASH_SIZE],
+            load_address: ROM_START_ADDRESS,
         }
     }
 
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn program_counter(&self) -> u16 {
+        self.program_counter
+    }
+
+    pub fn index_register(&self) -> u16 {
+        self.index_register
+    }
+
+    pub fn registers(&self) -> &RegisterFile {
+        &self.registers
+    }
+
+    // Return addresses currently on the stack, oldest first
+    pub fn stack(&self) -> Vec<u16> {
+        self.stack.entries(&self.memory)
+    }
+
+    pub fn stack_depth(&self) -> StackDepth {
+        self.stack.depth()
+    }
+
+    // Sets how many return addresses can be stacked before 2nnn faults. A stack already
+    // deeper than the new limit is left alone.
+    pub fn set_stack_depth(&mut self, depth: StackDepth) {
+        self.stack.set_depth(depth);
+    }
+
+    pub fn stack_in_memory(&self) -> bool {
+        self.stack.in_memory()
+    }
+
+    // Keeps return addresses in RAM at 0xEA0-0xECF as the VIP interpreter does, where
+    // programs can read and overwrite them. The area holds at most 24 of them.
+    pub fn set_stack_in_memory(&mut self, in_memory: bool) {
+        self.stack.set_in_memory(in_memory, &mut self.memory);
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        self.memory.as_slice()
+    }
+
+    // Decodes the instruction at the PC without executing it
+    pub fn peek_instruction(&self) -> Result<Instruction, DecodeError> {
+        Instruction::try_from(self.memory.read_instruction(self.program_counter as usize))
+            .map_err(|err| err.at(self.program_counter))
+    }
+
     pub fn is_waiting_for_key(&self) -> bool {
         self.waiting_for_key.is_some()
     }
@@ -54,48 +203,292 @@ impl Chip8 {
         self.waiting_for_release
     }
 
-    pub fn load_rom(&mut self, rom_data: &[u8]) {
-        self.memory.load_rom(rom_data);
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn timing(&self) -> TimingModel {
+        self.clock.model()
+    }
+
+    // Sets how much runs in each frame; the change takes effect from the next frame
+    pub fn set_timing(&mut self, model: TimingModel) {
+        self.clock = FrameClock::new(model);
+    }
+
+    pub fn memory_policy(&self) -> MemoryPolicy {
+        self.memory_policy
+    }
+
+    pub fn set_memory_policy(&mut self, policy: MemoryPolicy) {
+        self.memory_policy = policy;
+    }
+
+    pub fn set_random_source(&mut self, source: Box<dyn RandomSource>) {
+        self.random = source;
+    }
+
+    // Makes Cxkk reproducible: the same seed, ROM and input give the same run
+    pub fn seed_random(&mut self, seed: u64) {
+        self.random = Box::new(SeededRandom::new(seed));
+    }
+
+    // The seed that reproduces the rest of the random sequence, if it can be reproduced
+    pub fn random_seed(&self) -> Option<u64> {
+        self.random.seed()
+    }
+
+    pub fn is_waiting_for_vblank(&self) -> bool {
+        self.waiting_for_vblank
+    }
+
+    // Signals the start of a display frame (the VIP's display interrupt). `end_frame`
+    // calls this; frontends only need it to release a waiting draw outside a frame.
+    pub fn vblank(&mut self) {
+        self.vblank_occurred = true;
+        self.waiting_for_vblank = false;
+    }
+
+    // RPL user flags outlive a single run on real hardware; frontends persist these
+    // between sessions and restore them before the ROM starts
+    pub fn rpl_flags(&self) -> &[u8; NUM_RPL_FLAGS] {
+        &self.rpl_flags
+    }
+
+    pub fn set_rpl_flags(&mut self, flags: &[u8; NUM_RPL_FLAGS]) {
+        self.rpl_flags = *flags;
+    }
+
+    // The XO-CHIP audio pattern, or None if the program never loaded one and the
+    // frontend should play its usual square wave
+    pub fn audio_pattern(&self) -> Option<&[u8; AUDIO_PATTERN_SIZE]> {
+        self.audio_pattern.as_ref()
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    // Playback rate of the audio pattern in bits per second
+    pub fn audio_sample_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    // Copies the ROM into memory and starts execution at its load address. A ROM that
+    // does not fit leaves the machine as it was.
+    pub fn load_rom(&mut self, rom: &Rom) -> Result<(), RomError> {
+        self.memory.load_rom(rom.data(), rom.load_address())?;
+        self.rom_hash = *rom.sha1();
+        self.load_address = rom.load_address();
+        self.program_counter = rom.load_address() as u16;
+        Ok(())
+    }
+
+    // SHA-1 of the loaded ROM, used to match save states to the program they came from
+    pub fn rom_hash(&self) -> &[u8; ROM_HASH_SIZE] {
+        &self.rom_hash
     }
 
-    pub fn emulate_cycle(&mut self, first_cycle_of_frame: bool) {
+    pub fn load_address(&self) -> usize {
+        self.load_address
+    }
+
+    // Runs one 60 Hz frame: applies the keys held in `input`, executes instructions until
+    // the frame's budget is spent or the program stops to wait, then signals vblank and
+    // ticks the timers
+    pub fn run_frame(&mut self, input: &KeyState) -> FrameOutput {
+        self.begin_frame(input);
+        let mut error = None;
+        while self.frame_has_time() {
+            if let Err(err) = self.frame_cycle() {
+                error = Some(err);
+                break;
+            }
+        }
+        FrameOutput {
+            error,
+            ..self.end_frame()
+        }
+    }
+
+    // `run_frame` in parts, for frontends that need to stop inside a frame, such as the
+    // debugger and the cycle-limited headless runner. A frame is `begin_frame`, then
+    // `frame_cycle` while `frame_has_time` holds, then `end_frame`.
+    pub fn begin_frame(&mut self, input: &KeyState) {
+        self.apply_input(input);
+        self.clock.start_frame();
+    }
+
+    pub fn frame_has_time(&self) -> bool {
+        self.clock.has_time()
+            && !self.exited
+            && !self.waiting_for_vblank
+            && !self.is_waiting_for_key()
+            && !self.waiting_for_release
+    }
+
+    // Executes one instruction, charging it to the current frame
+    pub fn frame_cycle(&mut self) -> Result<StepOutcome, Chip8Error> {
+        self.charged(Self::emulate_cycle)
+    }
+
+    pub fn end_frame(&mut self) -> FrameOutput {
+        let sound_on = self.sound_timer > 0;
+        self.vblank();
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+
+        FrameOutput {
+            display_changed: std::mem::take(&mut self.display_changed),
+            sound_on,
+            error: None,
+            ignored_accesses: std::mem::take(&mut self.ignored_accesses),
+        }
+    }
+
+    // Runs one instruction through `execute` and charges its cost to the frame. A draw
+    // left waiting for vblank has not run, so it is charged next frame when it does.
+    pub(crate) fn charged<T>(&mut self, execute: impl FnOnce(&mut Self) -> T) -> T {
+        // The cost depends on the state before the instruction runs
+        let cost = self.clock.cost(self);
+        let result = execute(self);
+        if !self.waiting_for_vblank {
+            self.clock.spend(cost);
+        }
+        result
+    }
+
+    // Turns the difference between `input` and the keys held last frame into presses and
+    // releases, which also satisfy a pending Fx0A
+    fn apply_input(&mut self, input: &KeyState) {
+        for key in 0..16 {
+            let pressed = input.is_key_pressed(key);
+            if pressed == self.input.is_key_pressed(key) {
+                continue;
+            }
+            if pressed {
+                if self.is_waiting_for_key() {
+                    self.resolve_key_wait(key);
+                } else {
+                    self.keypad.set_key_pressed(key, true);
+                }
+            } else {
+                if !self.is_waiting_for_key() && self.waiting_for_release {
+                    self.resolve_key_release();
+                }
+                self.keypad.set_key_pressed(key, false);
+            }
+        }
+        self.input = *input;
+    }
+
+    pub fn emulate_cycle(&mut self) -> Result<StepOutcome, Chip8Error> {
+        if self.exited {
+            return Ok(StepOutcome::Exited);
+        }
+        if self.waiting_for_vblank {
+            return Ok(StepOutcome::WaitingForDisplay);
+        }
+        // Fx0A holds the machine until a key is pressed and released, which only input does
+        if self.is_waiting_for_key() || self.waiting_for_release {
+            return Ok(StepOutcome::WaitingForKey);
+        }
+
         // Fetch
-        let raw_instruction = self.memory.read_instruction(self.program_counter);
-        let instruction = Instruction::from(raw_instruction);
+        let pc = self.program_counter;
+        let raw_instruction = self.read_word(pc as usize, pc)?;
+        let instruction = Instruction::try_from(raw_instruction).map_err(|err| err.at(pc))?;
+
+        if !self.quirks.xo_chip && instruction.requires_xo_chip() {
+            return Err(Chip8Error::UnsupportedInstruction {
+                opcode: raw_instruction,
+                pc,
+            });
+        }
 
         // Increment PC
-        self.program_counter += 2;
+        self.program_counter = self.program_counter.wrapping_add(2);
+
+        // An error leaves the PC on the instruction that raised it, so running on reports
+        // the same error instead of carrying on after a half-finished instruction
+        self.execute(instruction, pc)
+            .inspect_err(|_| self.program_counter = pc)
+    }
+
+    // Executes `instruction`, fetched from `pc`, with the PC already past it
+    fn execute(&mut self, instruction: Instruction, pc: u16) -> Result<StepOutcome, Chip8Error> {
+        let mut outcome = StepOutcome::Executed;
 
         // Decode & Execute
         match instruction {
+            Instruction::ScrollDown(n) => {
+                self.display.scroll_down(n.value() as usize);
+                self.display_changed = true;
+            }
+            Instruction::ScrollUp(n) => {
+                self.display.scroll_up(n.value() as usize);
+                self.display_changed = true;
+            }
             Instruction::Clear => {
                 self.display.clear();
+                self.display_changed = true;
+            }
+            Instruction::ScrollRight => {
+                self.display.scroll_right(4);
+                self.display_changed = true;
+            }
+            Instruction::ScrollLeft => {
+                self.display.scroll_left(4);
+                self.display_changed = true;
+            }
+            Instruction::Exit => {
+                self.exited = true;
+                outcome = StepOutcome::Exited;
+            }
+            Instruction::LowResolution => {
+                self.display.set_resolution(Resolution::Low);
+                self.display_changed = true;
+            }
+            Instruction::HighResolution => {
+                self.display.set_resolution(Resolution::High);
+                self.display_changed = true;
             }
             Instruction::Return => {
-                self.stack_pointer -= 1;
-                self.program_counter = self.stack[self.stack_pointer as usize];
+                self.program_counter = self
+                    .stack
+                    .pop(&self.memory)
+                    .ok_or(Chip8Error::StackUnderflow { pc })?;
             }
             Instruction::Jump(addr) => {
                 self.program_counter = addr;
             }
             Instruction::Call(addr) => {
-                self.stack[self.stack_pointer as usize] = self.program_counter;
-                self.stack_pointer += 1;
+                if !self.stack.push(self.program_counter, &mut self.memory) {
+                    return Err(Chip8Error::StackOverflow { pc });
+                }
                 self.program_counter = addr;
             }
             Instruction::SkipEqual(x, kk) => {
                 if self.registers.get(x) == kk {
-                    self.program_counter += 2;
+                    self.skip_next_instruction();
                 }
             }
             Instruction::SkipNotEqual(x, kk) => {
                 if self.registers.get(x) != kk {
-                    self.program_counter += 2;
+                    self.skip_next_instruction();
                 }
             }
             Instruction::SkipEqualRegister(x, y) => {
                 if self.registers.get(x) == self.registers.get(y) {
-                    self.program_counter += 2;
+                    self.skip_next_instruction();
                 }
             }
             Instruction::Load(x, kk) => {
@@ -105,6 +498,28 @@ impl Chip8 {
                 let current = self.registers.get(x);
                 self.registers.set(x, current.wrapping_add(kk));
             }
+            Instruction::StoreRegisterRange(x, y) => {
+                self.check_range(
+                    self.index_register as usize,
+                    register_range(x, y).count(),
+                    pc,
+                )?;
+                for (offset, register) in register_range(x, y).enumerate() {
+                    let value = self.registers.get(register);
+                    self.write_byte(self.index_register as usize + offset, value, pc)?;
+                }
+            }
+            Instruction::LoadRegisterRange(x, y) => {
+                self.check_range(
+                    self.index_register as usize,
+                    register_range(x, y).count(),
+                    pc,
+                )?;
+                for (offset, register) in register_range(x, y).enumerate() {
+                    let value = self.read_byte(self.index_register as usize + offset, pc)?;
+                    self.registers.set(register, value);
+                }
+            }
             Instruction::LoadRegister(x, y) => {
                 let value = self.registers.get(y);
                 self.registers.set(x, value);
@@ -112,17 +527,23 @@ impl Chip8 {
             Instruction::LoadOr(x, y) => {
                 let value = self.registers.get(x) | self.registers.get(y);
                 self.registers.set(x, value);
-                self.registers.set(RegisterIndex::try_from(0xF).unwrap(), 0);
+                if self.quirks.vf_reset {
+                    self.registers.set(RegisterIndex::try_from(0xF).unwrap(), 0);
+                }
             }
             Instruction::LoadAnd(x, y) => {
                 let value = self.registers.get(x) & self.registers.get(y);
                 self.registers.set(x, value);
-                self.registers.set(RegisterIndex::try_from(0xF).unwrap(), 0);
+                if self.quirks.vf_reset {
+                    self.registers.set(RegisterIndex::try_from(0xF).unwrap(), 0);
+                }
             }
             Instruction::LoadXor(x, y) => {
                 let value = self.registers.get(x) ^ self.registers.get(y);
                 self.registers.set(x, value);
-                self.registers.set(RegisterIndex::try_from(0xF).unwrap(), 0);
+                if self.quirks.vf_reset {
+                    self.registers.set(RegisterIndex::try_from(0xF).unwrap(), 0);
+                }
             }
             Instruction::LoadAdd(x, y) => {
                 let (result, carry) = self.registers.get(x).overflowing_add(self.registers.get(y));
@@ -141,7 +562,9 @@ impl Chip8 {
                 );
             }
             Instruction::LoadShiftRight(x, y) => {
-                self.registers.set(x, self.registers.get(y));
+                if self.quirks.shift_uses_vy {
+                    self.registers.set(x, self.registers.get(y));
+                }
                 let lsb = self.registers.get(x) & 0x1;
                 self.registers.set(x, self.registers.get(x) >> 1);
                 self.registers
@@ -156,7 +579,9 @@ impl Chip8 {
                 );
             }
             Instruction::LoadShiftLeft(x, y) => {
-                self.registers.set(x, self.registers.get(y));
+                if self.quirks.shift_uses_vy {
+                    self.registers.set(x, self.registers.get(y));
+                }
                 let msb = (self.registers.get(x) & 0x80) >> 7;
                 self.registers.set(x, self.registers.get(x) << 1);
                 self.registers
@@ -164,60 +589,104 @@ impl Chip8 {
             }
             Instruction::SkipNotEqualRegister(x, y) => {
                 if self.registers.get(x) != self.registers.get(y) {
-                    self.program_counter += 2;
+                    self.skip_next_instruction();
                 }
             }
             Instruction::LoadIndexRegister(addr) => {
                 self.index_register = addr;
             }
             Instruction::JumpWithOffset(addr) => {
-                let offset = self.registers.get(RegisterIndex::try_from(0).unwrap()) as u16;
+                let offset_register = if self.quirks.jump_uses_vx {
+                    ((addr & 0x0F00) >> 8) as u8
+                } else {
+                    0
+                };
+                let offset = self
+                    .registers
+                    .get(RegisterIndex::try_from(offset_register).unwrap())
+                    as u16;
                 self.program_counter = addr + offset;
             }
             Instruction::Random(x, kk) => {
-                let random_byte: u8 = rand::random();
+                let random_byte = self.random.next_byte();
                 self.registers.set(x, random_byte & kk);
             }
             Instruction::Display(x, y, nibble) => {
-                if !first_cycle_of_frame {
-                    // To prevent multiple draw instructions in a single frame from
-                    // interfering with each other, we skip all but the first one.
-                    self.program_counter -= 2;
+                if self.quirks.display_wait && !self.vblank_occurred {
+                    // The VIP interpreter waits for the display interrupt before drawing,
+                    // so at most one sprite is drawn per frame
+                    self.program_counter = pc;
+                    self.waiting_for_vblank = true;
+                    return Ok(StepOutcome::WaitingForDisplay);
                 } else {
-                    let x = self.registers.get(x) as usize % 64;
-                    let y = self.registers.get(y) as usize % 32;
-                    let height = nibble.value() as usize;
+                    let x = self.registers.get(x) as usize;
+                    let y = self.registers.get(y) as usize;
 
-                    self.registers.set(RegisterIndex::try_from(0xF).unwrap(), 0);
+                    // Dxy0 draws a 16x16 sprite stored as 32 bytes, two per row
+                    let (height, width) = match nibble.value() {
+                        0 => (16, 16),
+                        n => (n as usize, 8),
+                    };
+                    let bytes_per_row = width / 8;
+                    let sprite_size = height * bytes_per_row * self.display.selected().count();
+                    self.check_range(self.index_register as usize, sprite_size, pc)?;
+                    self.display_changed = true;
 
-                    for row in 0..height {
-                        let sprite_byte = self.memory.read_byte(self.index_register + row as u16);
-                        for col in 0..8 {
-                            if (sprite_byte & (0x80 >> col)) != 0 {
-                                let pixel_x = x + col;
-                                let pixel_y = y + row;
-                                if pixel_x < 64 && pixel_y < 32 {
-                                    if self.display.toggle_pixel(pixel_x, pixel_y) {
-                                        self.registers
-                                            .set(RegisterIndex::try_from(0xF).unwrap(), 1);
-                                    }
-                                }
+                    // With several planes selected, each plane's sprite data follows the previous one
+                    let mut address = self.index_register as usize;
+                    let mut collision = false;
+                    for plane in self.display.selected() {
+                        let mut rows = Vec::with_capacity(height);
+                        for row in 0..height {
+                            let row_address = address + row * bytes_per_row;
+                            let mut bits = 0;
+                            for byte in 0..bytes_per_row {
+                                bits = (bits << 8) | self.read_byte(row_address + byte, pc)? as u16;
                             }
+                            rows.push(bits);
                         }
+                        collision |= self.display.draw_sprite(
+                            plane,
+                            x,
+                            y,
+                            &rows,
+                            width,
+                            self.quirks.sprite_wrap,
+                        );
+                        address += height * bytes_per_row;
                     }
+
+                    self.registers.set(
+                        RegisterIndex::try_from(0xF).unwrap(),
+                        if collision { 1 } else { 0 },
+                    );
                 }
             }
             Instruction::SkipKeyPress(x) => {
                 let key = self.registers.get(x);
                 if self.keypad.is_key_pressed(key) {
-                    self.program_counter += 2;
+                    self.skip_next_instruction();
                 }
             }
             Instruction::SkipKeyNotPress(x) => {
                 let key = self.registers.get(x);
                 if !self.keypad.is_key_pressed(key) {
-                    self.program_counter += 2;
+                    self.skip_next_instruction();
+                }
+            }
+            Instruction::LoadIndexLong => {
+                self.index_register = self.read_word(self.program_counter as usize, pc)?;
+                self.program_counter = self.program_counter.wrapping_add(2);
+            }
+            Instruction::SelectPlanes(n) => {
+                self.display.select_planes(n.value());
+            }
+            Instruction::LoadAudioPattern => {
+                let mut pattern = [0; AUDIO_PATTERN_SIZE];
+                for (offset, byte) in pattern.iter_mut().enumerate() {
+                    *byte = self.read_byte(self.index_register as usize + offset, pc)?;
                 }
+                self.audio_pattern = Some(pattern);
             }
             Instruction::LoadDelayTimer(x) => {
                 let value = self.delay_timer;
@@ -226,6 +695,7 @@ impl Chip8 {
             Instruction::LoadKeyPress(x) => {
                 self.waiting_for_key = Some(x);
                 self.waiting_for_release = true;
+                outcome = StepOutcome::WaitingForKey;
             }
             Instruction::StoreDelayTimer(x) => {
                 let value = self.registers.get(x);
@@ -243,34 +713,125 @@ impl Chip8 {
                 let value = self.registers.get(x);
                 self.index_register = FONTSET_START_ADDRESS as u16 + (value as u16 * 5);
             }
+            Instruction::LoadBigFontCharacter(x) => {
+                let value = self.registers.get(x);
+                self.index_register = BIG_FONTSET_START_ADDRESS as u16 + (value as u16 * 10);
+            }
             Instruction::LoadBinaryCodedDecimal(x) => {
                 let value = self.registers.get(x);
-                self.memory
-                    .write_byte(self.index_register, (value / 100) % 10);
-                self.memory
-                    .write_byte(self.index_register + 1, (value % 100) / 10);
-                self.memory.write_byte(self.index_register + 2, value % 10);
+                let address = self.index_register as usize;
+                self.check_range(address, 3, pc)?;
+                self.write_byte(address, (value / 100) % 10, pc)?;
+                self.write_byte(address + 1, (value % 100) / 10, pc)?;
+                self.write_byte(address + 2, value % 10, pc)?;
+            }
+            Instruction::LoadPitch(x) => {
+                self.pitch = self.registers.get(x);
             }
             Instruction::StoreRegisters(x) => {
+                self.check_range(self.index_register as usize, x.value() + 1, pc)?;
                 for i in 0..=x.value() {
                     let value = self
                         .registers
                         .get(RegisterIndex::try_from(i as u8).unwrap());
-                    self.memory.write_byte(self.index_register, value);
-                    self.index_register += 1;
+                    self.write_byte(self.index_register as usize + i, value, pc)?;
+                }
+                if self.quirks.load_store_increments_index {
+                    self.index_register = self.index_register.wrapping_add(x.value() as u16 + 1);
                 }
             }
             Instruction::LoadRegisters(x) => {
+                self.check_range(self.index_register as usize, x.value() + 1, pc)?;
                 for i in 0..=x.value() {
-                    let value = self.memory.read_byte(self.index_register);
+                    let value = self.read_byte(self.index_register as usize + i, pc)?;
                     self.registers
                         .set(RegisterIndex::try_from(i as u8).unwrap(), value);
-                    self.index_register += 1;
                 }
+                if self.quirks.load_store_increments_index {
+                    self.index_register = self.index_register.wrapping_add(x.value() as u16 + 1);
+                }
+            }
+            Instruction::StoreFlags(x) => {
+                for i in 0..=x.value() {
+                    self.rpl_flags[i] = self
+                        .registers
+                        .get(RegisterIndex::try_from(i as u8).unwrap());
+                }
+            }
+            Instruction::LoadFlags(x) => {
+                for i in 0..=x.value() {
+                    self.registers
+                        .set(RegisterIndex::try_from(i as u8).unwrap(), self.rpl_flags[i]);
+                }
+            }
+        }
+
+        self.vblank_occurred = false;
+        Ok(outcome)
+    }
+
+    // Skips the next instruction, which in XO-CHIP mode may be the four-byte F000 nnnn
+    fn skip_next_instruction(&mut self) {
+        let next = self.memory.read_instruction(self.program_counter as usize);
+        let length = if self.quirks.xo_chip && next == 0xF000 {
+            4
+        } else {
+            2
+        };
+        self.program_counter = self.program_counter.wrapping_add(length);
+    }
+
+    // Applies the memory policy to an access at `address` by the instruction at `pc`,
+    // giving the address to use, or None when the access is to be skipped
+    fn map_address(&mut self, address: usize, pc: u16) -> Result<Option<usize>, Chip8Error> {
+        let size = self.memory.size();
+        if address < size {
+            return Ok(Some(address));
+        }
+        let error = Chip8Error::MemoryOutOfBounds { address, pc };
+        match self.memory_policy {
+            MemoryPolicy::Wrap => Ok(Some(address % size)),
+            MemoryPolicy::Fault => Err(error),
+            MemoryPolicy::Ignore => {
+                self.ignored_accesses.push(error);
+                Ok(None)
             }
         }
     }
 
+    // Under the fault policy, fails unless all `len` bytes from `address` are in memory.
+    // Instructions that touch several bytes check first, so they fault before changing
+    // anything rather than part way through.
+    fn check_range(&self, address: usize, len: usize, pc: u16) -> Result<(), Chip8Error> {
+        let size = self.memory.size();
+        if self.memory_policy == MemoryPolicy::Fault && address + len > size {
+            return Err(Chip8Error::MemoryOutOfBounds {
+                address: address.max(size),
+                pc,
+            });
+        }
+        Ok(())
+    }
+
+    fn read_byte(&mut self, address: usize, pc: u16) -> Result<u8, Chip8Error> {
+        Ok(self
+            .map_address(address, pc)?
+            .map_or(0, |address| self.memory.read_byte(address)))
+    }
+
+    fn read_word(&mut self, address: usize, pc: u16) -> Result<u16, Chip8Error> {
+        let high_byte = self.read_byte(address, pc)? as u16;
+        let low_byte = self.read_byte(address + 1, pc)? as u16;
+        Ok((high_byte << 8) | low_byte)
+    }
+
+    fn write_byte(&mut self, address: usize, value: u8, pc: u16) -> Result<(), Chip8Error> {
+        if let Some(address) = self.map_address(address, pc)? {
+            self.memory.write_byte(address, value);
+        }
+        Ok(())
+    }
+
     pub fn resolve_key_wait(&mut self, key: u8) {
         if let Some(x) = self.waiting_for_key.take() {
             self.registers.set(x, key);
@@ -282,3 +843,438 @@ impl Chip8 {
         self.waiting_for_release = false;
     }
 }
+
+// Registers Vx through Vy inclusive, walking backwards when x > y as XO-CHIP specifies
+fn register_range(x: RegisterIndex, y: RegisterIndex) -> impl Iterator<Item = RegisterIndex> {
+    let (x, y) = (x.value() as u8, y.value() as u8);
+    let indices: Vec<u8> = if x <= y {
+        (x..=y).collect()
+    } else {
+        (y..=x).rev().collect()
+    };
+    indices
+        .into_iter()
+        .map(|i| RegisterIndex::try_from(i).unwrap())
+}
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Self::new(Quirks::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A machine with `program` loaded at 0x200
+    fn chip8_with(quirks: Quirks, program: &[u16]) -> Chip8 {
+        let data = program.iter().flat_map(|word| word.to_be_bytes()).collect();
+        let mut chip8 = Chip8::new(quirks);
+        chip8.load_rom(&Rom::new(data)).unwrap();
+        chip8
+    }
+
+    fn register(chip8: &Chip8, n: u8) -> u8 {
+        chip8.registers.get(RegisterIndex::try_from(n).unwrap())
+    }
+
+    // Runs `program` for as many instructions as it has
+    fn run(quirks: Quirks, program: &[u16]) -> Chip8 {
+        let mut chip8 = chip8_with(quirks, program);
+        for _ in program {
+            assert_eq!(chip8.emulate_cycle(), Ok(StepOutcome::Executed));
+        }
+        chip8
+    }
+
+    // SUPER-CHIP with one quirk changed
+    fn schip_with(change: impl FnOnce(&mut Quirks)) -> Quirks {
+        let mut quirks = Quirks::SUPER_CHIP;
+        change(&mut quirks);
+        quirks
+    }
+
+    #[test]
+    fn shifts_read_vy_only_with_the_shift_quirk() {
+        // V1 = 0b101, V2 = 0b110, then shift V1 by V2
+        let right = [0x6105, 0x6206, 0x8126];
+        let left = [0x6105, 0x6286, 0x812E];
+        let vy = schip_with(|quirks| quirks.shift_uses_vy = true);
+
+        let chip8 = run(Quirks::SUPER_CHIP, &right);
+        assert_eq!((register(&chip8, 1), register(&chip8, 0xF)), (0b10, 1));
+        let chip8 = run(vy, &right);
+        assert_eq!((register(&chip8, 1), register(&chip8, 0xF)), (0b11, 0));
+        let chip8 = run(Quirks::SUPER_CHIP, &left);
+        assert_eq!((register(&chip8, 1), register(&chip8, 0xF)), (0b1010, 0));
+        let chip8 = run(vy, &left);
+        assert_eq!((register(&chip8, 1), register(&chip8, 0xF)), (0b1100, 1));
+    }
+
+    #[test]
+    fn register_stores_and_loads_move_i_only_with_the_load_store_quirk() {
+        let program = [0xA300, 0xF255, 0xF165];
+        let increments = schip_with(|quirks| quirks.load_store_increments_index = true);
+
+        assert_eq!(run(Quirks::SUPER_CHIP, &program).index_register, 0x300);
+        assert_eq!(run(increments, &program).index_register, 0x305);
+    }
+
+    #[test]
+    fn logic_resets_vf_only_with_the_vf_reset_quirk() {
+        let resets = schip_with(|quirks| quirks.vf_reset = true);
+        for operation in [0x8011, 0x8012, 0x8013] {
+            let program = [0x6F07, 0x6003, 0x6106, operation];
+            assert_eq!(register(&run(Quirks::SUPER_CHIP, &program), 0xF), 7);
+            assert_eq!(register(&run(resets, &program), 0xF), 0);
+        }
+    }
+
+    #[test]
+    fn jumps_add_vx_only_with_the_jump_quirk() {
+        // B206 adds V0 = 2, or V2 = 4
+        let program = [0x6002, 0x6204, 0xB206];
+        let v0 = schip_with(|quirks| quirks.jump_uses_vx = false);
+
+        assert_eq!(run(Quirks::SUPER_CHIP, &program).program_counter, 0x20A);
+        assert_eq!(run(v0, &program).program_counter, 0x208);
+    }
+
+    #[test]
+    fn sprites_wrap_only_with_the_wrap_quirk() {
+        // The "0" glyph, four pixels wide, drawn three pixels from the bottom right corner
+        let program = [0x603D, 0x611D, 0x6200, 0xF229, 0xD015];
+        let lit = |chip8: &Chip8, x: usize, y: usize| chip8.display.get_buffer()[y][x];
+
+        let clipped = run(Quirks::SUPER_CHIP, &program);
+        assert!(lit(&clipped, 63, 29) && lit(&clipped, 61, 31));
+        assert!(!lit(&clipped, 0, 29) && !lit(&clipped, 61, 0));
+
+        let wrapped = run(schip_with(|quirks| quirks.sprite_wrap = true), &program);
+        assert!(lit(&wrapped, 63, 29) && lit(&wrapped, 61, 31));
+        assert!(lit(&wrapped, 0, 29) && lit(&wrapped, 61, 0));
+
+        // Positions past the edge wrap with either setting
+        let program = [0x6041, 0x6121, 0x6200, 0xF229, 0xD015];
+        assert!(lit(&run(Quirks::SUPER_CHIP, &program), 1, 1));
+    }
+
+    fn lit(chip8: &Chip8, x: usize, y: usize) -> bool {
+        chip8.display.get_buffer()[y][x]
+    }
+
+    #[test]
+    fn hires_switches_clear_the_screen() {
+        let program = [0xF029, 0xD005, 0x00FF, 0xD005, 0x00FE];
+        let mut chip8 = chip8_with(Quirks::SUPER_CHIP, &program);
+        for _ in 0..3 {
+            chip8.emulate_cycle().unwrap();
+        }
+        assert_eq!(chip8.display.resolution(), Resolution::High);
+        assert!(!lit(&chip8, 0, 0));
+        assert_eq!(chip8.display.width(), 128);
+
+        chip8.emulate_cycle().unwrap();
+        assert!(lit(&chip8, 0, 0));
+        chip8.emulate_cycle().unwrap();
+        assert_eq!(chip8.display.resolution(), Resolution::Low);
+        assert!(!lit(&chip8, 0, 0));
+    }
+
+    #[test]
+    fn dxy0_draws_16x16_sprites() {
+        // Rows of the sprite at 0x20C: a pixel at each end of the first row, then solid
+        let mut data: Vec<u8> = [0x00FF, 0xA20C, 0x6010, 0xD000, 0xD000, 0x120A]
+            .iter()
+            .flat_map(|word: &u16| word.to_be_bytes())
+            .collect();
+        data.extend([0x80, 0x01]);
+        data.extend([0xFF; 30]);
+        let mut chip8 = Chip8::new(Quirks::SUPER_CHIP);
+        chip8.load_rom(&Rom::new(data)).unwrap();
+        for _ in 0..4 {
+            chip8.emulate_cycle().unwrap();
+        }
+
+        assert!(lit(&chip8, 16, 16) && lit(&chip8, 31, 16));
+        assert!(!lit(&chip8, 17, 16) && !lit(&chip8, 30, 16));
+        assert!(lit(&chip8, 16, 31) && lit(&chip8, 31, 31));
+        assert!(!lit(&chip8, 32, 17) && !lit(&chip8, 16, 32));
+        assert_eq!(register(&chip8, 0xF), 0);
+
+        chip8.emulate_cycle().unwrap();
+        assert!(!lit(&chip8, 16, 16));
+        assert_eq!(register(&chip8, 0xF), 1);
+    }
+
+    #[test]
+    fn scrolling_moves_the_picture() {
+        // Lights the top-left pixel of the glyph "0" at (8, 8), then scrolls it around
+        let program = [0x6008, 0x6200, 0xF229, 0xD005];
+        let scrolled = |scrolls: &[u16]| run(Quirks::SUPER_CHIP, &[&program, scrolls].concat());
+
+        assert!(lit(&scrolled(&[0x00C3]), 8, 11));
+        assert!(lit(&scrolled(&[0x00FB]), 12, 8));
+        assert!(lit(&scrolled(&[0x00FC]), 4, 8));
+        assert!(!lit(&scrolled(&[0x00FC]), 8, 8));
+        // Rows scrolled off the edge are gone, not wrapped
+        let gone = scrolled(&[0x00CF, 0x00CF]);
+        assert!((0..32).all(|y| !lit(&gone, 8, y)));
+    }
+
+    #[test]
+    fn exit_stops_the_program() {
+        let mut chip8 = chip8_with(Quirks::SUPER_CHIP, &[0x00FD, 0x6001]);
+        assert_eq!(chip8.emulate_cycle(), Ok(StepOutcome::Exited));
+        assert_eq!(chip8.emulate_cycle(), Ok(StepOutcome::Exited));
+        assert!(chip8.has_exited());
+        assert_eq!(register(&chip8, 0), 0);
+    }
+
+    fn lit_on(chip8: &Chip8, plane: usize, x: usize, y: usize) -> bool {
+        chip8.display.get_plane(plane)[y][x]
+    }
+
+    fn bytes_at(chip8: &Chip8, address: u16, count: u16) -> Vec<u8> {
+        (address..address + count)
+            .map(|address| chip8.memory.read_byte(address as usize))
+            .collect()
+    }
+
+    #[test]
+    fn planes_select_where_sprites_draw() {
+        // The "0" glyph on the second plane only, then on both: the first plane's rows come
+        // from I and the second plane's from the five bytes after them
+        let chip8 = run(Quirks::XO_CHIP, &[0xF201, 0x6000, 0xF029, 0xD005]);
+        assert!(!lit_on(&chip8, 0, 0, 0) && lit_on(&chip8, 1, 0, 0));
+        assert_eq!(chip8.display.pixel_color(0, 0), 2);
+
+        let chip8 = run(Quirks::XO_CHIP, &[0xF301, 0x6000, 0xF029, 0xD005]);
+        // Row 2 of "0" is 1001; the second plane gets row 2 of "1", 0010
+        assert!(lit_on(&chip8, 0, 0, 2) && !lit_on(&chip8, 1, 0, 2));
+        assert!(!lit_on(&chip8, 0, 2, 2) && lit_on(&chip8, 1, 2, 2));
+
+        // Clearing and scrolling only touch the selected planes
+        let chip8 = run(
+            Quirks::XO_CHIP,
+            &[
+                0xF301, 0x6000, 0xF029, 0xD005, 0xF101, 0x00E0, 0xF201, 0x00D1,
+            ],
+        );
+        assert!((0..5).all(|y| !lit_on(&chip8, 0, 0, y)));
+        assert!(lit_on(&chip8, 1, 1, 0) && !lit_on(&chip8, 1, 1, 1));
+    }
+
+    #[test]
+    fn register_ranges_store_and_load_in_either_order() {
+        let setup = [0xA300, 0x6101, 0x6202, 0x6303];
+        let chip8 = run(Quirks::XO_CHIP, &[&setup[..], &[0x5132]].concat());
+        assert_eq!(bytes_at(&chip8, 0x300, 3), [1, 2, 3]);
+        // Unlike Fx55, I stays put
+        assert_eq!(chip8.index_register, 0x300);
+        let chip8 = run(Quirks::XO_CHIP, &[&setup[..], &[0x5312]].concat());
+        assert_eq!(bytes_at(&chip8, 0x300, 3), [3, 2, 1]);
+
+        let chip8 = run(Quirks::XO_CHIP, &[&setup[..], &[0x5132, 0x5573]].concat());
+        assert_eq!(
+            (
+                register(&chip8, 5),
+                register(&chip8, 6),
+                register(&chip8, 7)
+            ),
+            (1, 2, 3)
+        );
+        let chip8 = run(Quirks::XO_CHIP, &[&setup[..], &[0x5132, 0x5A83]].concat());
+        assert_eq!(
+            (
+                register(&chip8, 0xA),
+                register(&chip8, 9),
+                register(&chip8, 8)
+            ),
+            (1, 2, 3)
+        );
+    }
+
+    #[test]
+    fn skips_step_over_all_four_bytes_of_long_loads() {
+        let program = [0x3000, 0xF000, 0x1234, 0x6101];
+        let mut chip8 = chip8_with(Quirks::XO_CHIP, &program);
+        chip8.emulate_cycle().unwrap();
+        assert_eq!(chip8.program_counter, 0x206);
+        chip8.emulate_cycle().unwrap();
+        assert_eq!(register(&chip8, 1), 1);
+        assert_eq!(chip8.index_register, 0);
+
+        let mut chip8 = chip8_with(Quirks::XO_CHIP, &[0x3001, 0xF000, 0x1234, 0x6101]);
+        chip8.emulate_cycle().unwrap();
+        chip8.emulate_cycle().unwrap();
+        assert_eq!(chip8.index_register, 0x1234);
+        assert_eq!(chip8.program_counter, 0x206);
+    }
+
+    #[test]
+    fn xo_chip_instructions_need_xo_chip_mode() {
+        let mut chip8 = chip8_with(Quirks::SUPER_CHIP, &[0xF000, 0x1234]);
+        assert_eq!(
+            chip8.emulate_cycle(),
+            Err(Chip8Error::UnsupportedInstruction {
+                opcode: 0xF000,
+                pc: 0x200,
+            })
+        );
+
+        // Long loads reach the whole 64K
+        let mut chip8 = chip8_with(Quirks::XO_CHIP, &[0xF000, 0xFFF0, 0x6007, 0xF055]);
+        for _ in 0..3 {
+            chip8.emulate_cycle().unwrap();
+        }
+        assert_eq!(chip8.memory.read_byte(0xFFF0), 7);
+    }
+
+    #[test]
+    fn frames_run_their_budget_and_tick_the_timers() {
+        // Starts the delay and sound timers at 3, then counts in V1
+        let program = [0x6003, 0xF015, 0xF018, 0x7101, 0x1206];
+        let mut chip8 = chip8_with(Quirks::default(), &program);
+
+        let output = chip8.run_frame(&KeyState::new());
+        assert_eq!(register(&chip8, 1), 44);
+        assert_eq!((chip8.delay_timer(), chip8.sound_timer()), (2, 2));
+        assert!(output.sound_on && !output.display_changed);
+        assert_eq!(output.error, None);
+
+        chip8.run_frame(&KeyState::new());
+        chip8.run_frame(&KeyState::new());
+        let output = chip8.run_frame(&KeyState::new());
+        assert_eq!(register(&chip8, 1), 44 + 3 * 45);
+        assert_eq!((chip8.delay_timer(), chip8.sound_timer()), (0, 0));
+        assert!(!output.sound_on);
+
+        // An error ends the frame and is reported with it
+        let mut chip8 = chip8_with(Quirks::default(), &[0x6001, 0x0000]);
+        let output = chip8.run_frame(&KeyState::new());
+        assert!(matches!(output.error, Some(Chip8Error::Decode(_))));
+        assert_eq!(chip8.program_counter(), 0x202);
+    }
+
+    // Stores V0 = 123 as BCD at I = 0xFFF, the last byte of memory
+    fn bcd_at_end_of_memory(policy: MemoryPolicy) -> (Chip8, Result<StepOutcome, Chip8Error>) {
+        let mut chip8 = chip8_with(Quirks::default(), &[0xAFFF, 0x607B, 0xF033]);
+        chip8.set_memory_policy(policy);
+        chip8.emulate_cycle().unwrap();
+        chip8.emulate_cycle().unwrap();
+        let result = chip8.emulate_cycle();
+        (chip8, result)
+    }
+
+    // Runs to the instruction at 0xFFE, which leaves the PC past the end of memory. On the
+    // way it writes 6507 (LD V5, 7) at address 0 for a wrapped fetch to find.
+    fn run_to_end_of_memory(policy: MemoryPolicy) -> Chip8 {
+        let mut data = vec![0; 0x1000 - ROM_START_ADDRESS];
+        let program: [u16; 5] = [0xA000, 0x6065, 0x6107, 0xF155, 0x1FFE];
+        for (i, word) in program.iter().enumerate() {
+            data[i * 2..i * 2 + 2].copy_from_slice(&word.to_be_bytes());
+        }
+        let end = data.len();
+        data[end - 2..].copy_from_slice(&[0x6A, 0x01]);
+        let mut chip8 = Chip8::default();
+        chip8.load_rom(&Rom::new(data)).unwrap();
+        chip8.set_memory_policy(policy);
+        for _ in 0..program.len() + 1 {
+            chip8.emulate_cycle().unwrap();
+        }
+        assert_eq!(chip8.program_counter(), 0x1000);
+        assert_eq!(register(&chip8, 0xA), 1);
+        chip8
+    }
+
+    #[test]
+    fn wrapped_writes_continue_at_address_zero() {
+        let (chip8, result) = bcd_at_end_of_memory(MemoryPolicy::Wrap);
+        assert_eq!(result, Ok(StepOutcome::Executed));
+        assert_eq!(chip8.memory()[0xFFF], 1);
+        assert_eq!(chip8.memory()[0x000], 2);
+        assert_eq!(chip8.memory()[0x001], 3);
+    }
+
+    #[test]
+    fn ignored_writes_skip_the_bytes_past_the_end() {
+        let (chip8, result) = bcd_at_end_of_memory(MemoryPolicy::Ignore);
+        assert_eq!(result, Ok(StepOutcome::Executed));
+        assert_eq!(chip8.memory()[0xFFF], 1);
+        assert_eq!(chip8.memory()[0x000], 0);
+        assert_eq!(chip8.memory()[0x001], 0);
+        assert_eq!(chip8.ignored_accesses.len(), 2);
+        assert_eq!(chip8.program_counter(), 0x206);
+    }
+
+    #[test]
+    fn wrapped_fetches_continue_at_address_zero() {
+        let mut chip8 = run_to_end_of_memory(MemoryPolicy::Wrap);
+        assert_eq!(chip8.emulate_cycle(), Ok(StepOutcome::Executed));
+        assert_eq!(register(&chip8, 5), 7);
+    }
+
+    #[test]
+    fn ignored_fetches_read_zero() {
+        let mut chip8 = run_to_end_of_memory(MemoryPolicy::Ignore);
+        // 0000 is not an instruction, so the program stops there instead of running memory
+        // from address 0
+        assert!(matches!(chip8.emulate_cycle(), Err(Chip8Error::Decode(_))));
+        assert_eq!(chip8.ignored_accesses.len(), 2);
+        assert_eq!(register(&chip8, 5), 0);
+    }
+
+    #[test]
+    fn key_wait_holds_until_a_key_is_pressed_and_released() {
+        let mut chip8 = chip8_with(Quirks::default(), &[0xF00A, 0x6101, 0x1204]);
+        assert_eq!(chip8.emulate_cycle(), Ok(StepOutcome::WaitingForKey));
+        assert_eq!(chip8.emulate_cycle(), Ok(StepOutcome::WaitingForKey));
+        assert_eq!(chip8.program_counter(), 0x202);
+        assert_eq!(
+            chip8.registers().get(RegisterIndex::try_from(1).unwrap()),
+            0
+        );
+
+        let mut keys = KeyState::new();
+        keys.set_key_pressed(0x7, true);
+        chip8.begin_frame(&keys);
+        assert_eq!(chip8.emulate_cycle(), Ok(StepOutcome::WaitingForKey));
+        chip8.begin_frame(&KeyState::new());
+        assert_eq!(chip8.emulate_cycle(), Ok(StepOutcome::Executed));
+        assert_eq!(
+            chip8.registers().get(RegisterIndex::try_from(0).unwrap()),
+            0x7
+        );
+        assert_eq!(
+            chip8.registers().get(RegisterIndex::try_from(1).unwrap()),
+            1
+        );
+    }
+
+    #[test]
+    fn faulting_writes_change_nothing_and_fault_again() {
+        let (mut chip8, result) = bcd_at_end_of_memory(MemoryPolicy::Fault);
+        let fault = Chip8Error::MemoryOutOfBounds {
+            address: 0x1000,
+            pc: 0x204,
+        };
+        assert_eq!(result, Err(fault.clone()));
+        assert_eq!(chip8.memory()[0xFFF], 0);
+        assert_eq!(chip8.program_counter(), 0x204);
+        assert_eq!(chip8.emulate_cycle(), Err(fault));
+    }
+
+    #[test]
+    fn faulting_fetches_fault_again() {
+        let mut chip8 = run_to_end_of_memory(MemoryPolicy::Fault);
+        let fault = Chip8Error::MemoryOutOfBounds {
+            address: 0x1000,
+            pc: 0x1000,
+        };
+        assert_eq!(chip8.emulate_cycle(), Err(fault.clone()));
+        assert_eq!(chip8.program_counter(), 0x1000);
+        assert_eq!(chip8.emulate_cycle(), Err(fault));
+    }
+}
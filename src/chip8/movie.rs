@@ -0,0 +1,311 @@
+use std::fmt;
+
+use super::quirks::Quirks;
+use super::rom::{Rom, RomError};
+use super::savestate::{ROM_HASH_SIZE, SaveStateError, StateReader, StateWriter};
+use super::timing::TimingModel;
+use super::{Chip8, KeyState, MemoryPolicy, NUM_RPL_FLAGS, StackDepth};
+
+// Movie layout, all multi-byte values little-endian:
+//   magic "C8MV", format version (u16), SHA-1 of the ROM (20 bytes), its load address
+//   (u16), quirks bitfield (u8), timing model (u8: 0 fixed, 1 VIP) and its instructions
+//   per frame (u32), random seed (u64), RPL flags (16 bytes), memory policy (u8: 0 wrap,
+//   1 fault, 2 ignore), stack depth (u8: 0 limited, 1 unlimited) and its limit (u32),
+//   whether the stack is in memory (u8), frame count (u32), then the held keys of each
+//   frame as a u16 with bit n set while key n is down
+const MAGIC: &[u8; 4] = b"C8MV";
+pub const MOVIE_VERSION: u16 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieError {
+    // The data does not start with the movie magic
+    NotAMovie,
+    UnsupportedVersion(u16),
+    // The movie was recorded with a different ROM
+    RomMismatch,
+    // The ROM could not be loaded to replay the movie
+    Rom(RomError),
+    Truncated,
+    Invalid(&'static str),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::NotAMovie => write!(f, "not a movie file"),
+            MovieError::UnsupportedVersion(version) => write!(
+                f,
+                "movie version {} is not supported (expected {})",
+                version, MOVIE_VERSION
+            ),
+            MovieError::RomMismatch => write!(f, "movie was recorded with a different ROM"),
+            MovieError::Rom(err) => write!(f, "{}", err),
+            MovieError::Truncated => write!(f, "movie is truncated"),
+            MovieError::Invalid(what) => write!(f, "movie has an invalid {}", what),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<SaveStateError> for MovieError {
+    fn from(err: SaveStateError) -> Self {
+        match err {
+            SaveStateError::Truncated => MovieError::Truncated,
+            _ => MovieError::Invalid("field"),
+        }
+    }
+}
+
+// A recording of the keys held in every frame of a run, along with everything else that
+// decides how the run goes, so that replaying it reproduces the run exactly. Recording
+// starts from a freshly loaded ROM; loading a save state part way through breaks the replay.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: [u8; ROM_HASH_SIZE],
+    pub load_address: usize,
+    pub quirks: Quirks,
+    pub timing: TimingModel,
+    pub seed: u64,
+    pub rpl_flags: [u8; NUM_RPL_FLAGS],
+    pub memory_policy: MemoryPolicy,
+    pub stack_depth: StackDepth,
+    pub stack_in_memory: bool,
+    pub frames: Vec<KeyState>,
+}
+
+impl Movie {
+    // Starts a recording of `chip8`, which must have just loaded its ROM. Cxkk is seeded
+    // with `seed` so the replay draws the same numbers.
+    pub fn record(chip8: &mut Chip8, seed: u64) -> Self {
+        chip8.seed_random(seed);
+        Self {
+            rom_hash: *chip8.rom_hash(),
+            load_address: chip8.load_address(),
+            quirks: chip8.quirks(),
+            timing: chip8.timing(),
+            seed,
+            rpl_flags: *chip8.rpl_flags(),
+            memory_policy: chip8.memory_policy(),
+            stack_depth: chip8.stack_depth(),
+            stack_in_memory: chip8.stack_in_memory(),
+            frames: Vec::new(),
+        }
+    }
+
+    // Adds the keys passed to `Chip8::run_frame` for the next frame
+    pub fn push_frame(&mut self, input: &KeyState) {
+        self.frames.push(*input);
+    }
+
+    // The keys to pass for frame `frame`, or None once the movie has run out
+    pub fn frame(&self, frame: usize) -> Option<&KeyState> {
+        self.frames.get(frame)
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    // Builds the machine the movie was recorded on, ready to replay frame 0. The ROM is
+    // loaded where it was when recording, wherever `rom` says.
+    pub fn start(&self, rom: &Rom) -> Result<Chip8, MovieError> {
+        if *rom.sha1() != self.rom_hash {
+            return Err(MovieError::RomMismatch);
+        }
+        let mut chip8 = Chip8::new(self.quirks);
+        chip8
+            .load_rom(&rom.clone().with_load_address(self.load_address))
+            .map_err(MovieError::Rom)?;
+        chip8.set_timing(self.timing);
+        chip8.seed_random(self.seed);
+        chip8.set_rpl_flags(&self.rpl_flags);
+        chip8.set_memory_policy(self.memory_policy);
+        chip8.set_stack_depth(self.stack_depth);
+        chip8.set_stack_in_memory(self.stack_in_memory);
+        Ok(chip8)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+
+        writer.bytes(MAGIC);
+        writer.u16(MOVIE_VERSION);
+        writer.bytes(&self.rom_hash);
+        writer.u16(self.load_address as u16);
+        writer.u8(self.quirks.to_bits());
+        match self.timing {
+            TimingModel::Fixed(instructions) => {
+                writer.u8(0);
+                writer.u32(instructions);
+            }
+            TimingModel::CosmacVip => {
+                writer.u8(1);
+                writer.u32(0);
+            }
+        }
+        writer.u64(self.seed);
+        writer.bytes(&self.rpl_flags);
+        writer.u8(match self.memory_policy {
+            MemoryPolicy::Wrap => 0,
+            MemoryPolicy::Fault => 1,
+            MemoryPolicy::Ignore => 2,
+        });
+        match self.stack_depth {
+            StackDepth::Limited(depth) => {
+                writer.u8(0);
+                writer.u32(depth as u32);
+            }
+            StackDepth::Unlimited => {
+                writer.u8(1);
+                writer.u32(0);
+            }
+        }
+        writer.bool(self.stack_in_memory);
+        writer.u32(self.frames.len() as u32);
+        for keys in &self.frames {
+            writer.u16(keys.to_bits());
+        }
+
+        writer.into_bytes()
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, MovieError> {
+        let mut reader = StateReader::new(data);
+
+        if reader
+            .bytes(MAGIC.len())
+            .map_err(|_| MovieError::NotAMovie)?
+            != MAGIC
+        {
+            return Err(MovieError::NotAMovie);
+        }
+        let version = reader.u16()?;
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        let rom_hash = reader.array()?;
+        let load_address = reader.u16()? as usize;
+        let quirks = Quirks::from_bits(reader.u8()?);
+        let timing = match (reader.u8()?, reader.u32()?) {
+            (0, instructions) => TimingModel::Fixed(instructions),
+            (1, _) => TimingModel::CosmacVip,
+            _ => return Err(MovieError::Invalid("timing model")),
+        };
+        let seed = reader.u64()?;
+        let rpl_flags = reader.array()?;
+        let memory_policy = match reader.u8()? {
+            0 => MemoryPolicy::Wrap,
+            1 => MemoryPolicy::Fault,
+            2 => MemoryPolicy::Ignore,
+            _ => return Err(MovieError::Invalid("memory policy")),
+        };
+        let stack_depth = match (reader.u8()?, reader.u32()?) {
+            (0, depth) => StackDepth::Limited(depth as usize),
+            (1, _) => StackDepth::Unlimited,
+            _ => return Err(MovieError::Invalid("stack depth")),
+        };
+        let stack_in_memory = reader.bool()?;
+        let count = reader.u32()?;
+        let mut frames = Vec::new();
+        for _ in 0..count {
+            frames.push(KeyState::from_bits(reader.u16()?));
+        }
+
+        if !reader.is_empty() {
+            return Err(MovieError::Invalid("length"));
+        }
+
+        Ok(Self {
+            rom_hash,
+            load_address,
+            quirks,
+            timing,
+            seed,
+            rpl_flags,
+            memory_policy,
+            stack_depth,
+            stack_in_memory,
+            frames,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_end_in_the_recorded_state() {
+        // Waits for a key, adds a random byte to V1 and stores its digits, forever
+        let rom = Rom::new(vec![
+            0xF2, 0x0A, 0xC0, 0xFF, 0x81, 0x04, 0xA3, 0x00, 0xF1, 0x33, 0x12, 0x00,
+        ]);
+        let input = |frame: usize| {
+            let mut keys = KeyState::new();
+            keys.set_key_pressed((frame / 8 % 16) as u8, frame % 8 < 3);
+            keys
+        };
+
+        let mut chip8 = Chip8::new(Quirks::COSMAC_VIP);
+        chip8.load_rom(&rom).unwrap();
+        chip8.set_timing(TimingModel::CosmacVip);
+        let mut movie = Movie::record(&mut chip8, 1234);
+        for frame in 0..240 {
+            let keys = input(frame);
+            movie.push_frame(&keys);
+            chip8.run_frame(&keys);
+        }
+        let recorded = chip8.save_state();
+
+        let movie = Movie::parse(&movie.to_bytes()).unwrap();
+        let mut replay = movie.start(&rom).unwrap();
+        for frame in 0..movie.len() {
+            replay.run_frame(movie.frame(frame).unwrap());
+        }
+        assert_eq!(replay.save_state(), recorded);
+
+        // Another seed draws other numbers, so the states would differ
+        let mut reseeded = movie.start(&rom).unwrap();
+        reseeded.seed_random(4321);
+        for frame in 0..movie.len() {
+            reseeded.run_frame(movie.frame(frame).unwrap());
+        }
+        assert_ne!(reseeded.save_state(), recorded);
+    }
+
+    #[test]
+    fn movies_bring_the_memory_policy_and_stack_they_were_recorded_with() {
+        let rom = Rom::new(vec![0x12, 0x00]);
+        let mut chip8 = Chip8::default();
+        chip8.load_rom(&rom).unwrap();
+        chip8.set_memory_policy(MemoryPolicy::Fault);
+        chip8.set_stack_depth(StackDepth::Unlimited);
+        chip8.set_stack_in_memory(true);
+
+        let mut movie = Movie::record(&mut chip8, 7);
+        movie.push_frame(&KeyState::new());
+        let movie = Movie::parse(&movie.to_bytes()).unwrap();
+        assert_eq!(movie.memory_policy, MemoryPolicy::Fault);
+        assert_eq!(movie.stack_depth, StackDepth::Unlimited);
+        assert!(movie.stack_in_memory);
+
+        let replay = movie.start(&rom).unwrap();
+        assert_eq!(replay.memory_policy(), MemoryPolicy::Fault);
+        assert_eq!(replay.stack_depth(), StackDepth::Unlimited);
+        assert!(replay.stack_in_memory());
+    }
+
+    #[test]
+    fn older_movies_are_rejected() {
+        let mut chip8 = Chip8::default();
+        chip8.load_rom(&Rom::new(vec![0x12, 0x00])).unwrap();
+        let mut data = Movie::record(&mut chip8, 0).to_bytes();
+        data[4..6].copy_from_slice(&2u16.to_le_bytes());
+        assert_eq!(Movie::parse(&data), Err(MovieError::UnsupportedVersion(2)));
+    }
+}
@@ -0,0 +1,1138 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use super::ROM_START_ADDRESS;
+use super::assembler::{AssembleError, Assembly, SourceMap};
+use super::instruction::Instruction;
+use super::register::RegisterIndex;
+use super::types::Nibble;
+
+// Compiles Octo's high-level assembly. Supported: labels (`: name`), `:alias`, `:const`,
+// `:calc`, `:macro`, `:byte`, `:org`, `:call`, `:unpack`, every statement for the
+// instructions this emulator runs, `if ... then`, `if ... begin ... else ... end`,
+// `loop ... while ... again`, and the `<`, `>`, `<=`, `>=` comparisons, which clobber vf.
+// `:calc` uses integer arithmetic and, as in Octo, evaluates strictly right to left.
+// Execution starts at `: main`; unless main comes first, 0x200 holds a jump to it.
+
+// Guards against macros that expand into themselves
+const MAX_MACRO_EXPANSIONS: usize = 100_000;
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn error(&self, message: impl Into<String>) -> AssembleError {
+        AssembleError::new(self.line, self.column, message)
+    }
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+enum Control {
+    // Exits from `while` jump past the matching `again`
+    Loop { start: u16, breaks: Vec<u16> },
+    // The jump over the block when the condition fails
+    If { jump: u16 },
+    Else { jump: u16 },
+}
+
+enum FixupKind {
+    // The low 12 bits of an instruction
+    Address,
+    // The word following F000
+    Long,
+    // The byte operands of the two `vx := byte` emitted by `:unpack`
+    Unpack(u8),
+}
+
+struct Fixup {
+    at: u16,
+    kind: FixupKind,
+    token: Token,
+}
+
+#[derive(Clone, Copy)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+}
+
+#[derive(Clone, Copy)]
+enum Operand {
+    Register(RegisterIndex),
+    Byte(u8),
+}
+
+#[derive(Clone, Copy)]
+enum Condition {
+    Key(RegisterIndex, bool),
+    Compare(RegisterIndex, Comparison, Operand),
+}
+
+impl Condition {
+    fn negate(self) -> Self {
+        match self {
+            Condition::Key(x, pressed) => Condition::Key(x, !pressed),
+            Condition::Compare(x, comparison, operand) => {
+                let negated = match comparison {
+                    Comparison::Equal => Comparison::NotEqual,
+                    Comparison::NotEqual => Comparison::Equal,
+                    Comparison::Less => Comparison::GreaterOrEqual,
+                    Comparison::GreaterOrEqual => Comparison::Less,
+                    Comparison::Greater => Comparison::LessOrEqual,
+                    Comparison::LessOrEqual => Comparison::Greater,
+                };
+                Condition::Compare(x, negated, operand)
+            }
+        }
+    }
+}
+
+pub fn compile(source: &str) -> Result<Assembly, AssembleError> {
+    let tokens = tokenize(source);
+    let end = tokens.last().map_or((1, 1), |token| {
+        (token.line, token.column + token.text.len())
+    });
+
+    let mut compiler = Compiler {
+        tokens: tokens.into(),
+        end,
+        rom: Vec::new(),
+        here: ROM_START_ADDRESS,
+        line: 1,
+        labels: BTreeMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: Vec::new(),
+        control: Vec::new(),
+        source_map: SourceMap::new(),
+        expansions: 0,
+        main_jump: false,
+    };
+
+    let main_first = matches!(
+        (compiler.tokens.front(), compiler.tokens.get(1)),
+        (Some(colon), Some(name)) if colon.text == ":" && name.text == "main"
+    );
+    if !main_first {
+        let token = Token {
+            text: "main".to_string(),
+            line: 1,
+            column: 1,
+        };
+        compiler.emit_address(0x1000, &token)?;
+        compiler.main_jump = true;
+    }
+
+    while !compiler.tokens.is_empty() {
+        compiler.statement()?;
+    }
+    if let Some((_, token)) = compiler.control.last() {
+        return Err(token.error(format!("'{}' is never closed", token.text)));
+    }
+    compiler.resolve_fixups()?;
+
+    Ok(Assembly {
+        rom: compiler.rom,
+        labels: compiler.labels,
+        source_map: compiler.source_map,
+    })
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or("");
+        let mut start = None;
+        for (offset, c) in code.char_indices().chain([(code.len(), ' ')]) {
+            match (c.is_whitespace(), start) {
+                (true, Some(begin)) => {
+                    tokens.push(Token {
+                        text: code[begin..offset].to_string(),
+                        line: index + 1,
+                        column: begin + 1,
+                    });
+                    start = None;
+                }
+                (false, None) => start = Some(offset),
+                _ => {}
+            }
+        }
+    }
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn is_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+struct Compiler {
+    tokens: VecDeque<Token>,
+    // Line and column just past the last token, for errors at the end of the source
+    end: (usize, usize),
+    rom: Vec<u8>,
+    here: usize,
+    // Line of the statement being compiled, recorded in the source map
+    line: usize,
+    labels: BTreeMap<String, u16>,
+    constants: HashMap<String, i64>,
+    aliases: HashMap<String, RegisterIndex>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    control: Vec<(Control, Token)>,
+    source_map: SourceMap,
+    expansions: usize,
+    // Whether 0x200 holds a jump to main
+    main_jump: bool,
+}
+
+impl Compiler {
+    fn next(&mut self) -> Result<Token, AssembleError> {
+        self.tokens
+            .pop_front()
+            .ok_or_else(|| AssembleError::new(self.end.0, self.end.1, "unexpected end of source"))
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Token, AssembleError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(token.error(format!("expected '{}', found '{}'", text, token.text)));
+        }
+        Ok(token)
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.front().is_some_and(|token| token.text == text)
+    }
+
+    fn here(&self) -> u16 {
+        self.here as u16
+    }
+
+    fn write(&mut self, address: u16, byte: u8) {
+        let offset = address as usize - ROM_START_ADDRESS;
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+    }
+
+    fn emit_byte(&mut self, byte: u8, token: &Token) -> Result<(), AssembleError> {
+        if self.here > 0xFFFF {
+            return Err(token.error("program does not fit in memory"));
+        }
+        self.source_map.insert(self.here(), self.line);
+        self.write(self.here(), byte);
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit_word(&mut self, word: u16, token: &Token) -> Result<(), AssembleError> {
+        if self.here + 1 > 0xFFFF {
+            return Err(token.error("program does not fit in memory"));
+        }
+        self.source_map.insert(self.here(), self.line);
+        let [high, low] = word.to_be_bytes();
+        self.write(self.here(), high);
+        self.write(self.here() + 1, low);
+        self.here += 2;
+        Ok(())
+    }
+
+    fn emit(&mut self, instruction: Instruction, token: &Token) -> Result<(), AssembleError> {
+        self.emit_word(u16::from(instruction), token)
+    }
+
+    // Emits `opcode | address`, leaving the address to be patched once a forward label is known
+    fn emit_address(&mut self, opcode: u16, token: &Token) -> Result<(), AssembleError> {
+        let address = match self.lookup(token) {
+            Some(value) => check_address(value, token)?,
+            None if is_name(&token.text) => {
+                self.fixups.push(Fixup {
+                    at: self.here(),
+                    kind: FixupKind::Address,
+                    token: token.clone(),
+                });
+                0
+            }
+            None => return Err(token.error(format!("invalid address '{}'", token.text))),
+        };
+        self.emit_word(opcode | address, token)
+    }
+
+    fn patch_jump(&mut self, at: u16, token: &Token) -> Result<(), AssembleError> {
+        let target = check_address(self.here as i64, token)?;
+        let [high, low] = (0x1000 | target).to_be_bytes();
+        self.write(at, high);
+        self.write(at + 1, low);
+        Ok(())
+    }
+
+    // Numbers, constants and labels defined so far
+    fn lookup(&self, token: &Token) -> Option<i64> {
+        parse_number(&token.text)
+            .or_else(|| self.constants.get(&token.text).copied())
+            .or_else(|| self.labels.get(&token.text).map(|&address| address as i64))
+    }
+
+    fn value(&self, token: &Token) -> Result<i64, AssembleError> {
+        self.lookup(token)
+            .ok_or_else(|| token.error(format!("undefined name '{}'", token.text)))
+    }
+
+    fn byte(&self, token: &Token) -> Result<u8, AssembleError> {
+        let value = self.value(token)?;
+        if !(-0x80..=0xFF).contains(&value) {
+            return Err(token.error(format!("byte {} is out of range", value)));
+        }
+        Ok(value as u8)
+    }
+
+    fn nibble(&self, token: &Token) -> Result<Nibble, AssembleError> {
+        let value = self.value(token)?;
+        Nibble::try_from(value as u8)
+            .ok()
+            .filter(|_| (0..=0xF).contains(&value))
+            .ok_or_else(|| token.error(format!("nibble {} is out of range", value)))
+    }
+
+    fn register_of(&self, token: &Token) -> Option<RegisterIndex> {
+        if let Some(&register) = self.aliases.get(&token.text) {
+            return Some(register);
+        }
+        let digit = token.text.strip_prefix(['v', 'V'])?;
+        if digit.len() != 1 {
+            return None;
+        }
+        RegisterIndex::try_from(u8::from_str_radix(digit, 16).ok()?).ok()
+    }
+
+    fn register(&mut self) -> Result<RegisterIndex, AssembleError> {
+        let token = self.next()?;
+        self.register_of(&token)
+            .ok_or_else(|| token.error(format!("expected a register, found '{}'", token.text)))
+    }
+
+    fn define(&mut self, name: &Token) -> Result<(), AssembleError> {
+        if !is_name(&name.text) || self.register_of(name).is_some() {
+            return Err(name.error(format!("invalid name '{}'", name.text)));
+        }
+        if self.labels.contains_key(&name.text) || self.constants.contains_key(&name.text) {
+            return Err(name.error(format!("'{}' is already defined", name.text)));
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), AssembleError> {
+        let token = self.next()?;
+        self.line = token.line;
+
+        match token.text.as_str() {
+            ":" => {
+                let name = self.next()?;
+                self.define(&name)?;
+                if name.text == "main" && self.main_jump {
+                    self.source_map.insert(ROM_START_ADDRESS as u16, name.line);
+                }
+                self.labels.insert(name.text, self.here());
+            }
+            ":alias" => {
+                let name = self.next()?;
+                self.define(&name)?;
+                let register = self.register()?;
+                self.aliases.insert(name.text, register);
+            }
+            ":const" => {
+                let name = self.next()?;
+                self.define(&name)?;
+                let value = self.next()?;
+                let value = self.value(&value)?;
+                self.constants.insert(name.text, value);
+            }
+            ":calc" => {
+                let name = self.next()?;
+                self.define(&name)?;
+                self.expect("{")?;
+                let value = self.calc()?;
+                self.constants.insert(name.text, value);
+            }
+            ":byte" => {
+                let value = if self.peek_is("{") {
+                    self.next()?;
+                    self.calc()?
+                } else {
+                    let value = self.next()?;
+                    self.value(&value)?
+                };
+                if !(-0x80..=0xFF).contains(&value) {
+                    return Err(token.error(format!("byte {} is out of range", value)));
+                }
+                self.emit_byte(value as u8, &token)?;
+            }
+            ":org" => {
+                let value = if self.peek_is("{") {
+                    self.next()?;
+                    self.calc()?
+                } else {
+                    let value = self.next()?;
+                    self.value(&value)?
+                };
+                if !(ROM_START_ADDRESS as i64..=0xFFFF).contains(&value) {
+                    return Err(token.error(format!("cannot :org to {:#X}", value)));
+                }
+                self.here = value as usize;
+            }
+            ":call" => {
+                let target = self.next()?;
+                self.emit_address(0x2000, &target)?;
+            }
+            ":unpack" => {
+                let high = self.next()?;
+                let high = self.nibble(&high)?.value();
+                let target = self.next()?;
+                let address = match self.lookup(&target) {
+                    Some(value) => check_address(value, &target)?,
+                    None => {
+                        self.fixups.push(Fixup {
+                            at: self.here(),
+                            kind: FixupKind::Unpack(high),
+                            token: target.clone(),
+                        });
+                        0
+                    }
+                };
+                let v0 = RegisterIndex::try_from(0).unwrap();
+                let v1 = RegisterIndex::try_from(1).unwrap();
+                let [address_high, address_low] = address.to_be_bytes();
+                self.emit(Instruction::Load(v0, (high << 4) | address_high), &token)?;
+                self.emit(Instruction::Load(v1, address_low), &token)?;
+            }
+            ":macro" => self.define_macro()?,
+            // Debugger hooks; breakpoints are set from the debugger instead
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            "return" | ";" => self.emit(Instruction::Return, &token)?,
+            "clear" => self.emit(Instruction::Clear, &token)?,
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(Instruction::LoadBinaryCodedDecimal(x), &token)?;
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                let store = token.text == "save";
+                let instruction = if self.peek_is("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    if store {
+                        Instruction::StoreRegisterRange(x, y)
+                    } else {
+                        Instruction::LoadRegisterRange(x, y)
+                    }
+                } else if store {
+                    Instruction::StoreRegisters(x)
+                } else {
+                    Instruction::LoadRegisters(x)
+                };
+                self.emit(instruction, &token)?;
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit(Instruction::StoreFlags(x), &token)?;
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit(Instruction::LoadFlags(x), &token)?;
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let height = self.next()?;
+                let height = self.nibble(&height)?;
+                self.emit(Instruction::Display(x, y, height), &token)?;
+            }
+            "jump" => {
+                let target = self.next()?;
+                self.emit_address(0x1000, &target)?;
+            }
+            "jump0" => {
+                let target = self.next()?;
+                self.emit_address(0xB000, &target)?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let instruction = match token.text.as_str() {
+                    "delay" => Instruction::StoreDelayTimer(x),
+                    "buzzer" => Instruction::StoreSoundTimer(x),
+                    _ => Instruction::LoadPitch(x),
+                };
+                self.emit(instruction, &token)?;
+            }
+            "i" => self.index_statement(&token)?,
+            "scroll-down" | "scroll-up" => {
+                let rows = self.next()?;
+                let rows = self.nibble(&rows)?;
+                let instruction = if token.text == "scroll-down" {
+                    Instruction::ScrollDown(rows)
+                } else {
+                    Instruction::ScrollUp(rows)
+                };
+                self.emit(instruction, &token)?;
+            }
+            "scroll-left" => self.emit(Instruction::ScrollLeft, &token)?,
+            "scroll-right" => self.emit(Instruction::ScrollRight, &token)?,
+            "exit" => self.emit(Instruction::Exit, &token)?,
+            "lores" => self.emit(Instruction::LowResolution, &token)?,
+            "hires" => self.emit(Instruction::HighResolution, &token)?,
+            "plane" => {
+                let planes = self.next()?;
+                let planes = self.nibble(&planes)?;
+                self.emit(Instruction::SelectPlanes(planes), &token)?;
+            }
+            "audio" => self.emit(Instruction::LoadAudioPattern, &token)?,
+            "if" => {
+                let condition = self.condition()?;
+                let keyword = self.next()?;
+                match keyword.text.as_str() {
+                    "then" => self.emit_skip_unless(condition, &token)?,
+                    "begin" => {
+                        self.emit_skip_unless(condition.negate(), &token)?;
+                        let jump = self.here();
+                        self.emit(Instruction::Jump(0), &token)?;
+                        self.control.push((Control::If { jump }, token));
+                    }
+                    _ => {
+                        return Err(keyword.error(format!(
+                            "expected 'then' or 'begin', found '{}'",
+                            keyword.text
+                        )));
+                    }
+                }
+            }
+            "else" => {
+                let Some((Control::If { jump }, _)) = self.control.pop() else {
+                    return Err(token.error("'else' without 'if ... begin'"));
+                };
+                let skip_else = self.here();
+                self.emit(Instruction::Jump(0), &token)?;
+                self.patch_jump(jump, &token)?;
+                self.control
+                    .push((Control::Else { jump: skip_else }, token));
+            }
+            "end" => match self.control.pop() {
+                Some((Control::If { jump } | Control::Else { jump }, _)) => {
+                    self.patch_jump(jump, &token)?
+                }
+                _ => return Err(token.error("'end' without 'if ... begin'")),
+            },
+            "loop" => {
+                let start = self.here();
+                self.control.push((
+                    Control::Loop {
+                        start,
+                        breaks: Vec::new(),
+                    },
+                    token,
+                ));
+            }
+            "while" => {
+                let condition = self.condition()?;
+                self.emit_skip_unless(condition.negate(), &token)?;
+                let jump = self.here();
+                let Some(breaks) =
+                    self.control
+                        .iter_mut()
+                        .rev()
+                        .find_map(|(control, _)| match control {
+                            Control::Loop { breaks, .. } => Some(breaks),
+                            _ => None,
+                        })
+                else {
+                    return Err(token.error("'while' outside of a loop"));
+                };
+                breaks.push(jump);
+                self.emit(Instruction::Jump(0), &token)?;
+            }
+            "again" => {
+                let Some((Control::Loop { start, breaks }, _)) = self.control.pop() else {
+                    return Err(token.error("'again' without 'loop'"));
+                };
+                self.emit(
+                    Instruction::Jump(check_address(start as i64, &token)?),
+                    &token,
+                )?;
+                for jump in breaks {
+                    self.patch_jump(jump, &token)?;
+                }
+            }
+            _ => {
+                if let Some(x) = self.register_of(&token) {
+                    self.register_statement(x, &token)?;
+                } else if self.macros.contains_key(&token.text) {
+                    self.expand_macro(&token)?;
+                } else if parse_number(&token.text).is_some()
+                    || self.constants.contains_key(&token.text)
+                {
+                    let value = self.byte(&token)?;
+                    self.emit_byte(value, &token)?;
+                } else if is_name(&token.text) {
+                    // A bare label is a subroutine call
+                    self.emit_address(0x2000, &token)?;
+                } else {
+                    return Err(token.error(format!("unknown statement '{}'", token.text)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn index_statement(&mut self, token: &Token) -> Result<(), AssembleError> {
+        let op = self.next()?;
+        match op.text.as_str() {
+            ":=" => {
+                let source = self.next()?;
+                match source.text.as_str() {
+                    "hex" => {
+                        let x = self.register()?;
+                        self.emit(Instruction::LoadFontCharacter(x), token)
+                    }
+                    "bighex" => {
+                        let x = self.register()?;
+                        self.emit(Instruction::LoadBigFontCharacter(x), token)
+                    }
+                    "long" => {
+                        let target = self.next()?;
+                        self.emit(Instruction::LoadIndexLong, token)?;
+                        let address = match self.lookup(&target) {
+                            Some(value) if (0..=0xFFFF).contains(&value) => value as u16,
+                            Some(value) => {
+                                return Err(
+                                    target.error(format!("address {:#X} is out of range", value))
+                                );
+                            }
+                            None => {
+                                self.fixups.push(Fixup {
+                                    at: self.here(),
+                                    kind: FixupKind::Long,
+                                    token: target.clone(),
+                                });
+                                0
+                            }
+                        };
+                        // The operand word belongs to the F000 line, so keep it out of
+                        // the source map
+                        let [high, low] = address.to_be_bytes();
+                        self.write(self.here(), high);
+                        self.write(self.here() + 1, low);
+                        self.here += 2;
+                        Ok(())
+                    }
+                    _ => self.emit_address(0xA000, &source),
+                }
+            }
+            "+=" => {
+                let x = self.register()?;
+                self.emit(Instruction::AddIndexRegister(x), token)
+            }
+            _ => Err(op.error(format!("expected ':=' or '+=', found '{}'", op.text))),
+        }
+    }
+
+    fn register_statement(&mut self, x: RegisterIndex, token: &Token) -> Result<(), AssembleError> {
+        let op = self.next()?;
+        let source = self.next()?;
+        let y = self.register_of(&source);
+
+        let instruction = match (op.text.as_str(), y) {
+            (":=", Some(y)) => Instruction::LoadRegister(x, y),
+            (":=", None) => match source.text.as_str() {
+                "random" => {
+                    let mask = self.next()?;
+                    Instruction::Random(x, self.byte(&mask)?)
+                }
+                "key" => Instruction::LoadKeyPress(x),
+                "delay" => Instruction::LoadDelayTimer(x),
+                _ => Instruction::Load(x, self.byte(&source)?),
+            },
+            ("+=", Some(y)) => Instruction::LoadAdd(x, y),
+            ("+=", None) => Instruction::Add(x, self.byte(&source)?),
+            ("-=", Some(y)) => Instruction::LoadSub(x, y),
+            ("-=", None) => Instruction::Add(x, self.byte(&source)?.wrapping_neg()),
+            ("=-", Some(y)) => Instruction::LoadSubNegative(x, y),
+            ("|=", Some(y)) => Instruction::LoadOr(x, y),
+            ("&=", Some(y)) => Instruction::LoadAnd(x, y),
+            ("^=", Some(y)) => Instruction::LoadXor(x, y),
+            (">>=", Some(y)) => Instruction::LoadShiftRight(x, y),
+            ("<<=", Some(y)) => Instruction::LoadShiftLeft(x, y),
+            ("=-" | "|=" | "&=" | "^=" | ">>=" | "<<=", None) => {
+                return Err(source.error(format!("'{}' needs a register operand", op.text)));
+            }
+            _ => return Err(op.error(format!("unknown operator '{}'", op.text))),
+        };
+        self.emit(instruction, token)
+    }
+
+    fn condition(&mut self) -> Result<Condition, AssembleError> {
+        let x = self.register()?;
+        let op = self.next()?;
+        let comparison = match op.text.as_str() {
+            "key" => return Ok(Condition::Key(x, true)),
+            "-key" => return Ok(Condition::Key(x, false)),
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            ">" => Comparison::Greater,
+            "<=" => Comparison::LessOrEqual,
+            ">=" => Comparison::GreaterOrEqual,
+            _ => return Err(op.error(format!("unknown comparison '{}'", op.text))),
+        };
+        let operand = self.next()?;
+        let operand = match self.register_of(&operand) {
+            Some(y) => Operand::Register(y),
+            None => Operand::Byte(self.byte(&operand)?),
+        };
+        Ok(Condition::Compare(x, comparison, operand))
+    }
+
+    // Emits code that skips the next instruction when the condition does not hold. The
+    // ordering comparisons compute the carry of a subtraction into vf first.
+    fn emit_skip_unless(
+        &mut self,
+        condition: Condition,
+        token: &Token,
+    ) -> Result<(), AssembleError> {
+        let vf = RegisterIndex::try_from(0xF).unwrap();
+        let instruction = match condition {
+            Condition::Key(x, true) => Instruction::SkipKeyNotPress(x),
+            Condition::Key(x, false) => Instruction::SkipKeyPress(x),
+            Condition::Compare(x, Comparison::Equal, Operand::Byte(kk)) => {
+                Instruction::SkipNotEqual(x, kk)
+            }
+            Condition::Compare(x, Comparison::Equal, Operand::Register(y)) => {
+                Instruction::SkipNotEqualRegister(x, y)
+            }
+            Condition::Compare(x, Comparison::NotEqual, Operand::Byte(kk)) => {
+                Instruction::SkipEqual(x, kk)
+            }
+            Condition::Compare(x, Comparison::NotEqual, Operand::Register(y)) => {
+                Instruction::SkipEqualRegister(x, y)
+            }
+            Condition::Compare(x, comparison, operand) => {
+                // vf = 1 when x >= operand (for < and >=) or operand >= x (for > and <=)
+                let x_first = matches!(comparison, Comparison::Less | Comparison::GreaterOrEqual);
+                match (operand, x_first) {
+                    (Operand::Register(y), true) => {
+                        self.emit(Instruction::LoadRegister(vf, x), token)?;
+                        self.emit(Instruction::LoadSub(vf, y), token)?;
+                    }
+                    (Operand::Byte(kk), true) => {
+                        self.emit(Instruction::Load(vf, kk), token)?;
+                        self.emit(Instruction::LoadSubNegative(vf, x), token)?;
+                    }
+                    (Operand::Register(y), false) => {
+                        self.emit(Instruction::LoadRegister(vf, y), token)?;
+                        self.emit(Instruction::LoadSub(vf, x), token)?;
+                    }
+                    (Operand::Byte(kk), false) => {
+                        self.emit(Instruction::Load(vf, kk), token)?;
+                        self.emit(Instruction::LoadSub(vf, x), token)?;
+                    }
+                }
+                // < and > hold when there was a borrow, >= and <= when there was none
+                match comparison {
+                    Comparison::Less | Comparison::Greater => Instruction::SkipNotEqual(vf, 0),
+                    _ => Instruction::SkipEqual(vf, 0),
+                }
+            }
+        };
+        self.emit(instruction, token)
+    }
+
+    fn define_macro(&mut self) -> Result<(), AssembleError> {
+        let name = self.next()?;
+        self.define(&name)?;
+        if self.macros.contains_key(&name.text) {
+            return Err(name.error(format!("'{}' is already defined", name.text)));
+        }
+
+        let mut params = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "{" {
+                break;
+            }
+            params.push(token.text);
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+            body.push(token);
+        }
+
+        self.macros.insert(name.text, Macro { params, body });
+        Ok(())
+    }
+
+    // Substitutes the arguments into the macro body and queues it as the next tokens. The
+    // expanded tokens take the position of the invocation, so errors and the source map
+    // point at the line that used the macro.
+    fn expand_macro(&mut self, invocation: &Token) -> Result<(), AssembleError> {
+        self.expansions += 1;
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return Err(invocation.error("too many macro expansions (recursive macro?)"));
+        }
+
+        let params = self.macros[&invocation.text].params.clone();
+        let mut arguments = HashMap::new();
+        for param in params {
+            let argument = self.next()?;
+            arguments.insert(param, argument.text);
+        }
+
+        let expanded: Vec<Token> = self.macros[&invocation.text]
+            .body
+            .iter()
+            .map(|token| Token {
+                text: arguments
+                    .get(&token.text)
+                    .cloned()
+                    .unwrap_or_else(|| token.text.clone()),
+                line: invocation.line,
+                column: invocation.column,
+            })
+            .collect();
+        for token in expanded.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    // Evaluates a `:calc` expression up to the closing brace
+    fn calc(&mut self) -> Result<i64, AssembleError> {
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "}" {
+                break;
+            }
+            tokens.push(token);
+        }
+        if tokens.is_empty() {
+            return Err(AssembleError::new(self.line, 1, "empty expression"));
+        }
+
+        let mut position = 0;
+        let value = self.expression(&tokens, &mut position)?;
+        if let Some(extra) = tokens.get(position) {
+            return Err(extra.error(format!("unexpected '{}'", extra.text)));
+        }
+        Ok(value)
+    }
+
+    fn expression(&self, tokens: &[Token], position: &mut usize) -> Result<i64, AssembleError> {
+        let left = self.term(tokens, position)?;
+        let Some(op) = tokens.get(*position) else {
+            return Ok(left);
+        };
+        if op.text == ")" {
+            return Ok(left);
+        }
+        *position += 1;
+        let right = self.expression(tokens, position)?;
+
+        let value = match op.text.as_str() {
+            "+" => left.wrapping_add(right),
+            "-" => left.wrapping_sub(right),
+            "*" => left.wrapping_mul(right),
+            "/" | "%" if right == 0 => return Err(op.error("division by zero")),
+            "/" => left / right,
+            "%" => left % right,
+            "&" => left & right,
+            "|" => left | right,
+            "^" => left ^ right,
+            "<<" => left.wrapping_shl(right as u32),
+            ">>" => left.wrapping_shr(right as u32),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            _ => return Err(op.error(format!("unknown operator '{}'", op.text))),
+        };
+        Ok(value)
+    }
+
+    fn term(&self, tokens: &[Token], position: &mut usize) -> Result<i64, AssembleError> {
+        let Some(token) = tokens.get(*position) else {
+            let last = tokens.last().unwrap();
+            return Err(last.error("expected a value"));
+        };
+        *position += 1;
+
+        match token.text.as_str() {
+            "(" => {
+                let value = self.expression(tokens, position)?;
+                match tokens.get(*position) {
+                    Some(close) if close.text == ")" => {
+                        *position += 1;
+                        Ok(value)
+                    }
+                    _ => Err(token.error("unclosed '('")),
+                }
+            }
+            "-" => Ok(self.term(tokens, position)?.wrapping_neg()),
+            "~" => Ok(!self.term(tokens, position)?),
+            "!" => Ok((self.term(tokens, position)? == 0) as i64),
+            "HERE" => Ok(self.here as i64),
+            _ => self.value(token),
+        }
+    }
+
+    fn resolve_fixups(&mut self) -> Result<(), AssembleError> {
+        for fixup in std::mem::take(&mut self.fixups) {
+            let Some(&address) = self.labels.get(&fixup.token.text) else {
+                return Err(fixup
+                    .token
+                    .error(format!("undefined label '{}'", fixup.token.text)));
+            };
+            match fixup.kind {
+                FixupKind::Address => {
+                    let address = check_address(address as i64, &fixup.token)?;
+                    let offset = fixup.at as usize - ROM_START_ADDRESS;
+                    self.rom[offset] |= (address >> 8) as u8;
+                    self.rom[offset + 1] = address as u8;
+                }
+                FixupKind::Long => {
+                    let [high, low] = address.to_be_bytes();
+                    self.write(fixup.at, high);
+                    self.write(fixup.at + 1, low);
+                }
+                FixupKind::Unpack(high) => {
+                    let address = check_address(address as i64, &fixup.token)?;
+                    let offset = fixup.at as usize - ROM_START_ADDRESS;
+                    self.rom[offset + 1] = (high << 4) | (address >> 8) as u8;
+                    self.rom[offset + 3] = address as u8;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_address(value: i64, token: &Token) -> Result<u16, AssembleError> {
+    if !(0..=0xFFF).contains(&value) {
+        return Err(token.error(format!("address {:#X} is out of range", value)));
+    }
+    Ok(value as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+    use crate::chip8::rom::Rom;
+
+    fn bytes(source: &str) -> Vec<u8> {
+        match compile(source) {
+            Ok(assembly) => assembly.rom,
+            Err(err) => panic!("{}: {}", err, source),
+        }
+    }
+
+    #[test]
+    fn if_then_skips_the_next_statement() {
+        assert_eq!(
+            bytes(": main if v0 == 5 then v1 := 2"),
+            [0x40, 0x05, 0x61, 0x02]
+        );
+        assert_eq!(
+            bytes(": main if v3 key then v1 := 2"),
+            [0xE3, 0xA1, 0x61, 0x02]
+        );
+    }
+
+    #[test]
+    fn if_begin_else_end_jumps_around_the_blocks() {
+        let source = ": main
+            if v0 != v1 begin
+                v2 := 1
+            else
+                v2 := 2
+            end";
+        assert_eq!(
+            bytes(source),
+            [0x90, 0x10, 0x12, 0x08, 0x62, 0x01, 0x12, 0x0A, 0x62, 0x02]
+        );
+        assert_eq!(
+            bytes(": main if v0 == 1 begin v2 := 1 end"),
+            [0x30, 0x01, 0x12, 0x06, 0x62, 0x01]
+        );
+    }
+
+    #[test]
+    fn while_leaves_the_loop_past_again() {
+        let source = ": main
+            loop
+                v0 += 1
+                while v0 != 10
+            again";
+        assert_eq!(
+            bytes(source),
+            [0x70, 0x01, 0x40, 0x0A, 0x12, 0x08, 0x12, 0x00]
+        );
+    }
+
+    #[test]
+    fn ordering_comparisons_compute_into_vf() {
+        assert_eq!(
+            bytes(": main if v0 < v1 then v2 := 1"),
+            [0x8F, 0x00, 0x8F, 0x15, 0x4F, 0x00, 0x62, 0x01]
+        );
+        assert_eq!(
+            bytes(": main if v0 > 5 then v2 := 1"),
+            [0x6F, 0x05, 0x8F, 0x05, 0x4F, 0x00, 0x62, 0x01]
+        );
+        assert_eq!(
+            bytes(": main if v0 <= v1 then v2 := 1"),
+            [0x8F, 0x10, 0x8F, 0x05, 0x3F, 0x00, 0x62, 0x01]
+        );
+        assert_eq!(
+            bytes(": main if v0 >= 5 then v2 := 1"),
+            [0x6F, 0x05, 0x8F, 0x07, 0x3F, 0x00, 0x62, 0x01]
+        );
+    }
+
+    #[test]
+    fn ordering_comparisons_hold_when_they_should() {
+        let values = [0, 1, 5, 127, 128, 254, 255];
+        for op in ["<", ">", "<=", ">="] {
+            let holds = |a: u8, b: u8| match op {
+                "<" => a < b,
+                ">" => a > b,
+                "<=" => a <= b,
+                _ => a >= b,
+            };
+            for a in values {
+                for b in values {
+                    // Both a register and a byte on the right-hand side
+                    for rhs in ["v1".to_string(), b.to_string()] {
+                        let source = format!(
+                            ": main v0 := {} v1 := {} if v0 {} {} then v2 := 1 : end jump end",
+                            a, b, op, rhs
+                        );
+                        let mut chip8 = Chip8::default();
+                        chip8.load_rom(&Rom::new(bytes(&source))).unwrap();
+                        for _ in 0..7 {
+                            chip8.emulate_cycle().unwrap();
+                        }
+                        let v2 = chip8.registers().get(RegisterIndex::try_from(2).unwrap());
+                        assert_eq!(v2 == 1, holds(a, b), "{}", source);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn calc_evaluates_right_to_left() {
+        assert_eq!(bytes(": main :calc X { 10 - 2 - 3 } v0 := X"), [0x60, 0x0B]);
+        assert_eq!(bytes(": main :calc X { 2 * 3 + 4 } v0 := X"), [0x60, 0x0E]);
+    }
+
+    #[test]
+    fn macros_expand_with_their_arguments() {
+        let source = ": main
+            :macro set-both A B { v0 := A v1 := B }
+            set-both 1 2 set-both 3 4";
+        assert_eq!(
+            bytes(source),
+            [0x60, 0x01, 0x61, 0x02, 0x60, 0x03, 0x61, 0x04]
+        );
+    }
+
+    #[test]
+    fn recursive_macros_are_reported() {
+        let err = compile(":macro boom { boom }\n: main\n  boom")
+            .err()
+            .unwrap();
+        // Reported where the expansion started
+        assert_eq!((err.line, err.column), (3, 3));
+        assert!(err.message.contains("recursive"), "{}", err.message);
+    }
+
+    #[test]
+    fn unpack_and_long_loads_resolve_later_labels() {
+        assert_eq!(
+            bytes(": main :unpack 0xA data ; : data 1 2"),
+            [0x60, 0xA2, 0x61, 0x06, 0x00, 0xEE, 0x01, 0x02]
+        );
+        assert_eq!(
+            bytes(": main i := long data ; : data 1"),
+            [0xF0, 0x00, 0x02, 0x06, 0x00, 0xEE, 0x01]
+        );
+    }
+
+    #[test]
+    fn jumps_to_main_unless_it_comes_first() {
+        assert_eq!(
+            bytes(": sub ; : main sub"),
+            [0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]
+        );
+        assert_eq!(bytes(": main sub : sub ;"), [0x22, 0x02, 0x00, 0xEE]);
+    }
+
+    #[test]
+    fn source_map_points_at_statement_lines() {
+        let assembly = compile("\n: sub ;\n: main\n  sub\n  i := long sub").unwrap();
+        let map = &assembly.source_map;
+        assert_eq!(map.line_for(0x200), Some(3));
+        assert_eq!(map.line_for(0x202), Some(2));
+        assert_eq!(map.line_for(0x204), Some(4));
+        assert_eq!(map.line_for(0x206), Some(5));
+        assert_eq!(map.line_for(0x208), None);
+    }
+}
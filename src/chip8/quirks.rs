@@ -0,0 +1,153 @@
+use std::fmt;
+use std::str::FromStr;
+
+// Behaviours that differ between CHIP-8 interpreters. Each preset matches the
+// platform most ROMs of that era were written against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    // 8xy1/8xy2/8xy3 reset VF to 0
+    pub vf_reset: bool,
+    // 8xy6/8xyE shift Vy into Vx instead of shifting Vx in place
+    pub shift_uses_vy: bool,
+    // Fx55/Fx65 leave I pointing past the last register accessed
+    pub load_store_increments_index: bool,
+    // Bnnn jumps to nnn + Vx (x being the high nibble of nnn) instead of nnn + V0
+    pub jump_uses_vx: bool,
+    // Sprites drawn past the screen edge wrap around instead of being clipped
+    pub sprite_wrap: bool,
+    // Dxyn waits for the next vblank before drawing, so one sprite is drawn per frame
+    pub display_wait: bool,
+    // Enables the XO-CHIP extensions: 64 KiB of memory, long index loads,
+    // bit-plane selection, register ranges and the audio pattern buffer
+    pub xo_chip: bool,
+    // The stack holds 12 return addresses, as on the VIP, instead of 16
+    pub vip_stack_depth: bool,
+}
+
+impl Quirks {
+    pub const COSMAC_VIP: Quirks = Quirks {
+        vf_reset: true,
+        shift_uses_vy: true,
+        load_store_increments_index: true,
+        jump_uses_vx: false,
+        sprite_wrap: false,
+        display_wait: true,
+        xo_chip: false,
+        vip_stack_depth: true,
+    };
+
+    pub const CHIP_48: Quirks = Quirks {
+        vf_reset: false,
+        shift_uses_vy: false,
+        load_store_increments_index: true,
+        jump_uses_vx: true,
+        sprite_wrap: false,
+        display_wait: false,
+        xo_chip: false,
+        vip_stack_depth: false,
+    };
+
+    pub const SUPER_CHIP: Quirks = Quirks {
+        vf_reset: false,
+        shift_uses_vy: false,
+        load_store_increments_index: false,
+        jump_uses_vx: true,
+        sprite_wrap: false,
+        display_wait: false,
+        xo_chip: false,
+        vip_stack_depth: false,
+    };
+
+    pub const XO_CHIP: Quirks = Quirks {
+        vf_reset: false,
+        shift_uses_vy: true,
+        load_store_increments_index: true,
+        jump_uses_vx: false,
+        sprite_wrap: true,
+        display_wait: false,
+        xo_chip: true,
+        vip_stack_depth: false,
+    };
+
+    // Preset names accepted by `FromStr`, paired with their quirks
+    pub const PRESETS: [(&'static str, Quirks); 4] = [
+        ("vip", Quirks::COSMAC_VIP),
+        ("chip48", Quirks::CHIP_48),
+        ("schip", Quirks::SUPER_CHIP),
+        ("xochip", Quirks::XO_CHIP),
+    ];
+
+    // Packs the flags into a byte, in field order from the least significant bit
+    pub fn to_bits(&self) -> u8 {
+        [
+            self.vf_reset,
+            self.shift_uses_vy,
+            self.load_store_increments_index,
+            self.jump_uses_vx,
+            self.sprite_wrap,
+            self.display_wait,
+            self.xo_chip,
+            self.vip_stack_depth,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (i, &flag)| bits | ((flag as u8) << i))
+    }
+
+    pub fn from_bits(bits: u8) -> Self {
+        let flag = |i: u8| bits & (1 << i) != 0;
+        Quirks {
+            vf_reset: flag(0),
+            shift_uses_vy: flag(1),
+            load_store_increments_index: flag(2),
+            jump_uses_vx: flag(3),
+            sprite_wrap: flag(4),
+            display_wait: flag(5),
+            xo_chip: flag(6),
+            vip_stack_depth: flag(7),
+        }
+    }
+
+    pub fn preset_name(&self) -> Option<&'static str> {
+        Self::PRESETS
+            .iter()
+            .find(|(_, quirks)| quirks == self)
+            .map(|(name, _)| *name)
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::COSMAC_VIP
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownPreset(pub String);
+
+impl fmt::Display for UnknownPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = Quirks::PRESETS.iter().map(|(name, _)| *name).collect();
+        write!(
+            f,
+            "unknown quirks preset '{}' (expected one of: {})",
+            self.0,
+            names.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownPreset {}
+
+impl FromStr for Quirks {
+    type Err = UnknownPreset;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_ascii_lowercase().replace(['-', '_', ' '], "");
+        Self::PRESETS
+            .iter()
+            .find(|(preset, _)| *preset == name)
+            .map(|(_, quirks)| *quirks)
+            .ok_or_else(|| UnknownPreset(s.to_string()))
+    }
+}
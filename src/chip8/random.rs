@@ -0,0 +1,118 @@
+// Where Cxkk gets its random bytes. Hosts can supply their own source with
+// `Chip8::set_random_source`; the default draws from the operating system's generator, or
+// on WebAssembly, which has none, is seeded with 0 until the host seeds it.
+pub trait RandomSource {
+    fn next_byte(&mut self) -> u8;
+
+    // A seed that makes `SeededRandom` continue this source's sequence from here, or None
+    // if the sequence cannot be reproduced. Save states store it so a restored machine
+    // draws the same bytes the original would have.
+    fn seed(&self) -> Option<u64> {
+        None
+    }
+}
+
+// Unreproducible randomness from the `rand` crate's thread-local generator
+#[cfg(not(target_arch = "wasm32"))]
+pub struct ThreadRandom;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl RandomSource for ThreadRandom {
+    fn next_byte(&mut self) -> u8 {
+        rand::random()
+    }
+}
+
+// SplitMix64, which is small enough to keep its whole state in a save state and produces
+// the same sequence on every platform and with every version of `rand`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeededRandom {
+    state: u64,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn next_byte(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+
+    fn seed(&self) -> Option<u64> {
+        Some(self.state)
+    }
+}
+
+pub(crate) fn default_source() -> Box<dyn RandomSource> {
+    #[cfg(not(target_arch = "wasm32"))]
+    return Box::new(ThreadRandom);
+    #[cfg(target_arch = "wasm32")]
+    return Box::new(SeededRandom::new(0));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::register::RegisterIndex;
+    use crate::chip8::rom::Rom;
+    use crate::chip8::{Chip8, KeyState};
+
+    fn bytes(source: &mut impl RandomSource, count: usize) -> Vec<u8> {
+        (0..count).map(|_| source.next_byte()).collect()
+    }
+
+    #[test]
+    fn seeds_fix_the_sequence() {
+        let first = bytes(&mut SeededRandom::new(42), 64);
+        assert_eq!(first, bytes(&mut SeededRandom::new(42), 64));
+        assert_ne!(first, bytes(&mut SeededRandom::new(43), 64));
+    }
+
+    #[test]
+    fn a_source_continues_from_its_seed() {
+        let mut source = SeededRandom::new(7);
+        bytes(&mut source, 10);
+        let mut resumed = SeededRandom::new(source.seed().unwrap());
+        assert_eq!(bytes(&mut source, 32), bytes(&mut resumed, 32));
+    }
+
+    #[test]
+    fn save_states_keep_the_random_sequence() {
+        // C0FF then jump back: V0 takes a new random byte every instruction pair
+        let mut chip8 = Chip8::default();
+        chip8
+            .load_rom(&Rom::new(vec![0xC0, 0xFF, 0x12, 0x00]))
+            .unwrap();
+        chip8.seed_random(1234);
+        chip8.run_frame(&KeyState::new());
+        let state = chip8.save_state();
+
+        let v0 = |chip8: &Chip8| chip8.registers().get(RegisterIndex::try_from(0).unwrap());
+        let sequence = |chip8: &mut Chip8| {
+            (0..32)
+                .map(|_| {
+                    chip8.emulate_cycle().unwrap();
+                    chip8.emulate_cycle().unwrap();
+                    v0(chip8)
+                })
+                .collect::<Vec<_>>()
+        };
+        let expected = sequence(&mut chip8);
+
+        // A different source must be replaced by the one in the state
+        chip8.seed_random(99);
+        chip8.load_state(&state).unwrap();
+        assert_eq!(sequence(&mut chip8), expected);
+    }
+}
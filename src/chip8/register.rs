@@ -39,7 +39,17 @@ impl RegisterFile {
         self.registers[reg.value()]
     }
 
+    pub fn values(&self) -> &[u8; NUM_REGISTERS] {
+        &self.registers
+    }
+
     pub fn set(&mut self, reg: RegisterIndex, val: u8) {
         self.registers[reg.value()] = val;
     }
 }
+
+impl Default for RegisterFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,347 @@
+use std::collections::VecDeque;
+
+use super::Chip8;
+use super::savestate::SaveStateError;
+
+// How one recorded state is rebuilt from the state recorded after it
+enum Delta {
+    // Run-length encoded XOR against the newer state
+    Xor(Vec<u8>),
+    // Stored whole, used when the state size changed (e.g. a state with other quirks was loaded)
+    Full(Vec<u8>),
+}
+
+impl Delta {
+    fn len(&self) -> usize {
+        match self {
+            Delta::Xor(data) | Delta::Full(data) => data.len(),
+        }
+    }
+}
+
+struct Entry {
+    frame: u64,
+    delta: Delta,
+}
+
+// Ring buffer of per-frame machine states. Only the newest state is kept in full; every
+// older state is stored as a compressed difference from the one after it, so a frame in
+// which little of memory or the screen changed costs a few bytes.
+pub struct RewindBuffer {
+    max_frames: usize,
+    max_bytes: usize,
+    // Oldest first; `entries[i]` rebuilds its state from the state of `entries[i + 1]`,
+    // and the last entry from `newest`
+    entries: VecDeque<Entry>,
+    newest: Option<(u64, Vec<u8>)>,
+    delta_bytes: usize,
+}
+
+impl RewindBuffer {
+    // Keeps at most `max_frames` states and roughly `max_bytes` of compressed history
+    pub fn new(max_frames: usize, max_bytes: usize) -> Self {
+        Self {
+            max_frames,
+            max_bytes,
+            entries: VecDeque::new(),
+            newest: None,
+            delta_bytes: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len() + self.newest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    // Approximate memory used by the recorded history
+    pub fn size_in_bytes(&self) -> usize {
+        self.delta_bytes + self.newest.as_ref().map_or(0, |(_, state)| state.len())
+    }
+
+    // Frame numbers of the oldest and newest recorded states
+    pub fn frame_range(&self) -> Option<(u64, u64)> {
+        let (newest, _) = self.newest.as_ref()?;
+        let oldest = self.entries.front().map_or(*newest, |entry| entry.frame);
+        Some((oldest, *newest))
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.newest = None;
+        self.delta_bytes = 0;
+    }
+
+    // Records the machine state for `frame`, which should be later than any recorded so far
+    pub fn record(&mut self, chip8: &Chip8, frame: u64) {
+        let state = chip8.save_state();
+
+        if let Some((previous_frame, previous)) = self.newest.take() {
+            let delta = if previous.len() == state.len() {
+                Delta::Xor(encode_xor(&previous, &state))
+            } else {
+                Delta::Full(previous)
+            };
+            self.delta_bytes += delta.len();
+            self.entries.push_back(Entry {
+                frame: previous_frame,
+                delta,
+            });
+        }
+        self.newest = Some((frame, state));
+
+        while self.len() > self.max_frames.max(1)
+            || (self.size_in_bytes() > self.max_bytes && !self.entries.is_empty())
+        {
+            let evicted = self.entries.pop_front().unwrap();
+            self.delta_bytes -= evicted.delta.len();
+        }
+    }
+
+    // Steps back to the previous recorded frame, discarding the newest one, and restores
+    // it into `chip8`. Returns the frame restored, or None once the history is exhausted.
+    pub fn rewind(&mut self, chip8: &mut Chip8) -> Result<Option<u64>, SaveStateError> {
+        let Some(entry) = self.entries.pop_back() else {
+            return Ok(None);
+        };
+        self.delta_bytes -= entry.delta.len();
+
+        let (_, newer) = self.newest.take().unwrap();
+        let state = match entry.delta {
+            Delta::Xor(delta) => apply_xor(&newer, &delta),
+            Delta::Full(state) => state,
+        };
+        chip8.load_state(&state)?;
+        self.newest = Some((entry.frame, state));
+
+        Ok(Some(entry.frame))
+    }
+
+    // Rewinds to the latest recorded frame at or before `frame`, dropping everything after it
+    pub fn rewind_to(
+        &mut self,
+        chip8: &mut Chip8,
+        frame: u64,
+    ) -> Result<Option<u64>, SaveStateError> {
+        let mut restored = None;
+        while let Some((newest, _)) = self.newest {
+            if newest <= frame || self.entries.is_empty() {
+                break;
+            }
+            restored = self.rewind(chip8)?;
+        }
+        Ok(restored)
+    }
+}
+
+// Encodes the XOR of two equally sized buffers as alternating runs: a varint count of
+// unchanged bytes, a varint count of changed bytes, then the changed bytes' XOR values
+fn encode_xor(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+
+    while i < new.len() {
+        let unchanged = old[i..]
+            .iter()
+            .zip(&new[i..])
+            .take_while(|(a, b)| a == b)
+            .count();
+        i += unchanged;
+        let changed = old[i..]
+            .iter()
+            .zip(&new[i..])
+            .take_while(|(a, b)| a != b)
+            .count();
+
+        write_varint(&mut out, unchanged);
+        write_varint(&mut out, changed);
+        out.extend(
+            old[i..i + changed]
+                .iter()
+                .zip(&new[i..i + changed])
+                .map(|(a, b)| a ^ b),
+        );
+        i += changed;
+    }
+
+    out
+}
+
+fn apply_xor(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut out = base.to_vec();
+    let mut position = 0;
+    let mut i = 0;
+
+    while i < delta.len() {
+        let unchanged = read_varint(delta, &mut i);
+        let changed = read_varint(delta, &mut i);
+        position += unchanged;
+        for byte in &mut out[position..position + changed] {
+            *byte ^= delta[i];
+            i += 1;
+        }
+        position += changed;
+    }
+
+    out
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], i: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*i];
+        *i += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::KeyState;
+    use crate::chip8::quirks::Quirks;
+    use crate::chip8::rom::Rom;
+
+    // Xorshift bytes, so the round-trip tests see many different run shapes
+    fn noise(seed: u32, len: usize) -> Vec<u8> {
+        let mut state = seed.max(1);
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn xor_deltas_round_trip() {
+        for seed in 1..50 {
+            let old = noise(seed, 300);
+            let mut new = old.clone();
+            // Change a scattered subset of bytes
+            for (byte, mask) in new.iter_mut().zip(noise(seed + 1000, 300)) {
+                if mask % 3 == 0 {
+                    *byte = byte.wrapping_add(mask | 1);
+                }
+            }
+            assert_eq!(apply_xor(&new, &encode_xor(&new, &old)), old);
+            assert_eq!(apply_xor(&old, &encode_xor(&old, &new)), new);
+        }
+
+        let state = noise(7, 4096);
+        assert_eq!(encode_xor(&state, &state), vec![0x80, 0x20, 0x00]);
+        assert_eq!(apply_xor(&state, &encode_xor(&state, &state)), state);
+
+        let inverted: Vec<u8> = state.iter().map(|byte| !byte).collect();
+        let delta = encode_xor(&state, &inverted);
+        assert_eq!(delta[..3], [0x00, 0x80, 0x20]);
+        assert_eq!(apply_xor(&state, &delta), inverted);
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        let mut out = Vec::new();
+        write_varint(&mut out, 300);
+        assert_eq!(out, [0xAC, 0x02]);
+
+        let values = [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, 65_536, usize::MAX];
+        let mut out = Vec::new();
+        for &value in &values {
+            write_varint(&mut out, value);
+        }
+        let mut i = 0;
+        for &value in &values {
+            assert_eq!(read_varint(&out, &mut i), value);
+        }
+        assert_eq!(i, out.len());
+    }
+
+    // Counts in V0 and draws it, so every frame leaves a different state
+    fn counting_machine(quirks: Quirks) -> Chip8 {
+        let mut chip8 = Chip8::new(quirks);
+        let program = [0x70, 0x01, 0xF0, 0x29, 0x00, 0xE0, 0xD0, 0x05, 0x12, 0x00];
+        chip8.load_rom(&Rom::new(program.to_vec())).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn rewinding_restores_the_recorded_states() {
+        let mut chip8 = counting_machine(Quirks::default());
+        let mut buffer = RewindBuffer::new(100, usize::MAX);
+        let mut states = Vec::new();
+        for frame in 0..10 {
+            chip8.run_frame(&KeyState::new());
+            buffer.record(&chip8, frame);
+            states.push(chip8.save_state());
+        }
+
+        assert_eq!(buffer.rewind(&mut chip8), Ok(Some(8)));
+        assert_eq!(chip8.save_state(), states[8]);
+        assert_eq!(buffer.rewind_to(&mut chip8, 3), Ok(Some(3)));
+        assert_eq!(chip8.save_state(), states[3]);
+        assert_eq!(buffer.frame_range(), Some((0, 3)));
+        for frame in (0..3).rev() {
+            assert_eq!(buffer.rewind(&mut chip8), Ok(Some(frame)));
+            assert_eq!(chip8.save_state(), states[frame as usize]);
+        }
+        assert_eq!(buffer.rewind(&mut chip8), Ok(None));
+    }
+
+    #[test]
+    fn states_of_another_size_are_kept_whole() {
+        let mut chip8 = counting_machine(Quirks::default());
+        let mut xo_chip = counting_machine(Quirks::XO_CHIP);
+        xo_chip.run_frame(&KeyState::new());
+        let mut buffer = RewindBuffer::new(100, usize::MAX);
+
+        chip8.run_frame(&KeyState::new());
+        let before = chip8.save_state();
+        buffer.record(&chip8, 0);
+        chip8.load_state(&xo_chip.save_state()).unwrap();
+        assert_ne!(chip8.save_state().len(), before.len());
+        buffer.record(&chip8, 1);
+
+        assert_eq!(buffer.rewind(&mut chip8), Ok(Some(0)));
+        assert_eq!(chip8.save_state(), before);
+    }
+
+    #[test]
+    fn old_states_are_evicted_past_capacity() {
+        let mut chip8 = counting_machine(Quirks::default());
+        let mut buffer = RewindBuffer::new(3, usize::MAX);
+        for frame in 0..5 {
+            chip8.run_frame(&KeyState::new());
+            buffer.record(&chip8, frame);
+        }
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.frame_range(), Some((2, 4)));
+
+        // Too small for any delta: only the newest state stays
+        let mut buffer = RewindBuffer::new(100, 1);
+        for frame in 0..5 {
+            chip8.run_frame(&KeyState::new());
+            buffer.record(&chip8, frame);
+        }
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.frame_range(), Some((4, 4)));
+        assert_eq!(buffer.rewind(&mut chip8), Ok(None));
+    }
+}
@@ -0,0 +1,298 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use sha1::{Digest, Sha1};
+
+use super::analyzer::{self, DetectedPlatform, RomReport};
+use super::memory::{MEMORY_SIZE, ROM_START_ADDRESS};
+use super::quirks::Quirks;
+use super::savestate::ROM_HASH_SIZE;
+
+// The ETI-660 reserves more memory for its interpreter, so its programs start here
+pub const ETI660_START_ADDRESS: usize = 0x600;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RomError {
+    Empty,
+    // The ROM does not fit between its load address and the end of memory
+    TooLarge { size: usize, available: usize },
+    // Programs cannot load over the interpreter's area below 0x200 or past the end of memory
+    InvalidLoadAddress(usize),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomError::Empty => write!(f, "ROM is empty"),
+            RomError::TooLarge { size, available } => write!(
+                f,
+                "ROM is {} bytes but only {} bytes of memory are free",
+                size, available
+            ),
+            RomError::InvalidLoadAddress(address) => {
+                write!(f, "ROM cannot be loaded at {:#05X}", address)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+// A sign of which platform a ROM was written for, and what it was inferred from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlatformHint {
+    pub quirks: Quirks,
+    pub reason: String,
+}
+
+// A program image with what is known about it before it runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rom {
+    data: Vec<u8>,
+    sha1: [u8; ROM_HASH_SIZE],
+    load_address: usize,
+    // Hints from the file the ROM was read from
+    file_hints: Vec<PlatformHint>,
+}
+
+impl Rom {
+    // A ROM loaded at 0x200, the usual address
+    pub fn new(data: Vec<u8>) -> Self {
+        Self {
+            sha1: Sha1::digest(&data).into(),
+            data,
+            load_address: ROM_START_ADDRESS,
+            file_hints: Vec::new(),
+        }
+    }
+
+    // Reads a ROM file, taking a hint from extensions that name a platform
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut rom = Self::new(std::fs::read(path)?);
+
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        let quirks = match extension.as_deref() {
+            Some("sc8") => Some(Quirks::SUPER_CHIP),
+            Some("xo8") => Some(Quirks::XO_CHIP),
+            _ => None,
+        };
+        if let (Some(quirks), Some(extension)) = (quirks, extension) {
+            rom.file_hints.push(PlatformHint {
+                quirks,
+                reason: format!("the .{} extension", extension),
+            });
+        }
+        Ok(rom)
+    }
+
+    // Loads the program at `address` instead, e.g. ETI660_START_ADDRESS
+    pub fn with_load_address(mut self, address: usize) -> Self {
+        self.load_address = address;
+        self
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn sha1(&self) -> &[u8; ROM_HASH_SIZE] {
+        &self.sha1
+    }
+
+    pub fn load_address(&self) -> usize {
+        self.load_address
+    }
+
+    // Reads the program's code from its load address, see `analyzer::analyze`
+    pub fn analyze(&self) -> RomReport {
+        analyzer::analyze(&self.data, self.load_address as u16)
+    }
+
+    // The hints from the ROM's size and file, followed by the one from reading its code.
+    // That one is left out when it only finds SUPER-CHIP code in what is otherwise known
+    // to be an XO-CHIP program, which can use SUPER-CHIP instructions too.
+    pub fn hints(&self) -> Vec<PlatformHint> {
+        let mut hints = Vec::new();
+        let available = MEMORY_SIZE.saturating_sub(self.load_address);
+        if self.data.len() > available {
+            hints.push(PlatformHint {
+                quirks: Quirks::XO_CHIP,
+                reason: format!(
+                    "{} bytes is more than the {} that fit in 4 KiB of memory from {:#05X}",
+                    self.data.len(),
+                    available,
+                    self.load_address
+                ),
+            });
+        }
+        hints.extend(self.file_hints.iter().cloned());
+
+        let report = self.analyze();
+        let xo_chip = hints.iter().any(|hint| hint.quirks.xo_chip);
+        if let Some(hint) = report.hint()
+            && !(xo_chip && report.platform == DetectedPlatform::SuperChip)
+        {
+            hints.push(hint);
+        }
+        hints
+    }
+
+    // The quirks the hints point to, if there are any and they agree
+    pub fn suggested_quirks(&self) -> Option<Quirks> {
+        agreed_quirks(&self.hints())
+    }
+
+    // A line for frontends to show when they run with the suggested quirks
+    pub fn hint_summary(&self) -> Option<String> {
+        let hints = self.hints();
+        let quirks = agreed_quirks(&hints)?;
+        let reasons: Vec<String> = hints.into_iter().map(|hint| hint.reason).collect();
+        Some(format!(
+            "Using {} quirks: {}",
+            quirks.preset_name().unwrap_or("suggested"),
+            reasons.join("; ")
+        ))
+    }
+}
+
+fn agreed_quirks(hints: &[PlatformHint]) -> Option<Quirks> {
+    let quirks = hints.first()?.quirks;
+    hints
+        .iter()
+        .all(|hint| hint.quirks == quirks)
+        .then_some(quirks)
+}
+
+// Parses a frontend's `--load-address` value, in hex with a 0x prefix or in decimal
+pub fn parse_address(text: &str) -> Result<usize, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("invalid address '{}'", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+
+    // Writes `data` to a file with `extension` in the temporary directory and reads it back
+    fn read_with_extension(data: &[u8], extension: &str) -> Rom {
+        let path = std::env::temp_dir().join(format!(
+            "chip8-rom-test-{}-{}.{}",
+            std::process::id(),
+            data.len(),
+            extension
+        ));
+        std::fs::write(&path, data).unwrap();
+        let rom = Rom::read(&path);
+        std::fs::remove_file(&path).unwrap();
+        rom.unwrap()
+    }
+
+    #[test]
+    fn extensions_name_platforms() {
+        let rom = read_with_extension(&[0x12, 0x00], "SC8");
+        assert_eq!(rom.suggested_quirks(), Some(Quirks::SUPER_CHIP));
+        assert_eq!(
+            rom.hint_summary().unwrap(),
+            "Using schip quirks: the .sc8 extension"
+        );
+
+        let rom = read_with_extension(&[0x12, 0x00, 0x00], "xo8");
+        assert_eq!(rom.suggested_quirks(), Some(Quirks::XO_CHIP));
+        let rom = read_with_extension(&[0x12, 0x00, 0x00, 0x00], "ch8");
+        assert_eq!(rom.hints(), []);
+        assert_eq!(rom.hint_summary(), None);
+    }
+
+    #[test]
+    fn disagreeing_hints_suggest_nothing() {
+        // XO-CHIP code in a file named for SUPER-CHIP
+        let rom = read_with_extension(&[0xF0, 0x00, 0x03, 0x00, 0x12, 0x04], "sc8");
+        assert_eq!(rom.hints().len(), 2);
+        assert_eq!(rom.suggested_quirks(), None);
+        assert_eq!(rom.hint_summary(), None);
+    }
+
+    #[test]
+    fn super_chip_code_does_not_contradict_xo_chip() {
+        let mut data = vec![0x00, 0xFF, 0x12, 0x02];
+        data.resize(4000, 0);
+        let rom = Rom::new(data);
+        assert_eq!(rom.hints().len(), 1);
+        assert_eq!(rom.suggested_quirks(), Some(Quirks::XO_CHIP));
+
+        // Without the size, the code decides
+        let rom = Rom::new(vec![0x00, 0xFF, 0x12, 0x02]);
+        assert_eq!(
+            rom.hint_summary().unwrap(),
+            "Using schip quirks: SUPER-CHIP instruction HIGH at 0x200"
+        );
+    }
+
+    #[test]
+    fn addresses_parse_in_hex_or_decimal() {
+        assert_eq!(parse_address("0x600"), Ok(0x600));
+        assert_eq!(parse_address("0X2a0"), Ok(0x2A0));
+        assert_eq!(parse_address("1536"), Ok(0x600));
+        assert!(parse_address("0x").is_err());
+        assert!(parse_address("600h").is_err());
+        assert!(parse_address("-1").is_err());
+    }
+
+    #[test]
+    fn roms_that_do_not_fit_are_refused() {
+        let mut chip8 = Chip8::default();
+        let load = |chip8: &mut Chip8, rom: Rom| chip8.load_rom(&rom);
+
+        assert_eq!(load(&mut chip8, Rom::new(Vec::new())), Err(RomError::Empty));
+        assert_eq!(
+            load(&mut chip8, Rom::new(vec![0; 3585])),
+            Err(RomError::TooLarge {
+                size: 3585,
+                available: 3584,
+            })
+        );
+        let low = Rom::new(vec![0x12, 0x00]).with_load_address(0x1FF);
+        assert_eq!(
+            load(&mut chip8, low),
+            Err(RomError::InvalidLoadAddress(0x1FF))
+        );
+        let high = Rom::new(vec![0x12, 0x00]).with_load_address(MEMORY_SIZE);
+        assert_eq!(
+            load(&mut chip8, high),
+            Err(RomError::InvalidLoadAddress(MEMORY_SIZE))
+        );
+        assert_eq!(chip8.program_counter(), ROM_START_ADDRESS as u16);
+
+        let last = Rom::new(vec![0x12, 0xFF]).with_load_address(MEMORY_SIZE - 2);
+        assert_eq!(load(&mut chip8, last), Ok(()));
+        assert_eq!(chip8.memory()[MEMORY_SIZE - 2..], [0x12, 0xFF]);
+    }
+
+    #[test]
+    fn the_size_hint_follows_the_load_address() {
+        // Fits above 0x200 but not above 0x600
+        let rom = Rom::new(vec![0; 3000]);
+        assert_eq!(rom.suggested_quirks(), None);
+
+        let rom = rom.with_load_address(ETI660_START_ADDRESS);
+        assert_eq!(rom.suggested_quirks(), Some(Quirks::XO_CHIP));
+        assert_eq!(
+            rom.hint_summary().unwrap(),
+            "Using xochip quirks: 3000 bytes is more than the 2560 that fit in 4 KiB of \
+             memory from 0x600"
+        );
+    }
+}
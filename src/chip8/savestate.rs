@@ -0,0 +1,373 @@
+use std::fmt;
+
+use super::quirks::Quirks;
+use super::random::{self, SeededRandom};
+use super::register::RegisterIndex;
+use super::{Chip8, NUM_RPL_FLAGS};
+
+// Save state layout, all multi-byte values little-endian:
+//   magic "C8ST", format version (u16), SHA-1 of the loaded ROM (20 bytes),
+//   quirks bitfield (u8), followed by the machine state in the order of `Chip8::save_state`
+const MAGIC: &[u8; 4] = b"C8ST";
+pub const SAVE_STATE_VERSION: u16 = 4;
+pub const ROM_HASH_SIZE: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SaveStateError {
+    // The data does not start with the save state magic
+    NotASaveState,
+    UnsupportedVersion(u16),
+    // The state was taken while a different ROM was loaded
+    RomMismatch,
+    Truncated,
+    Invalid(&'static str),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveStateError::NotASaveState => write!(f, "not a save state"),
+            SaveStateError::UnsupportedVersion(version) => write!(
+                f,
+                "save state version {} is not supported (expected {})",
+                version, SAVE_STATE_VERSION
+            ),
+            SaveStateError::RomMismatch => write!(f, "save state belongs to a different ROM"),
+            SaveStateError::Truncated => write!(f, "save state is truncated"),
+            SaveStateError::Invalid(what) => write!(f, "save state has an invalid {}", what),
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+// Header fields that can be inspected without restoring the state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveStateHeader {
+    pub version: u16,
+    pub rom_hash: [u8; ROM_HASH_SIZE],
+    pub quirks: Quirks,
+}
+
+impl SaveStateHeader {
+    pub fn parse(data: &[u8]) -> Result<Self, SaveStateError> {
+        let mut reader = StateReader::new(data);
+        Self::read(&mut reader)
+    }
+
+    fn read(reader: &mut StateReader) -> Result<Self, SaveStateError> {
+        if reader
+            .bytes(MAGIC.len())
+            .map_err(|_| SaveStateError::NotASaveState)?
+            != MAGIC
+        {
+            return Err(SaveStateError::NotASaveState);
+        }
+        let version = reader.u16()?;
+        if version != SAVE_STATE_VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+        let rom_hash = reader.array()?;
+        let quirks = Quirks::from_bits(reader.u8()?);
+
+        Ok(Self {
+            version,
+            rom_hash,
+            quirks,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    // Packs booleans eight to a byte, most significant bit first
+    pub fn bits(&mut self, bits: impl IntoIterator<Item = bool>) {
+        let mut byte = 0;
+        let mut count = 0;
+        for bit in bits {
+            byte = (byte << 1) | bit as u8;
+            count += 1;
+            if count == 8 {
+                self.u8(byte);
+                byte = 0;
+                count = 0;
+            }
+        }
+        if count > 0 {
+            self.u8(byte << (8 - count));
+        }
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.data.len() < len {
+            return Err(SaveStateError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], SaveStateError> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::Invalid("flag")),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    // Reads `count` booleans written by `StateWriter::bits`
+    pub fn bits(&mut self, count: usize) -> Result<Vec<bool>, SaveStateError> {
+        let bytes = self.bytes(count.div_ceil(8))?;
+        Ok((0..count)
+            .map(|i| bytes[i / 8] & (0x80 >> (i % 8)) != 0)
+            .collect())
+    }
+}
+
+impl Chip8 {
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+
+        writer.bytes(MAGIC);
+        writer.u16(SAVE_STATE_VERSION);
+        writer.bytes(&self.rom_hash);
+        writer.u8(self.quirks.to_bits());
+
+        self.memory.save_state(&mut writer);
+        writer.bytes(self.registers.values());
+        writer.u16(self.index_register);
+        writer.u16(self.program_counter);
+        self.stack.save_state(&mut writer, &self.memory);
+        writer.u8(self.delay_timer);
+        writer.u8(self.sound_timer);
+        self.display.save_state(&mut writer);
+        writer.bits((0..16).map(|key| self.keypad.is_key_pressed(key)));
+        match self.waiting_for_key {
+            Some(x) => writer.u8(x.value() as u8),
+            None => writer.u8(0xFF),
+        }
+        writer.bool(self.waiting_for_release);
+        writer.bytes(&self.rpl_flags);
+        match &self.audio_pattern {
+            Some(pattern) => {
+                writer.bool(true);
+                writer.bytes(pattern);
+            }
+            None => writer.bool(false),
+        }
+        writer.u8(self.pitch);
+        writer.bool(self.exited);
+        writer.bool(self.vblank_occurred);
+        writer.bool(self.waiting_for_vblank);
+        // Always the same size, so rewind can diff states taken before and after seeding
+        let seed = self.random.seed();
+        writer.bool(seed.is_some());
+        writer.u64(seed.unwrap_or(0));
+
+        writer.into_bytes()
+    }
+
+    // Restores a state produced by `save_state`. The state must have been taken with
+    // the same ROM loaded; the quirks stored in the state replace the current ones.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let mut reader = StateReader::new(data);
+        let header = SaveStateHeader::read(&mut reader)?;
+        if header.rom_hash != self.rom_hash {
+            return Err(SaveStateError::RomMismatch);
+        }
+
+        // Decode into a fresh machine so a bad state leaves this one untouched
+        let mut restored = Chip8::new(header.quirks);
+        restored.rom_hash = header.rom_hash;
+        restored.load_address = self.load_address;
+        restored.memory.load_state(&mut reader)?;
+        for (i, value) in reader.bytes(16)?.iter().enumerate() {
+            restored
+                .registers
+                .set(RegisterIndex::try_from(i as u8).unwrap(), *value);
+        }
+        restored.index_register = reader.u16()?;
+        restored.program_counter = reader.u16()?;
+        restored.stack.load_state(&mut reader)?;
+        restored.delay_timer = reader.u8()?;
+        restored.sound_timer = reader.u8()?;
+        restored.display.load_state(&mut reader)?;
+        // The keys last seen are the restored ones, so the next frame's input presses and
+        // releases whatever differs from them instead of leaving saved keys stuck down
+        for (key, pressed) in reader.bits(16)?.into_iter().enumerate() {
+            restored.keypad.set_key_pressed(key as u8, pressed);
+            restored.input.set_key_pressed(key as u8, pressed);
+        }
+        restored.waiting_for_key = match reader.u8()? {
+            0xFF => None,
+            x => Some(
+                RegisterIndex::try_from(x)
+                    .map_err(|_| SaveStateError::Invalid("key wait register"))?,
+            ),
+        };
+        restored.waiting_for_release = reader.bool()?;
+        restored.rpl_flags = reader.array::<NUM_RPL_FLAGS>()?;
+        restored.audio_pattern = if reader.bool()? {
+            Some(reader.array()?)
+        } else {
+            None
+        };
+        restored.pitch = reader.u8()?;
+        restored.exited = reader.bool()?;
+        restored.vblank_occurred = reader.bool()?;
+        restored.waiting_for_vblank = reader.bool()?;
+        let seeded = reader.bool()?;
+        let seed = reader.u64()?;
+
+        if !reader.is_empty() {
+            return Err(SaveStateError::Invalid("length"));
+        }
+
+        // The frontend's timing and memory policy belong to the host, not the restored
+        // machine
+        restored.clock = self.clock;
+        restored.memory_policy = self.memory_policy;
+        restored.stack.set_depth(self.stack.depth());
+        // The stack is restored as it was saved, separate or in memory, and then moved to
+        // where the host keeps it
+        restored
+            .stack
+            .set_in_memory(self.stack.in_memory(), &mut restored.memory);
+        // A state without a seed keeps whatever source the host installed
+        restored.random = if seeded {
+            Box::new(SeededRandom::new(seed))
+        } else {
+            std::mem::replace(&mut self.random, random::default_source())
+        };
+        restored.display_changed = true;
+        *self = restored;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::KeyState;
+    use crate::chip8::rom::Rom;
+
+    fn keys(held: &[u8]) -> KeyState {
+        let mut keys = KeyState::new();
+        for &key in held {
+            keys.set_key_pressed(key, true);
+        }
+        keys
+    }
+
+    #[test]
+    fn keys_held_in_a_state_are_released_by_the_next_frame() {
+        let mut chip8 = Chip8::default();
+        chip8.load_rom(&Rom::new(vec![0x12, 0x00])).unwrap();
+        chip8.run_frame(&keys(&[0x5]));
+        let state = chip8.save_state();
+
+        chip8.run_frame(&keys(&[0x9]));
+        chip8.load_state(&state).unwrap();
+        assert!(chip8.keypad.is_key_pressed(0x5));
+        assert!(!chip8.keypad.is_key_pressed(0x9));
+
+        chip8.run_frame(&keys(&[0x9]));
+        assert!(!chip8.keypad.is_key_pressed(0x5));
+        assert!(chip8.keypad.is_key_pressed(0x9));
+    }
+
+    #[test]
+    fn states_round_trip() {
+        let rom = Rom::new(vec![
+            0x60, 0x2A, 0x22, 0x06, 0x12, 0x04, 0x71, 0x01, 0x00, 0xEE,
+        ]);
+        let mut chip8 = Chip8::default();
+        chip8.load_rom(&rom).unwrap();
+        chip8.run_frame(&KeyState::new());
+        let state = chip8.save_state();
+
+        let mut stranger = Chip8::default();
+        stranger.load_rom(&Rom::new(vec![0x00, 0xE0])).unwrap();
+        assert_eq!(
+            stranger.load_state(&state).err(),
+            Some(SaveStateError::RomMismatch)
+        );
+
+        let mut other = Chip8::default();
+        other.load_rom(&rom).unwrap();
+        other.load_state(&state).unwrap();
+        assert_eq!(other.save_state(), state);
+        assert_eq!(other.program_counter(), chip8.program_counter());
+        assert_eq!(other.registers().values(), chip8.registers().values());
+    }
+}
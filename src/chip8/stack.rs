@@ -0,0 +1,322 @@
+use super::memory::Memory;
+use super::savestate::{SaveStateError, StateReader, StateWriter};
+
+// Return addresses the COSMAC VIP interpreter has room for, and the 16 of later
+// interpreters
+pub const VIP_STACK_DEPTH: usize = 12;
+pub const DEFAULT_STACK_DEPTH: usize = 16;
+// The VIP interpreter keeps its stack in RAM, growing down from 0xECF towards this address
+pub const VIP_STACK_ADDRESS: usize = 0xEA0;
+const VIP_STACK_END: usize = 0xED0;
+const VIP_STACK_CAPACITY: usize = (VIP_STACK_END - VIP_STACK_ADDRESS) / 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackDepth {
+    // 2nnn faults with a stack overflow once this many return addresses are stacked
+    Limited(usize),
+    // Calls nest as deep as the program likes, for debugging programs that overflow
+    Unlimited,
+}
+
+impl StackDepth {
+    // Parses a frontend's `--stack-depth` value: a number of return addresses or "unlimited"
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "unlimited" => Ok(StackDepth::Unlimited),
+            _ => name.parse().map(StackDepth::Limited).map_err(|_| {
+                format!(
+                    "invalid stack depth '{}' (expected a number or unlimited)",
+                    name
+                )
+            }),
+        }
+    }
+}
+
+pub struct Stack {
+    // Return addresses, oldest first. With the stack in memory these mirror RAM, which is
+    // where 00EE reads them from, so a program that overwrites them returns elsewhere.
+    entries: Vec<u16>,
+    depth: StackDepth,
+    in_memory: bool,
+}
+
+impl Stack {
+    pub fn new(depth: StackDepth) -> Self {
+        Self {
+            entries: Vec::new(),
+            depth,
+            in_memory: false,
+        }
+    }
+
+    pub fn depth(&self) -> StackDepth {
+        self.depth
+    }
+
+    pub fn set_depth(&mut self, depth: StackDepth) {
+        self.depth = depth;
+    }
+
+    pub fn in_memory(&self) -> bool {
+        self.in_memory
+    }
+
+    // Moves the stack into or out of the VIP stack area, carrying the current entries over
+    pub fn set_in_memory(&mut self, in_memory: bool, memory: &mut Memory) {
+        if in_memory && !self.in_memory {
+            let entries = self.entries.iter().copied().take(VIP_STACK_CAPACITY);
+            for (level, address) in entries.enumerate() {
+                write_entry(memory, level, address);
+            }
+            self.entries.truncate(VIP_STACK_CAPACITY);
+        } else if !in_memory && self.in_memory {
+            self.entries = self.entries(memory);
+        }
+        self.in_memory = in_memory;
+    }
+
+    pub fn entries(&self, memory: &Memory) -> Vec<u16> {
+        if self.in_memory {
+            (0..self.entries.len())
+                .map(|level| read_entry(memory, level))
+                .collect()
+        } else {
+            self.entries.clone()
+        }
+    }
+
+    // Returns false, leaving the stack alone, if it is full
+    pub fn push(&mut self, address: u16, memory: &mut Memory) -> bool {
+        let level = self.entries.len();
+        let full = match self.depth {
+            StackDepth::Limited(depth) => level >= depth,
+            // Short of what a save state can record
+            StackDepth::Unlimited => level >= u16::MAX as usize,
+        };
+        if full || (self.in_memory && level >= VIP_STACK_CAPACITY) {
+            return false;
+        }
+        if self.in_memory {
+            write_entry(memory, level, address);
+        }
+        self.entries.push(address);
+        true
+    }
+
+    // Returns None if the stack is empty
+    pub fn pop(&mut self, memory: &Memory) -> Option<u16> {
+        let address = self.entries.pop()?;
+        if self.in_memory {
+            Some(read_entry(memory, self.entries.len()))
+        } else {
+            Some(address)
+        }
+    }
+
+    // Entries are padded to the default depth so states stay the same size as calls come
+    // and go, which keeps rewind's diffs small
+    pub fn save_state(&self, writer: &mut StateWriter, memory: &Memory) {
+        let entries = self.entries(memory);
+        writer.u16(entries.len() as u16);
+        let padding = DEFAULT_STACK_DEPTH.saturating_sub(entries.len());
+        for &address in entries.iter().chain(std::iter::repeat_n(&0, padding)) {
+            writer.u16(address);
+        }
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        let len = reader.u16()? as usize;
+        let mut entries = Vec::with_capacity(len);
+        for _ in 0..len.max(DEFAULT_STACK_DEPTH) {
+            entries.push(reader.u16()?);
+        }
+        entries.truncate(len);
+        self.entries = entries;
+        Ok(())
+    }
+}
+
+// Level 0 is the first return address pushed, at the top of the area
+fn entry_address(level: usize) -> usize {
+    VIP_STACK_END - 2 * (level + 1)
+}
+
+fn read_entry(memory: &Memory, level: usize) -> u16 {
+    let address = entry_address(level);
+    (memory.read_byte(address) as u16) << 8 | memory.read_byte(address + 1) as u16
+}
+
+fn write_entry(memory: &mut Memory, level: usize, value: u16) {
+    let address = entry_address(level);
+    memory.write_byte(address, (value >> 8) as u8);
+    memory.write_byte(address + 1, value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::error::Chip8Error;
+    use crate::chip8::quirks::Quirks;
+    use crate::chip8::rom::Rom;
+    use crate::chip8::{Chip8, ROM_START_ADDRESS};
+
+    // Pushes return addresses 0x200, 0x202, ... until the stack refuses one, giving how
+    // many it took
+    fn fill(stack: &mut Stack, memory: &mut Memory, limit: usize) -> usize {
+        (0..limit)
+            .take_while(|&level| stack.push(0x200 + 2 * level as u16, memory))
+            .count()
+    }
+
+    // A machine whose program calls itself forever
+    fn recursing(quirks: Quirks) -> Chip8 {
+        let mut chip8 = Chip8::new(quirks);
+        chip8.load_rom(&Rom::new(vec![0x22, 0x00])).unwrap();
+        chip8
+    }
+
+    // How many calls the machine makes before one overflows the stack
+    fn calls_before_overflow(chip8: &mut Chip8) -> usize {
+        for calls in 0.. {
+            match chip8.emulate_cycle() {
+                Ok(_) => {}
+                Err(Chip8Error::StackOverflow { pc }) => {
+                    assert_eq!(pc as usize, ROM_START_ADDRESS);
+                    return calls;
+                }
+                Err(err) => panic!("unexpected error {}", err),
+            }
+        }
+        unreachable!()
+    }
+
+    #[test]
+    fn the_vip_preset_overflows_after_12_calls_and_others_after_16() {
+        assert_eq!(
+            calls_before_overflow(&mut recursing(Quirks::COSMAC_VIP)),
+            12
+        );
+        assert_eq!(
+            calls_before_overflow(&mut recursing(Quirks::SUPER_CHIP)),
+            16
+        );
+        // The depth is a quirk of its own, kept when other quirks change
+        let modified = Quirks {
+            shift_uses_vy: false,
+            ..Quirks::COSMAC_VIP
+        };
+        assert_eq!(calls_before_overflow(&mut recursing(modified)), 12);
+        let modified = Quirks {
+            vip_stack_depth: true,
+            ..Quirks::SUPER_CHIP
+        };
+        assert_eq!(calls_before_overflow(&mut recursing(modified)), 12);
+    }
+
+    #[test]
+    fn limited_stacks_refuse_pushes_past_their_depth() {
+        let mut memory = Memory::new();
+        let mut stack = Stack::new(StackDepth::Limited(VIP_STACK_DEPTH));
+        assert_eq!(fill(&mut stack, &mut memory, 100), 12);
+        assert_eq!(stack.entries(&memory).len(), 12);
+
+        stack.set_depth(StackDepth::Limited(DEFAULT_STACK_DEPTH));
+        assert_eq!(fill(&mut stack, &mut memory, 100), 4);
+        assert_eq!(stack.pop(&memory), Some(0x206));
+    }
+
+    #[test]
+    fn unlimited_stacks_keep_growing() {
+        let mut memory = Memory::new();
+        let mut stack = Stack::new(StackDepth::Unlimited);
+        assert_eq!(fill(&mut stack, &mut memory, 1000), 1000);
+        assert_eq!(stack.pop(&memory), Some(0x200 + 2 * 999));
+    }
+
+    #[test]
+    fn popping_an_empty_stack_underflows() {
+        let mut memory = Memory::new();
+        let mut stack = Stack::new(StackDepth::Limited(DEFAULT_STACK_DEPTH));
+        assert_eq!(stack.pop(&memory), None);
+        stack.push(0x234, &mut memory);
+        assert_eq!(stack.pop(&memory), Some(0x234));
+        assert_eq!(stack.pop(&memory), None);
+
+        let mut chip8 = Chip8::default();
+        chip8.load_rom(&Rom::new(vec![0x00, 0xEE])).unwrap();
+        assert_eq!(
+            chip8.emulate_cycle(),
+            Err(Chip8Error::StackUnderflow { pc: 0x200 })
+        );
+    }
+
+    #[test]
+    fn the_vip_stack_grows_down_from_0xecf() {
+        let mut memory = Memory::new();
+        let mut stack = Stack::new(StackDepth::Unlimited);
+        stack.push(0x123, &mut memory);
+        stack.set_in_memory(true, &mut memory);
+        stack.push(0x456, &mut memory);
+        assert_eq!(memory.read_byte(0xECE), 0x01);
+        assert_eq!(memory.read_byte(0xECF), 0x23);
+        assert_eq!(memory.read_byte(0xECC), 0x04);
+        assert_eq!(memory.read_byte(0xECD), 0x56);
+
+        // The area holds 24 addresses, down to 0xEA0, whatever the depth limit
+        assert_eq!(fill(&mut stack, &mut memory, 100), 22);
+        assert_eq!(memory.read_byte(VIP_STACK_ADDRESS), 0x02);
+        assert_eq!(memory.read_byte(VIP_STACK_ADDRESS + 1), 0x2A);
+        assert_eq!(memory.read_byte(VIP_STACK_ADDRESS - 1), 0);
+    }
+
+    #[test]
+    fn programs_can_patch_return_addresses_on_the_vip_stack() {
+        let mut memory = Memory::new();
+        let mut stack = Stack::new(StackDepth::Limited(VIP_STACK_DEPTH));
+        stack.set_in_memory(true, &mut memory);
+        stack.push(0x300, &mut memory);
+        memory.write_byte(0xECE, 0x04);
+        assert_eq!(stack.entries(&memory), vec![0x400]);
+        assert_eq!(stack.pop(&memory), Some(0x400));
+
+        // Moving out of memory keeps the patched addresses
+        stack.push(0x300, &mut memory);
+        memory.write_byte(0xECF, 0x10);
+        stack.set_in_memory(false, &mut memory);
+        assert_eq!(stack.pop(&memory), Some(0x310));
+    }
+
+    #[test]
+    fn states_are_padded_to_the_default_depth_and_round_trip() {
+        let memory = Memory::new();
+        let mut stack = Stack::new(StackDepth::Unlimited);
+        let mut scratch = Memory::new();
+        let state = |stack: &Stack| {
+            let mut writer = StateWriter::new();
+            stack.save_state(&mut writer, &memory);
+            writer.into_bytes()
+        };
+
+        assert_eq!(state(&stack).len(), 2 + 2 * DEFAULT_STACK_DEPTH);
+        fill(&mut stack, &mut scratch, 3);
+        let shallow = state(&stack);
+        assert_eq!(shallow.len(), 2 + 2 * DEFAULT_STACK_DEPTH);
+        fill(&mut stack, &mut scratch, 17);
+        let deep = state(&stack);
+        assert_eq!(deep.len(), 2 + 2 * 20);
+
+        for (bytes, expected) in [(&shallow, 3), (&deep, 20)] {
+            let mut restored = Stack::new(StackDepth::Unlimited);
+            let mut reader = StateReader::new(bytes);
+            restored.load_state(&mut reader).unwrap();
+            assert!(reader.is_empty());
+            assert_eq!(restored.entries(&memory).len(), expected);
+            assert_eq!(state(&restored), *bytes);
+        }
+        assert_eq!(
+            Stack::new(StackDepth::Unlimited).load_state(&mut StateReader::new(&shallow[..10])),
+            Err(SaveStateError::Truncated)
+        );
+    }
+}
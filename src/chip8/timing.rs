@@ -0,0 +1,188 @@
+use super::Chip8;
+use super::instruction::Instruction;
+use super::register::RegisterIndex;
+
+// The VIP's 1802 runs at 1.76064 MHz and takes 8 clock pulses per machine cycle
+pub const VIP_MACHINE_CYCLES_PER_SECOND: u32 = 1_760_640 / 8;
+pub const VIP_MACHINE_CYCLES_PER_FRAME: u32 = VIP_MACHINE_CYCLES_PER_SECOND / 60;
+// Display DMA steals one machine cycle per byte shown: 8 bytes on each of 128 scanlines
+pub const VIP_DISPLAY_DMA_CYCLES: u32 = 1024;
+
+// Instructions per frame for the fixed model when a frontend does not choose a rate
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 5400 / 60;
+
+// Machine cycles the interpreter spends fetching and decoding every instruction
+const VIP_FETCH_CYCLES: u32 = 40;
+// Charged for instructions the VIP interpreter does not have
+const VIP_UNKNOWN_CYCLES: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimingModel {
+    // Every instruction costs the same and a frame runs this many of them
+    Fixed(u32),
+    // Instructions cost their COSMAC VIP machine cycles
+    CosmacVip,
+}
+
+impl TimingModel {
+    // Parses a frontend's `--timing` value: "fixed" runs `instructions_per_frame`
+    // instructions a frame, "vip" uses the VIP cycle counts
+    pub fn from_name(name: &str, instructions_per_frame: u32) -> Result<Self, String> {
+        match name {
+            "fixed" => Ok(TimingModel::Fixed(instructions_per_frame)),
+            "vip" => Ok(TimingModel::CosmacVip),
+            _ => Err(format!(
+                "unknown timing model '{}' (expected fixed or vip)",
+                name
+            )),
+        }
+    }
+
+    // Budget of one 60 Hz frame in the model's units
+    pub fn frame_budget(&self) -> u32 {
+        match self {
+            TimingModel::Fixed(instructions) => *instructions,
+            TimingModel::CosmacVip => VIP_MACHINE_CYCLES_PER_FRAME - VIP_DISPLAY_DMA_CYCLES,
+        }
+    }
+}
+
+impl Default for TimingModel {
+    fn default() -> Self {
+        TimingModel::Fixed(DEFAULT_INSTRUCTIONS_PER_FRAME)
+    }
+}
+
+// Machine cycles the VIP interpreter takes to run `instruction` from the machine's
+// current state. The figures are approximate, rounded from the interpreter's routines.
+pub fn vip_cycles(chip8: &Chip8, instruction: &Instruction) -> u32 {
+    let v = |x: &RegisterIndex| chip8.registers().get(*x);
+    let skip = |taken: bool, base: u32| if taken { base + 4 } else { base };
+
+    let execute = match instruction {
+        Instruction::Clear => 3078,
+        Instruction::Return => 10,
+        Instruction::Jump(_) => 12,
+        Instruction::Call(_) => 26,
+        Instruction::SkipEqual(x, kk) => skip(v(x) == *kk, 10),
+        Instruction::SkipNotEqual(x, kk) => skip(v(x) != *kk, 10),
+        Instruction::SkipEqualRegister(x, y) => skip(v(x) == v(y), 14),
+        Instruction::SkipNotEqualRegister(x, y) => skip(v(x) != v(y), 14),
+        Instruction::Load(..) => 6,
+        Instruction::Add(..) => 10,
+        Instruction::LoadRegister(..)
+        | Instruction::LoadOr(..)
+        | Instruction::LoadAnd(..)
+        | Instruction::LoadXor(..)
+        | Instruction::LoadAdd(..)
+        | Instruction::LoadSub(..)
+        | Instruction::LoadShiftRight(..)
+        | Instruction::LoadSubNegative(..)
+        | Instruction::LoadShiftLeft(..) => 44,
+        Instruction::LoadIndexRegister(_) => 12,
+        Instruction::JumpWithOffset(_) => 22,
+        Instruction::Random(..) => 36,
+        Instruction::Display(x, _, n) => {
+            // Unaligned sprites are shifted bit by bit into two bytes per row
+            let rows = match n.value() {
+                0 => 16,
+                n => n as u32,
+            };
+            let shift = (v(x) % 8) as u32;
+            let per_row = if shift == 0 { 34 } else { 46 + 4 * shift };
+            26 + rows * per_row
+        }
+        Instruction::SkipKeyPress(x) => skip(chip8.keypad.is_key_pressed(v(x) & 0xF), 14),
+        Instruction::SkipKeyNotPress(x) => skip(!chip8.keypad.is_key_pressed(v(x) & 0xF), 14),
+        Instruction::LoadDelayTimer(_)
+        | Instruction::LoadKeyPress(_)
+        | Instruction::StoreDelayTimer(_)
+        | Instruction::StoreSoundTimer(_) => 10,
+        Instruction::AddIndexRegister(_) | Instruction::LoadFontCharacter(_) => 16,
+        Instruction::LoadBinaryCodedDecimal(x) => {
+            // Each digit is found by repeated subtraction
+            let value = v(x) as u32;
+            80 + 16 * (value / 100 + value / 10 % 10 + value % 10)
+        }
+        Instruction::StoreRegisters(x) | Instruction::LoadRegisters(x) => {
+            14 + 14 * (x.value() as u32 + 1)
+        }
+        _ => VIP_UNKNOWN_CYCLES,
+    };
+
+    VIP_FETCH_CYCLES + execute
+}
+
+// Meters out emulated time a frame at a time for `Chip8::run_frame`, which calls
+// `start_frame` every 60 Hz tick and executes instructions while `has_time` holds,
+// spending the cost of each. A sprite draw waiting for vblank ends the frame early in
+// either model; that wait is the core's display-wait quirk, not the clock's.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameClock {
+    model: TimingModel,
+    // Budget left in this frame; negative when the last instruction overran it, in which
+    // case the overrun is taken from the next frame
+    remaining: i64,
+}
+
+impl FrameClock {
+    pub fn new(model: TimingModel) -> Self {
+        Self {
+            model,
+            remaining: 0,
+        }
+    }
+
+    pub fn model(&self) -> TimingModel {
+        self.model
+    }
+
+    pub fn start_frame(&mut self) {
+        self.remaining = self.model.frame_budget() as i64 + self.remaining.min(0);
+    }
+
+    pub fn has_time(&self) -> bool {
+        self.remaining > 0
+    }
+
+    // Cost of the instruction at the PC, which the caller is about to execute
+    pub fn cost(&self, chip8: &Chip8) -> u32 {
+        match (self.model, chip8.peek_instruction()) {
+            (TimingModel::Fixed(_), _) => 1,
+            (TimingModel::CosmacVip, Ok(instruction)) => vip_cycles(chip8, &instruction),
+            (TimingModel::CosmacVip, Err(_)) => VIP_FETCH_CYCLES,
+        }
+    }
+
+    pub fn spend(&mut self, cost: u32) {
+        self.remaining -= cost as i64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::KeyState;
+    use crate::chip8::quirks::Quirks;
+    use crate::chip8::rom::Rom;
+
+    #[test]
+    fn draws_waiting_for_vblank_are_charged_once() {
+        // 52 ADDs of 50 cycles leave 44 of the frame for a 100-cycle draw, which waits for
+        // vblank. Next frame the draw and the jump back take 152 cycles, leaving room for
+        // 50 more ADDs, the last of which overruns.
+        let mut program: Vec<u16> = vec![0x7101; 52];
+        program.extend([0xD001, 0x1200]);
+        let data = program.iter().flat_map(|word| word.to_be_bytes()).collect();
+        let mut chip8 = Chip8::new(Quirks::COSMAC_VIP);
+        chip8.load_rom(&Rom::new(data)).unwrap();
+        chip8.set_timing(TimingModel::CosmacVip);
+        let added = |chip8: &Chip8| chip8.registers().get(RegisterIndex::try_from(1).unwrap());
+
+        chip8.run_frame(&KeyState::new());
+        assert_eq!(added(&chip8), 52);
+        assert_eq!(chip8.program_counter(), 0x268);
+        chip8.run_frame(&KeyState::new());
+        assert_eq!(added(&chip8), 102);
+    }
+}
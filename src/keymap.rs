@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+
+use toml::{Table, Value};
+
+use crate::chip8::KeyState;
+
+// The layouts shipped in keymaps/, which frontends also accept by name. The first is the
+// built-in layout.
+const BUNDLED_KEYMAPS: [(&str, &str); 3] = [
+    ("qwerty", include_str!("../keymaps/qwerty.toml")),
+    ("azerty", include_str!("../keymaps/azerty.toml")),
+    ("dvorak", include_str!("../keymaps/dvorak.toml")),
+];
+
+// Which CHIP-8 key each host key and gamepad button presses. Frontends look keys up by
+// name, so the same file works for any of them: SDL key names for the keyboard and SDL
+// controller button names for gamepads, compared without regard to case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    keyboard: HashMap<String, u8>,
+    gamepad: HashMap<String, u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeymapError(pub String);
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid keymap: {}", self.0)
+    }
+}
+
+impl std::error::Error for KeymapError {}
+
+impl Keymap {
+    // Reads a keymap file. `[keyboard]` and `[gamepad]` map each CHIP-8 key (0-F) to a host
+    // key name or a list of them; `[rom.<sha1>.keyboard]` and `[rom.<sha1>.gamepad]` rebind
+    // keys for the ROM with that SHA-1, which is `rom_hash` here.
+    pub fn parse(text: &str, rom_hash: &[u8]) -> Result<Self, KeymapError> {
+        let table: Table = text
+            .parse()
+            .map_err(|e: toml::de::Error| KeymapError(e.to_string()))?;
+        let rom_key: String = rom_hash
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        let mut keymap = Keymap {
+            keyboard: HashMap::new(),
+            gamepad: HashMap::new(),
+        };
+        for (name, value) in &table {
+            match name.as_str() {
+                "keyboard" => bind(&mut keymap.keyboard, value, "keyboard")?,
+                "gamepad" => bind(&mut keymap.gamepad, value, "gamepad")?,
+                "rom" => {}
+                _ => return Err(KeymapError(format!("unknown section [{}]", name))),
+            }
+        }
+
+        // Overrides are applied after the base layout, whatever order the file is in
+        let roms = match table.get("rom") {
+            Some(Value::Table(roms)) => roms.iter().collect(),
+            Some(_) => return Err(KeymapError("[rom] must be a table".to_string())),
+            None => Vec::new(),
+        };
+        for (hash, value) in roms {
+            let Value::Table(sections) = value else {
+                return Err(KeymapError(format!("[rom.{}] must be a table", hash)));
+            };
+            for (name, value) in sections {
+                let context = format!("rom.{}.{}", hash, name);
+                let bindings = match name.as_str() {
+                    "keyboard" => &mut keymap.keyboard,
+                    "gamepad" => &mut keymap.gamepad,
+                    _ => return Err(KeymapError(format!("unknown section [{}]", context))),
+                };
+                if hash.eq_ignore_ascii_case(&rom_key) {
+                    bind(bindings, value, &context)?;
+                } else {
+                    // Still check it, so a mistake is found before running that ROM
+                    bind(&mut HashMap::new(), value, &context)?;
+                }
+            }
+        }
+
+        Ok(keymap)
+    }
+
+    pub fn keyboard_key(&self, name: &str) -> Option<u8> {
+        self.keyboard.get(&name.to_lowercase()).copied()
+    }
+
+    pub fn gamepad_key(&self, button: &str) -> Option<u8> {
+        self.gamepad.get(&button.to_lowercase()).copied()
+    }
+
+    // Makes a host key press `key`, on top of whatever else presses it
+    pub fn bind_keyboard(&mut self, name: &str, key: u8) {
+        self.keyboard.insert(name.to_lowercase(), key);
+    }
+
+    pub fn bind_gamepad(&mut self, button: &str, key: u8) {
+        self.gamepad.insert(button.to_lowercase(), key);
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::parse(BUNDLED_KEYMAPS[0].1, &[]).expect("built-in keymap is valid")
+    }
+}
+
+// The CHIP-8 key each held host input presses. A CHIP-8 key bound to several inputs, such
+// as a keyboard key and a gamepad button, stays down until every one of them is released.
+#[derive(Debug, Clone)]
+pub struct HeldInputs<I> {
+    held: HashMap<I, u8>,
+}
+
+impl<I: Eq + Hash> HeldInputs<I> {
+    pub fn new() -> Self {
+        Self {
+            held: HashMap::new(),
+        }
+    }
+
+    pub fn press(&mut self, input: I, key: u8) {
+        self.held.insert(input, key);
+    }
+
+    pub fn release(&mut self, input: &I) {
+        self.held.remove(input);
+    }
+
+    // Releases every held input `unplugged` picks out, e.g. a disconnected controller's
+    pub fn release_all(&mut self, unplugged: impl Fn(&I) -> bool) {
+        self.held.retain(|input, _| !unplugged(input));
+    }
+
+    pub fn keys(&self) -> KeyState {
+        let mut keys = KeyState::new();
+        for &key in self.held.values() {
+            keys.set_key_pressed(key, true);
+        }
+        keys
+    }
+}
+
+impl<I: Eq + Hash> Default for HeldInputs<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The text of the bundled layout called `name` ("qwerty", "azerty" or "dvorak"), for a
+// frontend's `--keymap` to take in place of a file
+pub fn bundled(name: &str) -> Option<&'static str> {
+    BUNDLED_KEYMAPS
+        .iter()
+        .find(|(bundled, _)| name.eq_ignore_ascii_case(bundled))
+        .map(|(_, text)| *text)
+}
+
+// Adds the bindings of a keyboard or gamepad table, replacing any existing bindings of
+// the CHIP-8 keys it lists
+fn bind(
+    bindings: &mut HashMap<String, u8>,
+    value: &Value,
+    context: &str,
+) -> Result<(), KeymapError> {
+    let section = parse_section(value, context)?;
+    bindings.retain(|_, key| !section.iter().any(|(rebound, _)| rebound == key));
+    for (key, names) in section {
+        for name in names {
+            bindings.insert(name, key);
+        }
+    }
+    Ok(())
+}
+
+// The CHIP-8 keys of a section with their lowercased host key names
+fn parse_section(value: &Value, context: &str) -> Result<Vec<(u8, Vec<String>)>, KeymapError> {
+    let Value::Table(table) = value else {
+        return Err(KeymapError(format!("[{}] must be a table", context)));
+    };
+
+    let mut section = Vec::new();
+    for (key, names) in table {
+        let chip8_key = u8::from_str_radix(key, 16)
+            .ok()
+            .filter(|key| *key < 16)
+            .ok_or_else(|| {
+                KeymapError(format!(
+                    "'{}' in [{}] is not a CHIP-8 key (0-F)",
+                    key, context
+                ))
+            })?;
+        let names = match names {
+            Value::String(name) => vec![name.to_lowercase()],
+            Value::Array(names) => names
+                .iter()
+                .map(|name| name.as_str().map(str::to_lowercase))
+                .collect::<Option<_>>()
+                .ok_or_else(|| {
+                    KeymapError(format!(
+                        "key {} in [{}] must list names as strings",
+                        key, context
+                    ))
+                })?,
+            _ => {
+                return Err(KeymapError(format!(
+                    "key {} in [{}] must be a name or a list of names",
+                    key, context
+                )));
+            }
+        };
+        section.push((chip8_key, names));
+    }
+    Ok(section)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = r#"
+        [rom.00ff.keyboard]
+        5 = "Up"
+
+        [keyboard]
+        5 = ["W", "K"]
+        6 = "E"
+
+        [gamepad]
+        5 = "DPUp"
+
+        [rom.ABCD.gamepad]
+        6 = ["a", "b"]
+    "#;
+
+    #[test]
+    fn overrides_apply_to_the_rom_with_their_hash() {
+        let keymap = Keymap::parse(FILE, &[0x00, 0xFF]).unwrap();
+        // Rebinding key 5 drops both of its keyboard keys, but not its gamepad button
+        assert_eq!(keymap.keyboard_key("up"), Some(0x5));
+        assert_eq!(keymap.keyboard_key("W"), None);
+        assert_eq!(keymap.keyboard_key("K"), None);
+        assert_eq!(keymap.keyboard_key("E"), Some(0x6));
+        assert_eq!(keymap.gamepad_key("dpup"), Some(0x5));
+        assert_eq!(keymap.gamepad_key("A"), None);
+
+        // Hashes match without regard to case
+        let keymap = Keymap::parse(FILE, &[0xAB, 0xCD]).unwrap();
+        assert_eq!(keymap.gamepad_key("a"), Some(0x6));
+        assert_eq!(keymap.gamepad_key("B"), Some(0x6));
+        assert_eq!(keymap.keyboard_key("W"), Some(0x5));
+        assert_eq!(keymap.keyboard_key("Up"), None);
+
+        let keymap = Keymap::parse(FILE, &[0x12, 0x34]).unwrap();
+        assert_eq!(keymap.keyboard_key("K"), Some(0x5));
+        assert_eq!(keymap.keyboard_key("Up"), None);
+        assert_eq!(keymap.gamepad_key("a"), None);
+    }
+
+    #[test]
+    fn mistakes_are_reported_even_for_other_roms() {
+        let error = |text: &str| Keymap::parse(text, &[0x12, 0x34]).unwrap_err().0;
+
+        assert_eq!(error("[mouse]\n1 = \"x\""), "unknown section [mouse]");
+        assert_eq!(
+            error("[rom.00ff.mouse]\n1 = \"x\""),
+            "unknown section [rom.00ff.mouse]"
+        );
+        assert_eq!(
+            error("[keyboard]\nG = \"x\""),
+            "'G' in [keyboard] is not a CHIP-8 key (0-F)"
+        );
+        assert_eq!(
+            error("[rom.00ff.keyboard]\n10 = \"x\""),
+            "'10' in [rom.00ff.keyboard] is not a CHIP-8 key (0-F)"
+        );
+        assert_eq!(
+            error("[gamepad]\n1 = 2"),
+            "key 1 in [gamepad] must be a name or a list of names"
+        );
+        assert_eq!(
+            error("[gamepad]\n1 = [\"a\", 2]"),
+            "key 1 in [gamepad] must list names as strings"
+        );
+        assert_eq!(error("keyboard = 1"), "[keyboard] must be a table");
+        assert!(Keymap::parse("[keyboard", &[]).is_err());
+    }
+
+    #[test]
+    fn bundled_layouts_are_found_by_name() {
+        for name in ["qwerty", "azerty", "Dvorak"] {
+            let text = bundled(name).unwrap();
+            Keymap::parse(text, &[]).unwrap();
+        }
+        assert_eq!(bundled("colemak"), None);
+
+        let azerty = Keymap::parse(bundled("azerty").unwrap(), &[]).unwrap();
+        assert_eq!(azerty.keyboard_key("A"), Some(0x4));
+        assert_eq!(Keymap::default().keyboard_key("Q"), Some(0x4));
+    }
+
+    #[test]
+    fn keys_stay_down_while_any_input_holds_them() {
+        let mut held = HeldInputs::new();
+        held.press("w", 0x5);
+        held.press("pad 1 up", 0x5);
+        held.press("pad 2 a", 0x6);
+        held.release(&"w");
+        assert!(held.keys().is_key_pressed(0x5));
+        held.release(&"pad 1 up");
+        assert!(!held.keys().is_key_pressed(0x5));
+
+        // Unplugging a controller releases only what it held
+        held.press("e", 0x6);
+        held.press("d", 0x9);
+        held.release_all(|input| input.starts_with("pad 2"));
+        assert!(held.keys().is_key_pressed(0x6));
+        assert!(held.keys().is_key_pressed(0x9));
+        held.release(&"e");
+        assert!(!held.keys().is_key_pressed(0x6));
+    }
+}
@@ -1 +1,8 @@
 pub mod chip8;
+#[cfg(feature = "keymap")]
+pub mod keymap;
+#[cfg(feature = "romdb")]
+pub mod romdb;
+// Also built for native tests, which call the exports directly
+#[cfg(any(target_arch = "wasm32", test))]
+mod wasm;
@@ -1,31 +1,77 @@
 extern crate sdl2;
 
 use sdl2::audio::AudioCallback;
+use sdl2::controller::{Button, GameController};
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Scancode};
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+use std::sync::mpsc::{self, Receiver};
 use std::{
-    env, fs,
+    env, fs, thread,
     time::{Duration, Instant},
 };
 
-use chip8_rust::chip8::Chip8;
+use chip8_rust::chip8::debugger::{Command, Debugger, StopReason};
 use chip8_rust::chip8::display::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use chip8_rust::chip8::movie::Movie;
+use chip8_rust::chip8::quirks::{Quirks, UnknownPreset};
+use chip8_rust::chip8::rewind::RewindBuffer;
+use chip8_rust::chip8::rom::{self, Rom};
+use chip8_rust::chip8::timing::TimingModel;
+use chip8_rust::chip8::{
+    AUDIO_PATTERN_SIZE, Chip8, MemoryPolicy, NUM_RPL_FLAGS, ROM_START_ADDRESS, StackDepth,
+};
+use chip8_rust::keymap::{self, HeldInputs, Keymap};
+use chip8_rust::romdb::{RomDatabase, RomInfo};
 
 const SCALE_FACTOR: u32 = 10; // Increase this for a larger window
 const WINDOW_WIDTH: u32 = DISPLAY_WIDTH as u32 * SCALE_FACTOR;
 const WINDOW_HEIGHT: u32 = DISPLAY_HEIGHT as u32 * SCALE_FACTOR;
 
+// Quick save slots are numbered 0-9; F6 selects the next one
+const NUM_SAVE_SLOTS: u8 = 10;
+
+// Rewind history kept while running, bounded by both duration and memory
+const REWIND_SECONDS: usize = 10;
+const REWIND_MAX_BYTES: usize = 32 * 1024 * 1024;
+
 const CYCLES_PER_SECOND: u32 = 5400;
 const TARGET_FPS: u64 = 60;
 const MICROSECONDS_PER_FRAME: u64 = 1_000_000 / TARGET_FPS;
 
+// Background followed by the colours for plane 1, plane 2 and both planes lit
+const PALETTE: [Color; 4] = [
+    Color::RGB(0, 0, 0),
+    Color::RGB(0, 255, 0),
+    Color::RGB(0, 120, 255),
+    Color::RGB(255, 255, 255),
+];
+
+// Host keys and controller buttons for the controls a ROM database entry can name
+const DATABASE_CONTROLS: [(&str, &str, &str); 6] = [
+    ("up", "Up", "dpup"),
+    ("down", "Down", "dpdown"),
+    ("left", "Left", "dpleft"),
+    ("right", "Right", "dpright"),
+    ("a", "Space", "a"),
+    ("b", "Left Shift", "b"),
+];
+
+const PATTERN_BITS: f32 = (AUDIO_PATTERN_SIZE * 8) as f32;
+
 struct SquareWave {
     phase_inc: f32,
     phase: f32,
     volume: f32,
+    sample_freq: f32,
+    // XO-CHIP programs replace the tone with a 128-bit pattern played at `pattern_rate` bits per second
+    pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+    pattern_rate: f32,
+    pattern_phase: f32,
 }
 
 impl AudioCallback for SquareWave {
@@ -33,11 +79,16 @@ impl AudioCallback for SquareWave {
 
     fn callback(&mut self, out: &mut [f32]) {
         for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
+            let high = match &self.pattern {
+                Some(pattern) => {
+                    let bit = self.pattern_phase as usize;
+                    self.pattern_phase =
+                        (self.pattern_phase + self.pattern_rate / self.sample_freq) % PATTERN_BITS;
+                    pattern[bit / 8] & (0x80 >> (bit % 8)) != 0
+                }
+                None => self.phase <= 0.5,
             };
+            *x = if high { self.volume } else { -self.volume };
             self.phase = (self.phase + self.phase_inc) % 1.0;
         }
     }
@@ -45,35 +96,180 @@ impl AudioCallback for SquareWave {
 
 fn main() -> Result<(), String> {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} <rom_file>", args[0]);
+    let Some(options) = parse_args(&args[1..])? else {
+        eprintln!(
+            "Usage: {} <rom_file> [--quirks <preset>] [--load-address <addr>] \
+             [--timing fixed|vip] [--seed <n>] [--romdb <dir> | --no-romdb] \
+             [--memory wrap|fault|ignore] [--stack-depth <n>|unlimited] [--vip-stack] \
+             [--keymap <file>|qwerty|azerty|dvorak] [--record <movie> | --replay <movie>] \
+             [--debug]",
+            args[0]
+        );
         std::process::exit(1);
+    };
+    let rom_path = &options.rom_path;
+    let rom =
+        Rom::read(rom_path).map_err(|e| format!("Failed to read ROM file {}: {}", rom_path, e))?;
+
+    // Known ROMs come with the platform, speed, colours and controls they were made for.
+    // Anything also given on the command line keeps the command line's value.
+    let database = match (&options.romdb_path, options.no_romdb) {
+        (_, true) => None,
+        (Some(dir), false) => Some(RomDatabase::read(dir).map_err(|e| e.to_string())?),
+        (None, false) => Some(RomDatabase::default()),
+    };
+    let info = database
+        .as_ref()
+        .and_then(|database| database.lookup(rom.sha1()))
+        .cloned();
+    if let Some(info) = &info {
+        println!("{}", describe(info));
+    }
+    let database_quirks = info
+        .as_ref()
+        .and_then(|info| info.platform.as_ref())
+        .map(|platform| platform.quirks);
+    let database_timing = info
+        .as_ref()
+        .and_then(|info| info.tickrate)
+        .map(TimingModel::Fixed);
+
+    let load_address = options
+        .load_address
+        .or(info.as_ref().and_then(|info| info.start_address))
+        .unwrap_or(ROM_START_ADDRESS);
+    let rom = rom.with_load_address(load_address);
+
+    let replay = match &options.replay_path {
+        Some(path) => {
+            let data = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+            Some(Movie::parse(&data).map_err(|e| format!("{}: {}", path, e))?)
+        }
+        None => None,
+    };
+
+    let mut chip8 = match &replay {
+        // A movie brings the quirks, timing, seed, RPL flags, memory policy and stack it
+        // was recorded with
+        Some(movie) => movie
+            .start(&rom)
+            .map_err(|e| format!("Cannot replay movie: {}", e))?,
+        None => {
+            let quirks = match options.quirks.or(database_quirks) {
+                Some(quirks) => quirks,
+                None => {
+                    if let Some(summary) = rom.hint_summary() {
+                        println!("{}", summary);
+                    }
+                    rom.suggested_quirks().unwrap_or_default()
+                }
+            };
+            let mut chip8 = Chip8::new(quirks);
+            chip8
+                .load_rom(&rom)
+                .map_err(|e| format!("Failed to load {}: {}", rom_path, e))?;
+            chip8.set_timing(
+                options
+                    .timing
+                    .or(database_timing)
+                    .unwrap_or(TimingModel::Fixed(CYCLES_PER_SECOND / TARGET_FPS as u32)),
+            );
+            if let Some(seed) = options.seed {
+                chip8.seed_random(seed);
+            }
+            chip8.set_memory_policy(options.memory_policy.unwrap_or_default());
+            if let Some(depth) = options.stack_depth {
+                chip8.set_stack_depth(depth);
+            }
+            chip8.set_stack_in_memory(options.vip_stack);
+            chip8
+        }
+    };
+
+    // SUPER-CHIP RPL flags are kept next to the ROM so high scores survive restarts
+    let flags_path = format!("{}.flags", rom_path);
+    if replay.is_none()
+        && let Ok(saved) = fs::read(&flags_path)
+    {
+        let mut flags = [0; NUM_RPL_FLAGS];
+        let len = saved.len().min(NUM_RPL_FLAGS);
+        flags[..len].copy_from_slice(&saved[..len]);
+        chip8.set_rpl_flags(&flags);
     }
-    let rom_path = &args[1];
-    let rom_data = fs::read(rom_path).expect("Failed to read ROM file");
+    let initial_flags = *chip8.rpl_flags();
+
+    // A keymap file may rebind keys for this ROM, so it is read once the ROM is loaded
+    let keymap = match &options.keymap_path {
+        Some(path) => {
+            // A bundled layout's name, else a file
+            let text = match keymap::bundled(path) {
+                Some(text) => text.to_string(),
+                None => fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read {}: {}", path, e))?,
+            };
+            Keymap::parse(&text, chip8.rom_hash()).map_err(|e| format!("{}: {}", path, e))?
+        }
+        // Without a keymap file the database's controls are added to the default layout
+        None => {
+            let mut keymap = Keymap::default();
+            for (control, key) in info.iter().flat_map(|info| &info.keys) {
+                if let Some((_, keyboard, gamepad)) = DATABASE_CONTROLS
+                    .iter()
+                    .find(|(name, _, _)| name == control)
+                {
+                    keymap.bind_keyboard(keyboard, *key);
+                    keymap.bind_gamepad(gamepad, *key);
+                }
+            }
+            keymap
+        }
+    };
 
-    let mut chip8 = Chip8::new();
-    chip8.load_rom(&rom_data);
+    let mut palette = PALETTE;
+    for (slot, [r, g, b]) in palette
+        .iter_mut()
+        .zip(info.iter().flat_map(|info| &info.colors))
+    {
+        *slot = Color::RGB(*r, *g, *b);
+    }
+
+    // Without --seed a recording picks its own, which the movie keeps
+    let mut recording = options
+        .record_path
+        .as_ref()
+        .map(|_| Movie::record(&mut chip8, options.seed.unwrap_or_else(rand::random)));
+    let mut movie_frame = 0;
+    // Jumping to another point in time would leave the movie out of step with the machine
+    let movie_active = recording.is_some() || replay.is_some();
 
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
     let audio_subsystem = sdl_context.audio()?;
+    let controller_subsystem = sdl_context.game_controller()?;
 
     let audio_spec = sdl2::audio::AudioSpecDesired {
         freq: Some(44100),
         channels: Some(1),
         samples: None,
     };
-    let device = audio_subsystem
+    let mut device = audio_subsystem
         .open_playback(None, &audio_spec, |spec| SquareWave {
             phase_inc: 440.0 / spec.freq as f32,
             phase: 0.0,
             volume: 0.25,
+            sample_freq: spec.freq as f32,
+            pattern: None,
+            pattern_rate: 0.0,
+            pattern_phase: 0.0,
         })
         .map_err(|e| e.to_string())?;
 
+    let window_title = match &info {
+        Some(info) => format!("CHIP-8 Emulator - {}", info.title),
+        None => "CHIP-8 Emulator".to_string(),
+    };
     let window = video_subsystem
-        .window("CHIP-8 Emulator", WINDOW_WIDTH, WINDOW_HEIGHT)
+        .window(&window_title, WINDOW_WIDTH, WINDOW_HEIGHT)
         .position_centered()
         .build()
         .map_err(|e| e.to_string())?;
@@ -81,8 +277,24 @@ fn main() -> Result<(), String> {
     let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
     let mut event_pump = sdl_context.event_pump()?;
 
-    let cycles_per_frame = CYCLES_PER_SECOND / TARGET_FPS as u32;
+    let mut held = HeldInputs::new();
+    // Open game controllers by joystick id. SDL reports the ones already plugged in as
+    // added when the event loop starts, so they are opened the same way as later ones.
+    let mut controllers: HashMap<u32, GameController> = HashMap::new();
     let mut last_frame_time = Instant::now();
+    let mut halted = false;
+    let mut save_slot = 0;
+    let mut frame_count: u64 = 0;
+    let mut rewind_buffer =
+        RewindBuffer::new(REWIND_SECONDS * TARGET_FPS as usize, REWIND_MAX_BYTES);
+
+    // With --debug, commands typed in the terminal drive the debugger and F10 breaks in
+    let mut debugger = Debugger::new();
+    let mut paused = false;
+    let debug_commands = options.debug.then(spawn_command_reader);
+    if debug_commands.is_some() {
+        println!("Debugger attached; press F10 to break, type 'help' for commands");
+    }
 
     'running: loop {
         // --- Event Handling ---
@@ -95,27 +307,112 @@ fn main() -> Result<(), String> {
                 } => {
                     break 'running;
                 }
-                Event::KeyDown { keycode, .. } => {
-                    if let Some(chip8_key) = map_key(keycode) {
-                        if chip8.is_waiting_for_key() {
-                            chip8.resolve_key_wait(chip8_key);
-                        } else {
-                            chip8.keypad.set_key_pressed(chip8_key, true);
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    ..
+                } => {
+                    let path = save_state_path(rom_path, save_slot);
+                    match fs::write(&path, chip8.save_state()) {
+                        Ok(()) => println!("Saved state to slot {}", save_slot),
+                        Err(err) => eprintln!("Failed to save state to {}: {}", path, err),
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F10),
+                    ..
+                } if debug_commands.is_some() => {
+                    paused = !paused;
+                    if paused {
+                        print!("{}", debugger.location(&chip8));
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    ..
+                } => {
+                    save_slot = (save_slot + 1) % NUM_SAVE_SLOTS;
+                    println!("Selected save slot {}", save_slot);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } if movie_active => {
+                    eprintln!("Loading states is disabled while recording or replaying a movie");
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => {
+                    let path = save_state_path(rom_path, save_slot);
+                    match fs::read(&path)
+                        .map_err(|e| e.to_string())
+                        .and_then(|data| chip8.load_state(&data).map_err(|e| e.to_string()))
+                    {
+                        Ok(()) => {
+                            halted = false;
+                            println!("Loaded state from slot {}", save_slot);
                         }
+                        Err(err) => eprintln!("Failed to load state from {}: {}", path, err),
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if let Some(chip8_key) = keymap.keyboard_key(&keycode.name()) {
+                        held.press(HostInput::Key(keycode), chip8_key);
                     }
                 }
-                Event::KeyUp { keycode, .. } => {
-                    if let Some(chip8_key) = map_key(keycode) {
-                        if !chip8.is_waiting_for_key() && chip8.is_waiting_for_release() {
-                            chip8.resolve_key_release();
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => held.release(&HostInput::Key(keycode)),
+                Event::ControllerDeviceAdded { which, .. } => {
+                    match controller_subsystem.open(which) {
+                        Ok(controller) => {
+                            println!("Connected controller: {}", controller.name());
+                            controllers.insert(controller.instance_id(), controller);
                         }
-                        chip8.keypad.set_key_pressed(chip8_key, false);
+                        Err(err) => eprintln!("Failed to open controller {}: {}", which, err),
                     }
                 }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    if let Some(controller) = controllers.remove(&which) {
+                        println!("Disconnected controller: {}", controller.name());
+                        // Its buttons never report being released
+                        held.release_all(
+                            |input| matches!(input, HostInput::Button(id, _) if *id == which),
+                        );
+                    }
+                }
+                Event::ControllerButtonDown { which, button, .. } => {
+                    if let Some(chip8_key) = keymap.gamepad_key(&button.string()) {
+                        held.press(HostInput::Button(which, button), chip8_key);
+                    }
+                }
+                Event::ControllerButtonUp { which, button, .. } => {
+                    held.release(&HostInput::Button(which, button));
+                }
                 _ => {}
             }
         }
 
+        // --- Debugger Commands ---
+        if let Some(commands) = &debug_commands {
+            while let Ok(line) = commands.try_recv() {
+                match line.parse::<Command>() {
+                    Ok(Command::Quit) => break 'running,
+                    Ok(Command::Continue) => paused = false,
+                    Ok(command) => {
+                        // Stepping is only meaningful while stopped
+                        paused = true;
+                        print!("{}", debugger.execute_command(&mut chip8, &command));
+                    }
+                    Err(err) => println!("{}", err),
+                }
+            }
+        }
+
         // --- Frame Rate Control ---
         let elapsed = last_frame_time.elapsed();
         if elapsed < Duration::from_micros(MICROSECONDS_PER_FRAME) {
@@ -124,33 +421,92 @@ fn main() -> Result<(), String> {
         last_frame_time = Instant::now();
 
         // --- CPU Emulation ---
-        if !chip8.is_waiting_for_key() && !chip8.is_waiting_for_release() {
-            for cycle_idx in 0..cycles_per_frame {
-                chip8.emulate_cycle(cycle_idx == 0);
-                if chip8.is_waiting_for_key() {
-                    break;
+        // Holding Backspace steps back through the recorded frames instead of running
+        let rewinding = !movie_active
+            && event_pump
+                .keyboard_state()
+                .is_scancode_pressed(Scancode::Backspace);
+        let mut sound_on = false;
+        if rewinding {
+            match rewind_buffer.rewind(&mut chip8) {
+                Ok(Some(frame)) => {
+                    frame_count = frame;
+                    halted = false;
                 }
+                Ok(None) => {}
+                Err(err) => eprintln!("Failed to rewind: {}", err),
             }
+        } else if !paused {
+            // A replay supplies the keys until it runs out, then the keyboard takes over
+            let input = match replay.as_ref().and_then(|movie| movie.frame(movie_frame)) {
+                Some(input) => *input,
+                None => {
+                    if replay
+                        .as_ref()
+                        .is_some_and(|movie| movie.len() == movie_frame)
+                    {
+                        println!("Replay finished after {} frames", movie_frame);
+                    }
+                    held.keys()
+                }
+            };
+            if let Some(movie) = &mut recording {
+                movie.push_frame(&input);
+            }
+            movie_frame += 1;
+
+            chip8.begin_frame(&input);
+            if !halted {
+                match debugger.run(&mut chip8) {
+                    None | Some(StopReason::WaitingForKey) => {}
+                    Some(StopReason::Exited) => break 'running,
+                    Some(StopReason::Error(err)) => {
+                        // Keep the window open with the last frame so the state can be inspected
+                        eprintln!("Emulation halted: {}", err);
+                        halted = true;
+                    }
+                    Some(reason) => {
+                        println!("{}", reason);
+                        print!("{}", debugger.location(&chip8));
+                        paused = true;
+                    }
+                }
+            }
+            let output = chip8.end_frame();
+            for access in &output.ignored_accesses {
+                eprintln!("Ignored: {}", access);
+            }
+            sound_on = output.sound_on;
+
+            frame_count += 1;
+            rewind_buffer.record(&chip8, frame_count);
         }
 
-        // Update Timers
-        if chip8.delay_timer > 0 {
-            chip8.delay_timer -= 1;
+        {
+            let mut wave = device.lock();
+            wave.pattern = chip8.audio_pattern().copied();
+            wave.pattern_rate = chip8.audio_sample_rate();
         }
 
-        if chip8.sound_timer > 0 {
-            chip8.sound_timer -= 1;
+        if sound_on {
             if device.status() != sdl2::audio::AudioStatus::Playing {
                 device.resume();
             }
-        } else {
-            if device.status() == sdl2::audio::AudioStatus::Playing {
-                device.pause();
-            }
+        } else if device.status() == sdl2::audio::AudioStatus::Playing {
+            device.pause();
         }
 
         // --- Drawing ---
-        draw_screen(&chip8, &mut canvas)?;
+        draw_screen(&chip8, &mut canvas, &palette)?;
+    }
+
+    if replay.is_none() && *chip8.rpl_flags() != initial_flags {
+        fs::write(&flags_path, chip8.rpl_flags()).map_err(|e| e.to_string())?;
+    }
+    if let (Some(movie), Some(path)) = (recording, &options.record_path) {
+        fs::write(path, movie.to_bytes())
+            .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+        println!("Recorded {} frames to {}", movie.len(), path);
     }
 
     Ok(())
@@ -159,25 +515,26 @@ fn main() -> Result<(), String> {
 fn draw_screen(
     chip8: &Chip8,
     canvas: &mut sdl2::render::Canvas<sdl2::video::Window>,
+    palette: &[Color; 4],
 ) -> Result<(), String> {
-    let display_buffer = chip8.display.get_buffer();
+    // Hi-res mode packs twice as many pixels into the same window
+    let scale = WINDOW_WIDTH / chip8.display.width() as u32;
 
     // Clear the screen with a background color
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.set_draw_color(palette[0]);
     canvas.clear();
 
-    // Set the drawing color for the "on" pixels
-    canvas.set_draw_color(Color::RGB(0, 255, 0));
-
-    // Iterate through the CHIP-8 buffer and draw rectangles for each "on" pixel
-    for (y, row) in display_buffer.iter().enumerate() {
-        for (x, &pixel) in row.iter().enumerate() {
-            if pixel {
+    // Draw a rectangle for each lit pixel, coloured by which planes it is lit on
+    for y in 0..chip8.display.height() {
+        for x in 0..chip8.display.width() {
+            let color = chip8.display.pixel_color(x, y);
+            if color != 0 {
+                canvas.set_draw_color(palette[color as usize]);
                 let rect = Rect::new(
-                    (x as u32 * SCALE_FACTOR) as i32,
-                    (y as u32 * SCALE_FACTOR) as i32,
-                    SCALE_FACTOR,
-                    SCALE_FACTOR,
+                    (x as u32 * scale) as i32,
+                    (y as u32 * scale) as i32,
+                    scale,
+                    scale,
                 );
                 canvas.fill_rect(rect)?;
             }
@@ -188,28 +545,198 @@ fn draw_screen(
     Ok(())
 }
 
-fn map_key(keycode: Option<Keycode>) -> Option<u8> {
-    match keycode {
-        Some(Keycode::Num1) => Some(0x1),
-        Some(Keycode::Num2) => Some(0x2),
-        Some(Keycode::Num3) => Some(0x3),
-        Some(Keycode::Num4) => Some(0xC),
+// A host input that can hold a CHIP-8 key down
+#[derive(PartialEq, Eq, Hash)]
+enum HostInput {
+    Key(Keycode),
+    // A button of the controller with this joystick id
+    Button(u32, Button),
+}
+
+struct Options {
+    rom_path: String,
+    // None picks what the ROM database or the ROM itself suggests, or the default
+    quirks: Option<Quirks>,
+    load_address: Option<usize>,
+    timing: Option<TimingModel>,
+    seed: Option<u64>,
+    memory_policy: Option<MemoryPolicy>,
+    // None leaves the quirks preset's depth
+    stack_depth: Option<StackDepth>,
+    vip_stack: bool,
+    keymap_path: Option<String>,
+    // None uses the bundled ROM database
+    romdb_path: Option<String>,
+    no_romdb: bool,
+    record_path: Option<String>,
+    replay_path: Option<String>,
+    debug: bool,
+}
 
-        Some(Keycode::Q) => Some(0x4),
-        Some(Keycode::W) => Some(0x5),
-        Some(Keycode::E) => Some(0x6),
-        Some(Keycode::R) => Some(0xD),
+const REPLAY_CONFLICT: &str = "--quirks, --load-address, --timing, --seed, --memory, \
+--stack-depth and --vip-stack come from the movie when replaying";
+
+// Returns None when the arguments don't form a valid command line
+fn parse_args(args: &[String]) -> Result<Option<Options>, String> {
+    let mut rom_path = None;
+    let mut quirks = None;
+    let mut load_address = None;
+    let mut timing = None;
+    let mut seed = None;
+    let mut memory_policy = None;
+    let mut stack_depth = None;
+    let mut vip_stack = false;
+    let mut keymap_path = None;
+    let mut romdb_path = None;
+    let mut no_romdb = false;
+    let mut record_path = None;
+    let mut replay_path = None;
+    let mut debug = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let Some(preset) = args.next() else {
+                    return Ok(None);
+                };
+                quirks = Some(preset.parse().map_err(|e: UnknownPreset| e.to_string())?);
+            }
+            "--load-address" => {
+                let Some(address) = args.next() else {
+                    return Ok(None);
+                };
+                load_address = Some(rom::parse_address(address)?);
+            }
+            "--timing" => {
+                let Some(name) = args.next() else {
+                    return Ok(None);
+                };
+                timing = Some(TimingModel::from_name(
+                    name,
+                    CYCLES_PER_SECOND / TARGET_FPS as u32,
+                )?);
+            }
+            "--seed" => {
+                let Some(value) = args.next() else {
+                    return Ok(None);
+                };
+                seed = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("Invalid seed '{}'", value))?,
+                );
+            }
+            "--memory" => {
+                let Some(name) = args.next() else {
+                    return Ok(None);
+                };
+                memory_policy = Some(MemoryPolicy::from_name(name)?);
+            }
+            "--stack-depth" => {
+                let Some(depth) = args.next() else {
+                    return Ok(None);
+                };
+                stack_depth = Some(StackDepth::from_name(depth)?);
+            }
+            "--vip-stack" => vip_stack = true,
+            "--keymap" => {
+                let Some(path) = args.next() else {
+                    return Ok(None);
+                };
+                keymap_path = Some(path.clone());
+            }
+            "--romdb" => {
+                let Some(path) = args.next() else {
+                    return Ok(None);
+                };
+                romdb_path = Some(path.clone());
+            }
+            "--no-romdb" => no_romdb = true,
+            "--record" => {
+                let Some(path) = args.next() else {
+                    return Ok(None);
+                };
+                record_path = Some(path.clone());
+            }
+            "--replay" => {
+                let Some(path) = args.next() else {
+                    return Ok(None);
+                };
+                replay_path = Some(path.clone());
+            }
+            "--debug" => debug = true,
+            _ if arg.starts_with("--") || rom_path.is_some() => return Ok(None),
+            _ => rom_path = Some(arg.clone()),
+        }
+    }
 
-        Some(Keycode::A) => Some(0x7),
-        Some(Keycode::S) => Some(0x8),
-        Some(Keycode::D) => Some(0x9),
-        Some(Keycode::F) => Some(0xE),
+    if (record_path.is_some() && replay_path.is_some()) || (romdb_path.is_some() && no_romdb) {
+        return Ok(None);
+    }
+    // The movie holds the settings it was recorded with
+    let recorded = quirks.is_some()
+        || load_address.is_some()
+        || timing.is_some()
+        || seed.is_some()
+        || memory_policy.is_some()
+        || stack_depth.is_some()
+        || vip_stack;
+    if replay_path.is_some() && recorded {
+        return Err(REPLAY_CONFLICT.to_string());
+    }
+    // Debugger steps run outside the frames a movie records, so the replay would differ
+    if record_path.is_some() && debug {
+        return Err("--record cannot be used with --debug".to_string());
+    }
 
-        Some(Keycode::Z) => Some(0xA),
-        Some(Keycode::X) => Some(0x0),
-        Some(Keycode::C) => Some(0xB),
-        Some(Keycode::V) => Some(0xF),
+    Ok(rom_path.map(|rom_path| Options {
+        rom_path,
+        quirks,
+        load_address,
+        timing,
+        seed,
+        memory_policy,
+        stack_depth,
+        vip_stack,
+        keymap_path,
+        romdb_path,
+        no_romdb,
+        record_path,
+        replay_path,
+        debug,
+    }))
+}
 
-        _ => None, // Any other key is not mapped
+// One line on what the ROM database knows about the ROM
+fn describe(info: &RomInfo) -> String {
+    let mut line = format!("Recognised \"{}\"", info.title);
+    if !info.authors.is_empty() {
+        line += &format!(" by {}", info.authors.join(", "));
+    }
+    if let Some(platform) = &info.platform {
+        line += &format!(", written for the {}", platform.name);
+    }
+    if let Some(tickrate) = info.tickrate {
+        line += &format!(", running {} instructions a frame", tickrate);
     }
+    line
+}
+
+// Reads debugger commands from stdin on a separate thread so the window stays responsive
+fn spawn_command_reader() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn save_state_path(rom_path: &str, slot: u8) -> String {
+    format!("{}.state{}", rom_path, slot)
 }
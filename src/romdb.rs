@@ -0,0 +1,456 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use serde_json::{Map, Value};
+
+use crate::chip8::quirks::Quirks;
+
+// The bundled copy, in the layout of the community chip-8-database. Its programs.json
+// can be swapped for the full one from that project.
+const BUNDLED_PROGRAMS: &str = include_str!("../romdb/programs.json");
+const BUNDLED_PLATFORMS: &str = include_str!("../romdb/platforms.json");
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomDatabaseError(pub String);
+
+impl fmt::Display for RomDatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid ROM database: {}", self.0)
+    }
+}
+
+impl std::error::Error for RomDatabaseError {}
+
+// A machine programs were written for, with the quirks and speed they expect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Platform {
+    pub id: String,
+    pub name: String,
+    pub quirks: Quirks,
+    // Instructions per 60 Hz frame
+    pub tickrate: Option<u32>,
+}
+
+// What the database knows about one ROM image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    // The platform to run the ROM as, with the quirks changed where the ROM needs it
+    pub platform: Option<Platform>,
+    // Instructions per frame, from the ROM's entry or else its platform's default
+    pub tickrate: Option<u32>,
+    pub start_address: Option<usize>,
+    // RGB for the background, then plane 1, plane 2 and both planes, as many as are given
+    pub colors: Vec<[u8; 3]>,
+    // The CHIP-8 key behind each of the entry's controls ("up", "down", "a", ...)
+    pub keys: Vec<(String, u8)>,
+}
+
+pub struct RomDatabase {
+    roms: HashMap<String, RomInfo>,
+}
+
+impl RomDatabase {
+    // Reads programs.json and platforms.json as published by the chip-8-database project
+    pub fn parse(programs: &str, platforms: &str) -> Result<Self, RomDatabaseError> {
+        let platforms = parse_platforms(&parse_json(platforms, "platforms.json")?)?;
+        let Value::Array(programs) = parse_json(programs, "programs.json")? else {
+            return Err(RomDatabaseError(
+                "programs.json must be a list of programs".to_string(),
+            ));
+        };
+
+        let mut roms = HashMap::new();
+        for (index, program) in programs.iter().enumerate() {
+            let context = format!("program {}", index);
+            let program = object(program, &context)?;
+            let title = match program.get("title") {
+                Some(Value::String(title)) => title.clone(),
+                _ => return Err(RomDatabaseError(format!("{} has no title", context))),
+            };
+            let authors = strings(program.get("authors"), &context)?;
+            let Some(images) = program.get("roms") else {
+                continue;
+            };
+            for (hash, rom) in object(images, &context)? {
+                let context = format!("ROM {} of '{}'", hash, title);
+                let rom = parse_rom(object(rom, &context)?, &platforms, &context)?;
+                roms.insert(
+                    hash.to_ascii_lowercase(),
+                    RomInfo {
+                        title: title.clone(),
+                        authors: authors.clone(),
+                        ..rom
+                    },
+                );
+            }
+        }
+        Ok(Self { roms })
+    }
+
+    // Reads the database from a directory holding programs.json and platforms.json,
+    // such as the database directory of a chip-8-database checkout
+    pub fn read(dir: impl AsRef<Path>) -> Result<Self, RomDatabaseError> {
+        let dir = dir.as_ref();
+        let read = |name: &str| {
+            let path = dir.join(name);
+            std::fs::read_to_string(&path)
+                .map_err(|e| RomDatabaseError(format!("{}: {}", path.display(), e)))
+        };
+        Self::parse(&read("programs.json")?, &read("platforms.json")?)
+    }
+
+    pub fn lookup(&self, sha1: &[u8]) -> Option<&RomInfo> {
+        let key: String = sha1.iter().map(|byte| format!("{:02x}", byte)).collect();
+        self.roms.get(&key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+}
+
+impl Default for RomDatabase {
+    fn default() -> Self {
+        RomDatabase::parse(BUNDLED_PROGRAMS, BUNDLED_PLATFORMS)
+            .expect("bundled ROM database is valid")
+    }
+}
+
+fn parse_json(text: &str, name: &str) -> Result<Value, RomDatabaseError> {
+    serde_json::from_str(text).map_err(|e| RomDatabaseError(format!("{}: {}", name, e)))
+}
+
+fn parse_platforms(value: &Value) -> Result<HashMap<String, Platform>, RomDatabaseError> {
+    let Value::Array(entries) = value else {
+        return Err(RomDatabaseError(
+            "platforms.json must be a list of platforms".to_string(),
+        ));
+    };
+
+    let mut platforms = HashMap::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let context = format!("platform {}", index);
+        let entry = object(entry, &context)?;
+        let Some(Value::String(id)) = entry.get("id") else {
+            return Err(RomDatabaseError(format!("{} has no id", context)));
+        };
+        let name = match entry.get("name") {
+            Some(Value::String(name)) => name.clone(),
+            _ => id.clone(),
+        };
+        // The database treats XO-CHIP as a platform rather than a quirk, and has no stack
+        // depth, so the platforms built on the VIP interpreter get its depth
+        let mut quirks = Quirks {
+            xo_chip: id == "xochip",
+            vip_stack_depth: matches!(id.as_str(), "originalChip8" | "hybridVIP" | "chip8x"),
+            ..Quirks::COSMAC_VIP
+        };
+        if let Some(overrides) = entry.get("quirks") {
+            apply_quirks(&mut quirks, object(overrides, id)?, id)?;
+        }
+        let tickrate = number(entry.get("defaultTickrate"), id)?.map(|rate| rate as u32);
+        platforms.insert(
+            id.clone(),
+            Platform {
+                id: id.clone(),
+                name,
+                quirks,
+                tickrate,
+            },
+        );
+    }
+    Ok(platforms)
+}
+
+// Everything about a ROM entry but the program's title and authors
+fn parse_rom(
+    rom: &Map<String, Value>,
+    platforms: &HashMap<String, Platform>,
+    context: &str,
+) -> Result<RomInfo, RomDatabaseError> {
+    // Entries list the platforms they run on best first; a quirky platform is one the ROM
+    // only runs on with some of its quirks changed
+    let quirky = match rom.get("quirkyPlatforms") {
+        Some(value) => Some(object(value, context)?),
+        None => None,
+    };
+    let id = strings(rom.get("platforms"), context)?
+        .into_iter()
+        .chain(quirky.into_iter().flat_map(|quirky| quirky.keys().cloned()))
+        .find(|id| platforms.contains_key(id));
+    let platform = match id {
+        Some(id) => {
+            let mut platform = platforms[&id].clone();
+            if let Some(overrides) = quirky.and_then(|quirky| quirky.get(&id)) {
+                apply_quirks(&mut platform.quirks, object(overrides, context)?, context)?;
+            }
+            Some(platform)
+        }
+        None => None,
+    };
+
+    let tickrate = number(rom.get("tickrate"), context)?
+        .map(|rate| rate as u32)
+        .or_else(|| platform.as_ref().and_then(|platform| platform.tickrate));
+    let start_address = number(rom.get("startAddress"), context)?.map(|address| address as usize);
+
+    let mut colors = Vec::new();
+    if let Some(value) = rom.get("colors")
+        && let Some(pixels) = object(value, context)?.get("pixels")
+    {
+        for color in strings(Some(pixels), context)? {
+            colors.push(parse_color(&color).ok_or_else(|| {
+                RomDatabaseError(format!("{} has invalid colour '{}'", context, color))
+            })?);
+        }
+    }
+
+    let mut keys = Vec::new();
+    if let Some(value) = rom.get("keys") {
+        for (control, key) in object(value, context)? {
+            let key = key.as_u64().filter(|key| *key < 16).ok_or_else(|| {
+                RomDatabaseError(format!(
+                    "{} binds '{}' to something other than a CHIP-8 key",
+                    context, control
+                ))
+            })?;
+            keys.push((control.clone(), key as u8));
+        }
+    }
+
+    Ok(RomInfo {
+        title: String::new(),
+        authors: Vec::new(),
+        platform,
+        tickrate,
+        start_address,
+        colors,
+        keys,
+    })
+}
+
+// Applies the database's quirk flags, which are named for the behaviour that differs from
+// the original interpreter
+fn apply_quirks(
+    quirks: &mut Quirks,
+    flags: &Map<String, Value>,
+    context: &str,
+) -> Result<(), RomDatabaseError> {
+    for (name, value) in flags {
+        let Value::Bool(flag) = *value else {
+            return Err(RomDatabaseError(format!(
+                "quirk '{}' of {} must be true or false",
+                name, context
+            )));
+        };
+        match name.as_str() {
+            "shift" => quirks.shift_uses_vy = !flag,
+            "memoryLeaveIUnchanged" => quirks.load_store_increments_index = !flag,
+            "wrap" => quirks.sprite_wrap = flag,
+            "jump" => quirks.jump_uses_vx = flag,
+            "vblank" => quirks.display_wait = flag,
+            "logic" => quirks.vf_reset = flag,
+            // SUPER-CHIP 1.0 advances I by one less; I still moves, which is what matters
+            // to the programs that depend on it
+            "memoryIncrementByX" => {}
+            // Quirks this core has no switch for are left at the platform's behaviour
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// "#rrggbb" to RGB
+fn parse_color(text: &str) -> Option<[u8; 3]> {
+    let hex = text.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+fn object<'a>(value: &'a Value, context: &str) -> Result<&'a Map<String, Value>, RomDatabaseError> {
+    value
+        .as_object()
+        .ok_or_else(|| RomDatabaseError(format!("expected an object in {}", context)))
+}
+
+// A list of strings, where a missing field is an empty list
+fn strings(value: Option<&Value>, context: &str) -> Result<Vec<String>, RomDatabaseError> {
+    let Some(value) = value else {
+        return Ok(Vec::new());
+    };
+    value
+        .as_array()
+        .and_then(|items| {
+            items
+                .iter()
+                .map(|item| item.as_str().map(str::to_string))
+                .collect()
+        })
+        .ok_or_else(|| RomDatabaseError(format!("expected a list of strings in {}", context)))
+}
+
+fn number(value: Option<&Value>, context: &str) -> Result<Option<u64>, RomDatabaseError> {
+    match value {
+        None => Ok(None),
+        Some(value) => value
+            .as_u64()
+            .map(Some)
+            .ok_or_else(|| RomDatabaseError(format!("expected a whole number in {}", context))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::rom::Rom;
+
+    // Two entries in the database's own format: a SUPER-CHIP program with its own speed,
+    // colours and controls, and one that needs the VIP with the shift quirk changed. The
+    // first hash, in capitals as some entries have it, is the SHA-1 of 12 00.
+    const PROGRAMS: &str = r##"[
+        {
+            "title": "Jump",
+            "authors": ["A", "B"],
+            "roms": {
+                "92A5652D382A18E89C4881EC57041FC7D885CA80": {
+                    "platforms": ["superchip", "xochip"],
+                    "tickrate": 20,
+                    "colors": { "pixels": ["#000000", "#ff8000"] },
+                    "keys": { "up": 5, "a": 6 }
+                }
+            }
+        },
+        {
+            "title": "Shift",
+            "roms": {
+                "2cdd5bd3f4e30a4d56d9a8841ffcd5fbc2d0f735": {
+                    "platforms": ["unknownPlatform"],
+                    "quirkyPlatforms": { "originalChip8": { "shift": true } },
+                    "startAddress": 1536
+                }
+            }
+        },
+        { "title": "No images" }
+    ]"##;
+
+    fn database() -> RomDatabase {
+        RomDatabase::parse(PROGRAMS, BUNDLED_PLATFORMS).unwrap()
+    }
+
+    #[test]
+    fn roms_are_found_by_their_sha1() {
+        let database = database();
+        assert_eq!(database.len(), 2);
+
+        let rom = Rom::new(vec![0x12, 0x00]);
+        let info = database.lookup(rom.sha1()).unwrap();
+        assert_eq!(info.title, "Jump");
+        assert_eq!(info.authors, ["A", "B"]);
+        let platform = info.platform.as_ref().unwrap();
+        assert_eq!(platform.id, "superchip");
+        assert!(!platform.quirks.shift_uses_vy);
+        assert!(!platform.quirks.load_store_increments_index);
+        assert!(platform.quirks.jump_uses_vx);
+        assert!(!platform.quirks.xo_chip);
+        assert!(!platform.quirks.vip_stack_depth);
+        assert_eq!(info.tickrate, Some(20));
+        assert_eq!(info.colors, [[0x00, 0x00, 0x00], [0xFF, 0x80, 0x00]]);
+        assert!(info.keys.contains(&("up".to_string(), 5)));
+        assert!(info.keys.contains(&("a".to_string(), 6)));
+
+        assert!(database.lookup(Rom::new(vec![0x12, 0x02]).sha1()).is_none());
+    }
+
+    #[test]
+    fn quirky_platforms_change_the_quirks_they_list() {
+        let database = database();
+        let info = database
+            .lookup(Rom::new(vec![0x00, 0xE0, 0x12, 0x00]).sha1())
+            .unwrap();
+        let platform = info.platform.as_ref().unwrap();
+        assert_eq!(platform.id, "originalChip8");
+        assert_eq!(
+            platform.quirks,
+            Quirks {
+                shift_uses_vy: false,
+                ..bundled_platforms()["originalChip8"].quirks
+            }
+        );
+        // The platform's speed stands in for a missing tickrate
+        assert_eq!(info.tickrate, Some(15));
+        assert_eq!(info.start_address, Some(0x600));
+    }
+
+    #[test]
+    fn malformed_entries_are_reported() {
+        let error = |programs: &str| {
+            RomDatabase::parse(programs, BUNDLED_PLATFORMS)
+                .err()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(
+            error(r#"[{ "roms": {} }]"#),
+            "invalid ROM database: program 0 has no title"
+        );
+        assert_eq!(
+            error(r#"[{ "title": "T", "roms": { "ab": { "keys": { "up": 16 } } } }]"#),
+            "invalid ROM database: ROM ab of 'T' binds 'up' to something other than a CHIP-8 key"
+        );
+        assert_eq!(
+            error(
+                r##"[{ "title": "T", "roms": { "ab": { "colors": { "pixels": ["#fff"] } } } }]"##
+            ),
+            "invalid ROM database: ROM ab of 'T' has invalid colour '#fff'"
+        );
+    }
+
+    #[test]
+    fn the_bundled_database_is_valid() {
+        RomDatabase::default();
+        assert_eq!(bundled_platforms().len(), 9);
+    }
+
+    // Holds for the empty list shipped here and for a full programs.json copied in
+    #[test]
+    fn every_bundled_rom_is_found_by_its_sha1() {
+        let database = RomDatabase::default();
+        let Value::Array(programs) = parse_json(BUNDLED_PROGRAMS, "programs.json").unwrap() else {
+            panic!("programs.json is not a list");
+        };
+        let mut count = 0;
+        for program in &programs {
+            let Some(Value::Object(images)) = program.get("roms") else {
+                continue;
+            };
+            for hash in images.keys() {
+                assert_eq!(hash.len(), 40, "{} is not a SHA-1", hash);
+                let sha1: Vec<u8> = (0..40)
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&hash[i..i + 2], 16).unwrap())
+                    .collect();
+                let info = database.lookup(&sha1).unwrap();
+                assert_eq!(
+                    Some(&Value::String(info.title.clone())),
+                    program.get("title")
+                );
+                count += 1;
+            }
+        }
+        assert_eq!(database.len(), count);
+    }
+
+    fn bundled_platforms() -> HashMap<String, Platform> {
+        parse_platforms(&parse_json(BUNDLED_PLATFORMS, "platforms.json").unwrap()).unwrap()
+    }
+}
@@ -0,0 +1,281 @@
+// C ABI exported by the WebAssembly build, for embedding the emulator in a web page or
+// driving it from any wasm runtime. A host creates an emulator, copies a ROM into the
+// buffer returned by `chip8_rom_buffer` and loads it, then each 60 Hz frame reports key
+// changes, calls `chip8_run_frame` and reads the framebuffer: one byte per pixel, row by
+// row, holding the pixel's colour index (0 unlit, 1 and 2 for the XO-CHIP planes, 3 for
+// both).
+
+use crate::chip8::display::{HIRES_DISPLAY_HEIGHT, HIRES_DISPLAY_WIDTH};
+use crate::chip8::quirks::Quirks;
+use crate::chip8::rom::Rom;
+use crate::chip8::timing::TimingModel;
+use crate::chip8::{Chip8, KeyState};
+
+// Bits returned by `chip8_run_frame`
+const FRAME_DISPLAY_CHANGED: u32 = 1 << 0;
+const FRAME_SOUND_ON: u32 = 1 << 1;
+const FRAME_ERROR: u32 = 1 << 2;
+const FRAME_EXITED: u32 = 1 << 3;
+
+// Presets accepted by `chip8_create`, in this order
+const PRESETS: [Quirks; 4] = [
+    Quirks::COSMAC_VIP,
+    Quirks::CHIP_48,
+    Quirks::SUPER_CHIP,
+    Quirks::XO_CHIP,
+];
+
+struct Emulator {
+    chip8: Chip8,
+    quirks: Quirks,
+    timing: TimingModel,
+    seed: u64,
+    keys: KeyState,
+    // The host's copy of the next ROM, filled through `chip8_rom_buffer`
+    rom: Vec<u8>,
+    framebuffer: Vec<u8>,
+}
+
+impl Emulator {
+    // A machine with the emulator's settings and nothing loaded
+    fn fresh_machine(&self) -> Chip8 {
+        let mut chip8 = Chip8::new(self.quirks);
+        chip8.set_timing(self.timing);
+        chip8.seed_random(self.seed);
+        chip8
+    }
+
+    fn update_framebuffer(&mut self) {
+        let display = &self.chip8.display;
+        self.framebuffer.clear();
+        for y in 0..display.height() {
+            for x in 0..display.width() {
+                self.framebuffer.push(display.pixel_color(x, y));
+            }
+        }
+    }
+}
+
+// Safety: `emulator` must come from `chip8_create` and not have been destroyed
+unsafe fn emulator<'a>(emulator: *mut Emulator) -> &'a mut Emulator {
+    unsafe { &mut *emulator }
+}
+
+// Creates an emulator using quirks preset `preset` (0 VIP, 1 CHIP-48, 2 SUPER-CHIP,
+// 3 XO-CHIP) with Cxkk seeded from `seed`. Returns null for an unknown preset.
+#[unsafe(no_mangle)]
+extern "C" fn chip8_create(preset: u32, seed: u64) -> *mut Emulator {
+    let Some(&quirks) = PRESETS.get(preset as usize) else {
+        return std::ptr::null_mut();
+    };
+    let mut emulator = Emulator {
+        chip8: Chip8::new(quirks),
+        quirks,
+        timing: TimingModel::default(),
+        seed,
+        keys: KeyState::new(),
+        rom: Vec::new(),
+        // Room for the largest resolution, so the framebuffer never moves
+        framebuffer: Vec::with_capacity(HIRES_DISPLAY_WIDTH * HIRES_DISPLAY_HEIGHT),
+    };
+    emulator.chip8 = emulator.fresh_machine();
+    emulator.update_framebuffer();
+    Box::into_raw(Box::new(emulator))
+}
+
+#[unsafe(no_mangle)]
+unsafe extern "C" fn chip8_destroy(emulator: *mut Emulator) {
+    if !emulator.is_null() {
+        drop(unsafe { Box::from_raw(emulator) });
+    }
+}
+
+// Returns a buffer of `len` bytes for the host to copy a ROM into before `chip8_load_rom`
+#[unsafe(no_mangle)]
+unsafe extern "C" fn chip8_rom_buffer(emulator: *mut Emulator, len: usize) -> *mut u8 {
+    let emulator = unsafe { self::emulator(emulator) };
+    emulator.rom = vec![0; len];
+    emulator.rom.as_mut_ptr()
+}
+
+// Resets the machine and loads the ROM in the ROM buffer at 0x200, or at `load_address`
+// if it is non-zero. Returns 0 on success, or -1 if the ROM is empty or does not fit.
+#[unsafe(no_mangle)]
+unsafe extern "C" fn chip8_load_rom(emulator: *mut Emulator, load_address: u32) -> i32 {
+    let emulator = unsafe { self::emulator(emulator) };
+    let mut chip8 = emulator.fresh_machine();
+    let mut rom = Rom::new(emulator.rom.clone());
+    if load_address != 0 {
+        rom = rom.with_load_address(load_address as usize);
+    }
+    if chip8.load_rom(&rom).is_err() {
+        return -1;
+    }
+    emulator.chip8 = chip8;
+    emulator.keys = KeyState::new();
+    emulator.update_framebuffer();
+    0
+}
+
+// Selects the timing model for frames from the next one on: 0 runs
+// `instructions_per_frame` instructions a frame, 1 uses COSMAC VIP cycle counts
+#[unsafe(no_mangle)]
+unsafe extern "C" fn chip8_set_timing(
+    emulator: *mut Emulator,
+    model: u32,
+    instructions_per_frame: u32,
+) {
+    let emulator = unsafe { self::emulator(emulator) };
+    emulator.timing = match model {
+        1 => TimingModel::CosmacVip,
+        _ => TimingModel::Fixed(instructions_per_frame),
+    };
+    emulator.chip8.set_timing(emulator.timing);
+}
+
+// Keys are 0-F; anything else is ignored
+#[unsafe(no_mangle)]
+unsafe extern "C" fn chip8_key_down(emulator: *mut Emulator, key: u32) {
+    let emulator = unsafe { self::emulator(emulator) };
+    if key < 16 {
+        emulator.keys.set_key_pressed(key as u8, true);
+    }
+}
+
+#[unsafe(no_mangle)]
+unsafe extern "C" fn chip8_key_up(emulator: *mut Emulator, key: u32) {
+    let emulator = unsafe { self::emulator(emulator) };
+    if key < 16 {
+        emulator.keys.set_key_pressed(key as u8, false);
+    }
+}
+
+// Runs one frame with the keys currently held and returns a combination of the FRAME_ bits
+#[unsafe(no_mangle)]
+unsafe extern "C" fn chip8_run_frame(emulator: *mut Emulator) -> u32 {
+    let emulator = unsafe { self::emulator(emulator) };
+    let output = emulator.chip8.run_frame(&emulator.keys);
+
+    let mut flags = 0;
+    if output.display_changed {
+        emulator.update_framebuffer();
+        flags |= FRAME_DISPLAY_CHANGED;
+    }
+    if output.sound_on {
+        flags |= FRAME_SOUND_ON;
+    }
+    if output.error.is_some() {
+        flags |= FRAME_ERROR;
+    }
+    if emulator.chip8.has_exited() {
+        flags |= FRAME_EXITED;
+    }
+    flags
+}
+
+// The framebuffer holds `chip8_width() * chip8_height()` bytes and stays at the same
+// address for the emulator's lifetime
+#[unsafe(no_mangle)]
+unsafe extern "C" fn chip8_framebuffer(emulator: *mut Emulator) -> *const u8 {
+    let emulator = unsafe { self::emulator(emulator) };
+    emulator.framebuffer.as_ptr()
+}
+
+#[unsafe(no_mangle)]
+unsafe extern "C" fn chip8_width(emulator: *mut Emulator) -> u32 {
+    let emulator = unsafe { self::emulator(emulator) };
+    emulator.chip8.display.width() as u32
+}
+
+#[unsafe(no_mangle)]
+unsafe extern "C" fn chip8_height(emulator: *mut Emulator) -> u32 {
+    let emulator = unsafe { self::emulator(emulator) };
+    emulator.chip8.display.height() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Creates an emulator with `program` loaded the way a host does
+    fn emulator_with(preset: u32, program: &[u8]) -> *mut Emulator {
+        let emulator = chip8_create(preset, 1);
+        assert!(!emulator.is_null());
+        unsafe {
+            let buffer = chip8_rom_buffer(emulator, program.len());
+            std::ptr::copy_nonoverlapping(program.as_ptr(), buffer, program.len());
+            assert_eq!(chip8_load_rom(emulator, 0), 0);
+        }
+        emulator
+    }
+
+    #[test]
+    fn frames_draw_into_the_framebuffer() {
+        // Draws the font's 0 at the top left, then loops
+        let emulator = emulator_with(0, &[0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06]);
+        unsafe {
+            assert_eq!((chip8_width(emulator), chip8_height(emulator)), (64, 32));
+            // The VIP preset holds the draw until the next frame begins
+            assert_eq!(chip8_run_frame(emulator) & FRAME_DISPLAY_CHANGED, 0);
+            let flags = chip8_run_frame(emulator);
+            assert_eq!(flags & FRAME_DISPLAY_CHANGED, FRAME_DISPLAY_CHANGED);
+            assert_eq!(flags & (FRAME_ERROR | FRAME_EXITED), 0);
+
+            let framebuffer = std::slice::from_raw_parts(chip8_framebuffer(emulator), 64 * 32);
+            // The top row of the 0 glyph is F0
+            assert_eq!(framebuffer[..8], [1, 1, 1, 1, 0, 0, 0, 0]);
+            // Its second row is 90
+            assert_eq!(framebuffer[64..72], [1, 0, 0, 1, 0, 0, 0, 0]);
+            chip8_destroy(emulator);
+        }
+    }
+
+    #[test]
+    fn keys_reach_the_program_and_out_of_range_keys_are_ignored() {
+        // Waits for a key into V0, then loops
+        let emulator = emulator_with(0, &[0xF0, 0x0A, 0x12, 0x02]);
+        unsafe {
+            chip8_run_frame(emulator);
+            chip8_key_down(emulator, 256);
+            chip8_key_down(emulator, 16);
+            assert_eq!((*emulator).keys, KeyState::new());
+
+            chip8_key_down(emulator, 0x5);
+            chip8_run_frame(emulator);
+            chip8_key_up(emulator, 0x5);
+            chip8_run_frame(emulator);
+            let registers = (*emulator).chip8.registers();
+            assert_eq!(registers.get(0.try_into().unwrap()), 0x5);
+            chip8_destroy(emulator);
+        }
+    }
+
+    #[test]
+    fn bad_presets_and_roms_are_refused() {
+        assert!(chip8_create(4, 0).is_null());
+        let emulator = chip8_create(3, 0);
+        unsafe {
+            chip8_rom_buffer(emulator, 0);
+            assert_eq!(chip8_load_rom(emulator, 0), -1);
+            chip8_rom_buffer(emulator, 2);
+            assert_eq!(chip8_load_rom(emulator, 0x100), -1);
+            chip8_destroy(emulator);
+        }
+    }
+
+    #[test]
+    fn errors_and_exits_are_reported() {
+        // 0000 is not an instruction
+        let emulator = emulator_with(0, &[0x00, 0x00]);
+        unsafe {
+            assert_eq!(chip8_run_frame(emulator) & FRAME_ERROR, FRAME_ERROR);
+            chip8_destroy(emulator);
+        }
+        // 00FD ends a SUPER-CHIP program
+        let emulator = emulator_with(2, &[0x00, 0xFD]);
+        unsafe {
+            assert_eq!(chip8_run_frame(emulator) & FRAME_EXITED, FRAME_EXITED);
+            chip8_destroy(emulator);
+        }
+    }
+}